@@ -2,7 +2,9 @@ use ggez::event::{EventHandler, KeyCode, KeyMods};
 use ggez::graphics::spritebatch::SpriteBatch;
 use ggez::{graphics, Context, GameError};
 use notedata::timingdata::{GameplayInfo, Rectangle, TimingColumn};
-use notedata::{BeatPair, Fraction, Note, NoteData, NoteRow, NoteType, NOTEFIELD_SIZE};
+use notedata::{
+    chart_from_notes, BeatPair, ChartInfo, Fraction, Note, NoteData, NoteRow, NoteType,
+};
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::path::PathBuf;
@@ -14,17 +16,45 @@ pub struct Editor {
     current_beat: (i32, Fraction),
     pub snap: i32,
     pub layout: NoteLayout,
-    pub column_info: [ColumnInfo; NOTEFIELD_SIZE],
+    pub column_info: Vec<ColumnInfo>,
     pub batches: Vec<SpriteBatch>,
     pub zoom: Fraction,
 }
 
 #[derive(Default)]
 pub struct ChartEditor {
+    info: ChartInfo,
     bpms: BTreeMap<(i32, Fraction), (f64, f64)>,
     notes: BTreeMap<(usize, Fraction), BTreeMap<usize, NoteType>>,
 }
 
+const STEPS_TYPES: [&str; 5] = [
+    "dance-single",
+    "dance-double",
+    "pump-single",
+    "kb6-single",
+    "kb7-single",
+];
+
+const COLUMN_KEYS: [KeyCode; 10] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Key0,
+];
+
+fn empty_columns(columns: usize) -> Vec<ColumnInfo> {
+    (0..columns)
+        .map(|_| ColumnInfo::from(TimingColumn::new()))
+        .collect()
+}
+
 /*impl Default for Editor {
     fn default() -> Self {
         Self::new()
@@ -38,11 +68,13 @@ impl Editor {
         options: PlayerOptions,
         ctx: &mut Context,
     ) -> Self {
-        let layout = NoteLayout::new(
+        let chart = ChartEditor::default();
+        let mut layout = NoteLayout::new(
             &NoteSkin::new(&noteskin, ctx).unwrap(),
             screen_height,
             options,
         );
+        layout.set_columns(chart.columns());
         let batches = vec![
             SpriteBatch::new(layout.sprites.hold_end.clone()),
             SpriteBatch::new(layout.sprites.hold_body.clone()),
@@ -50,26 +82,21 @@ impl Editor {
             SpriteBatch::new(layout.sprites.mine.clone()),
         ];
         Self {
-            chart: ChartEditor::default(),
+            column_info: empty_columns(chart.columns()),
+            chart,
             current_beat: (0, Fraction::new(0, 1)),
             snap: 4,
             layout,
-            column_info: [
-                ColumnInfo::from(TimingColumn::new()),
-                ColumnInfo::from(TimingColumn::new()),
-                ColumnInfo::from(TimingColumn::new()),
-                ColumnInfo::from(TimingColumn::new()),
-            ],
             batches,
             zoom: Fraction::new(1, 1),
         }
     }
     pub fn redraw_batch(&mut self) {
         self.batches.iter_mut().for_each(SpriteBatch::clear);
-        for column_index in 0..NOTEFIELD_SIZE {
-            let (draw_start, draw_end) = self.column_info[column_index].on_screen;
+        for (column_index, column) in self.column_info.iter().enumerate() {
+            let (draw_start, draw_end) = column.on_screen;
             self.layout.add_column_of_notes(
-                &self.column_info[column_index].notes.notes[draw_start..draw_end],
+                &column.notes.notes[draw_start..draw_end],
                 column_index,
                 &mut self.batches,
             );
@@ -141,6 +168,18 @@ impl Editor {
     pub fn export(&self) -> NoteData {
         self.chart.export()
     }
+    pub fn set_steps_type(&mut self, steps_type: &str) {
+        self.chart.info.steps_type = steps_type.to_owned();
+        self.layout.set_columns(self.chart.columns());
+        self.column_info = empty_columns(self.chart.columns());
+    }
+    pub fn next_steps_type(&mut self) {
+        let next = STEPS_TYPES
+            .iter()
+            .position(|&steps_type| steps_type == self.chart.info.steps_type)
+            .map_or(0, |index| (index + 1) % STEPS_TYPES.len());
+        self.set_steps_type(STEPS_TYPES[next]);
+    }
 }
 
 fn handle_keypress(editor: &mut Editor, code: KeyCode) {
@@ -183,45 +222,9 @@ fn handle_keypress(editor: &mut Editor, code: KeyCode) {
                 editor.get_noterow()
             );
         }
-        KeyCode::Key1 => {
-            editor.toggle_note(0, NoteType::Tap);
-            let (measure, beat) = editor.get_beat();
-            println!(
-                "measure: {} beat: {} row: {:?}",
-                measure,
-                beat,
-                editor.get_noterow()
-            );
-        }
-        KeyCode::Key2 => {
-            editor.toggle_note(1, NoteType::Tap);
-            let (measure, beat) = editor.get_beat();
-            println!(
-                "measure: {} beat: {} row: {:?}",
-                measure,
-                beat,
-                editor.get_noterow()
-            );
-        }
-        KeyCode::Key3 => {
-            editor.toggle_note(2, NoteType::Tap);
-            let (measure, beat) = editor.get_beat();
-            println!(
-                "measure: {} beat: {} row: {:?}",
-                measure,
-                beat,
-                editor.get_noterow()
-            );
-        }
-        KeyCode::Key4 => {
-            editor.toggle_note(3, NoteType::Tap);
-            let (measure, beat) = editor.get_beat();
-            println!(
-                "measure: {} beat: {} row: {:?}",
-                measure,
-                beat,
-                editor.get_noterow()
-            );
+        KeyCode::Tab => {
+            editor.next_steps_type();
+            println!("steps type changed to: {}", editor.chart.info.steps_type);
         }
         KeyCode::N => {
             let bpm = editor.get_bpm().unwrap_or(120.0) + 10.0;
@@ -231,13 +234,28 @@ fn handle_keypress(editor: &mut Editor, code: KeyCode) {
             let bpm = editor.get_bpm().unwrap_or(120.0) - 10.0;
             editor.chart.bpms.insert(editor.current_beat, (bpm, 0.0));
         }
-        KeyCode::Return => {
-            let simfile_string = editor.export().to_sm_string();
-            println!("{}", simfile_string);
-        }
+        KeyCode::Return => match editor.export().to_sm_string() {
+            Ok(simfile_string) => println!("{}", simfile_string),
+            Err(error) => println!("Could not export chart: {}", error),
+        },
         KeyCode::Add => editor.zoom *= 2,
         KeyCode::Subtract => editor.zoom /= 2,
-        _ => {}
+        _ => {
+            if let Some(column) = COLUMN_KEYS
+                .iter()
+                .position(|&key| key == code)
+                .filter(|&column| column < editor.column_info.len())
+            {
+                editor.toggle_note(column, NoteType::Tap);
+                let (measure, beat) = editor.get_beat();
+                println!(
+                    "measure: {} beat: {} row: {:?}",
+                    measure,
+                    beat,
+                    editor.get_noterow()
+                );
+            }
+        }
     }
 }
 
@@ -252,22 +270,22 @@ impl EventHandler for Editor {
         let time = ((self.current_beat.0 as f32
             + (*self.current_beat.1.numer() as f32 / *self.current_beat.1.denom() as f32))
             * scroll_const) as i64;
-        for column_index in 0..4 {
-            self.column_info[column_index].update_on_screen(&self.layout, time, 600);
+        for column in &mut self.column_info {
+            column.update_on_screen(&self.layout, time, 600);
         }
         let target_parameter =
             graphics::DrawParam::new().dest([0.0, (self.layout.delta_to_offset(time))]);
         self.redraw_batch();
 
-        self.column_info = [
-            ColumnInfo::from(TimingColumn::new()),
-            ColumnInfo::from(TimingColumn::new()),
-            ColumnInfo::from(TimingColumn::new()),
-            ColumnInfo::from(TimingColumn::new()),
-        ];
+        self.column_info = empty_columns(self.column_info.len());
         for ((measure, beat), contents) in self.chart.notes.iter() {
             for (&index, &note) in contents {
-                self.column_info[index].notes.notes.push(GameplayInfo(
+                //Notes can be left outside the notefield after switching to a narrower steps type
+                let column = match self.column_info.get_mut(index) {
+                    Some(column) => column,
+                    None => continue,
+                };
+                column.notes.notes.push(GameplayInfo(
                     (-1.0
                         * (*measure as f32 + (*beat.numer() as f32 / *beat.denom() as f32))
                         * scroll_const) as i64,
@@ -278,6 +296,7 @@ impl EventHandler for Editor {
                         h: 0.125,
                     },
                     note,
+                    None,
                 ))
             }
         }
@@ -318,6 +337,9 @@ impl ChartEditor {
             self.notes.insert((measure, beat), row);
         }
     }
+    pub fn columns(&self) -> usize {
+        self.info.columns(&[])
+    }
     pub fn get_bpm(&self, measure: i32, beat: Fraction) -> Option<f64> {
         self.bpms
             .range(..=(measure, beat))
@@ -342,21 +364,12 @@ impl ChartEditor {
                 value: *bpm,
             })
             .collect();
-        let mut out = vec![];
-        for ((measure, beat), map) in self.notes.iter() {
-            while out.len() <= *measure {
-                out.push(vec![]);
-            }
-            if let Some(current) = out.last_mut() {
-                current.push((
-                    map.iter()
-                        .map(|(&column, &note_type)| Note { note_type, column })
-                        .collect::<Vec<_>>(),
-                    *beat,
-                ));
-            }
-        }
-        data.charts = vec![out];
+        let notes = self.notes.iter().flat_map(|((measure, beat), map)| {
+            let position = (Fraction::from(*measure as i32) + beat) * 4;
+            map.iter()
+                .map(move |(&column, &note_type)| (position, Note::new(note_type, column)))
+        });
+        data.charts = vec![(self.info.clone(), chart_from_notes(notes))];
         data
     }
     pub fn get_noterow(&self, measure: usize, beat: Fraction) -> NoteRow {
@@ -385,21 +398,24 @@ mod tests {
         edit.toggle_note(1, Fraction::new(1, 2), 0, NoteType::Tap);
         edit.toggle_note(1, Fraction::new(1, 2), 0, NoteType::Mine);
         let mut cool = NoteData::new();
-        cool.charts = vec![vec![
-            vec![(vec![Note::new(NoteType::Tap, 3)], Fraction::new(1, 2))],
-            vec![(
-                vec![Note::new(NoteType::Mine, 0), Note::new(NoteType::Tap, 3)],
-                Fraction::new(1, 2),
-            )],
-            vec![],
+        cool.charts = vec![(
+            ChartInfo::new(),
             vec![
-                (vec![Note::new(NoteType::Tap, 1)], Fraction::new(1, 4)),
-                (
-                    vec![Note::new(NoteType::Tap, 2), Note::new(NoteType::Tap, 3)],
-                    Fraction::new(2, 3),
-                ),
+                vec![(vec![Note::new(NoteType::Tap, 3)], Fraction::new(1, 2))],
+                vec![(
+                    vec![Note::new(NoteType::Mine, 0), Note::new(NoteType::Tap, 3)],
+                    Fraction::new(1, 2),
+                )],
+                vec![],
+                vec![
+                    (vec![Note::new(NoteType::Tap, 1)], Fraction::new(1, 4)),
+                    (
+                        vec![Note::new(NoteType::Tap, 2), Note::new(NoteType::Tap, 3)],
+                        Fraction::new(2, 3),
+                    ),
+                ],
             ],
-        ]];
+        )];
         cool.structure.bpms = vec![
             BeatPair {
                 beat: 0,
@@ -414,4 +430,14 @@ mod tests {
         ];
         assert_eq!(edit.export(), cool)
     }
+
+    #[test]
+    fn columns_follow_steps_type() {
+        let mut edit = ChartEditor::default();
+        assert_eq!(edit.columns(), 4);
+        edit.info.steps_type = "kb7-single".to_owned();
+        edit.toggle_note(0, Fraction::new(0, 1), 6, NoteType::Tap);
+        assert_eq!(edit.columns(), 7);
+        assert_eq!(edit.export().charts[0].0.steps_type, "kb7-single");
+    }
 }
@@ -18,27 +18,52 @@ fn main() {
         .read_line(&mut file_path)
         .expect("failed to read input");
     let mut file_path = PathBuf::from(file_path.trim_end_matches('\n'));
-    let notedata = if let Some(extension) = file_path.extension() {
+    let mut notedata = if let Some(extension) = file_path.extension() {
         let sim = File::open(file_path.as_path()).expect("");
         match extension.to_str() {
             Some("sm") => {
                 notedata::NoteData::from_sm_reader(sim).expect("Could not deserialize .sm")
             }
+            Some("ssc") => {
+                notedata::NoteData::from_ssc_reader(sim).expect("Could not deserialize .ssc")
+            }
             Some("dwi") => {
                 notedata::NoteData::from_dwi_reader(sim).expect("Could note deseialize .dwi")
             }
+            Some("osu") => {
+                notedata::NoteData::from_osu_reader(sim).expect("Could not deserialize .osu")
+            }
+            Some("bms") | Some("bme") | Some("bml") => {
+                notedata::NoteData::from_bms_reader(sim).expect("Could not deserialize .bms")
+            }
+            Some("pms") => {
+                notedata::NoteData::from_pms_reader(sim).expect("Could not deserialize .pms")
+            }
             _ => panic!("Unsupported extension"),
         }
     } else {
         panic!("Couldn't read extension for simfile");
     };
+    let mut transforms = String::new();
+    print!("Enter transforms to apply, like mirror,noholds (blank for none): ");
+    let _ = io::stdout().flush();
+    io::stdin()
+        .read_line(&mut transforms)
+        .expect("failed to read input");
+    let transforms = transforms
+        .split(',')
+        .filter(|transform| !transform.trim().is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<notedata::Transform>, _>>()
+        .expect("Could not read transforms");
+    notedata.transform(&transforms);
     file_path.set_extension("sm");
-    let mut out_file = OpenOptions::new()
+    let out_file = OpenOptions::new()
         .write(true)
         .create_new(true)
         .open(file_path)
         .expect("Could not create output file");
-    out_file
-        .write(notedata.to_sm_string().as_bytes())
+    notedata
+        .to_sm_writer(out_file)
         .expect("Could not write to output file");
 }
@@ -1,4 +1,4 @@
-use notedata::{Chart, NoteType};
+use notedata::ChartStats;
 use std::env::current_dir;
 use std::{
     fs::File,
@@ -6,20 +6,6 @@ use std::{
     path::PathBuf,
 };
 
-fn count_of_chord_size(chart: &Chart, size: usize) -> usize {
-    chart
-        .iter()
-        .flatten()
-        .filter(|(notes, _)| {
-            notes
-                .iter()
-                .filter(|note| note.note_type == NoteType::Tap || note.note_type == NoteType::Hold)
-                .count()
-                == size
-        })
-        .count()
-}
-
 fn main() {
     let mut sim = String::new();
     if let Ok(path) = current_dir() {
@@ -39,9 +25,21 @@ fn main() {
             Some("sm") => {
                 notedata::NoteData::from_sm_reader(sim).expect("Could not deserialize .sm")
             }
+            Some("ssc") => {
+                notedata::NoteData::from_ssc_reader(sim).expect("Could not deserialize .ssc")
+            }
             Some("dwi") => {
                 notedata::NoteData::from_dwi_reader(sim).expect("Could note deseialize .dwi")
             }
+            Some("osu") => {
+                notedata::NoteData::from_osu_reader(sim).expect("Could not deserialize .osu")
+            }
+            Some("bms") | Some("bme") | Some("bml") => {
+                notedata::NoteData::from_bms_reader(sim).expect("Could not deserialize .bms")
+            }
+            Some("pms") => {
+                notedata::NoteData::from_pms_reader(sim).expect("Could not deserialize .pms")
+            }
             _ => panic!("Unsupported extension"),
         }
     } else {
@@ -73,24 +71,37 @@ fn main() {
         println!("Song has no offset");
     }
 
-    for chart in &notedata.charts {
+    for (info, chart) in &notedata.charts {
+        println!();
+        print!("{} {:?}", info.steps_type, info.difficulty);
+        if let Some(meter) = info.meter {
+            print!(" {}", meter);
+        }
         println!();
+        let stats = match ChartStats::new(chart, info.structure_or(&notedata.structure)) {
+            Some(stats) => stats,
+            None => {
+                println!("Chart has no timing");
+                continue;
+            }
+        };
+        println!("Length: {:.1}s", stats.length);
+        println!("Total number of notes: {}", stats.notes);
+        println!("Total taps: {}", stats.rows_with(1));
+        println!("Total jumps: {}", stats.rows_with(2));
+        println!("Total hands: {}", stats.rows_with(3));
+        println!("Total quads: {}", stats.rows_with(4));
+        println!("Total holds: {}", stats.holds);
+        println!("Total rolls: {}", stats.rolls);
+        println!("Total mines: {}", stats.mines);
+        println!("Peak NPS: {}", stats.peak_nps);
         println!(
-            "Total number of notes: {}",
-            chart
-                .iter()
-                .flatten()
-                .map(|(notes, _)| notes
-                    .iter()
-                    .filter(
-                        |note| note.note_type == NoteType::Tap || note.note_type == NoteType::Hold
-                    )
-                    .count())
-                .sum::<usize>()
+            "Radar: stream {:.2}, voltage {:.2}, air {:.2}, freeze {:.2}, chaos {:.2}",
+            stats.radar.stream,
+            stats.radar.voltage,
+            stats.radar.air,
+            stats.radar.freeze,
+            stats.radar.chaos
         );
-        println!("Total taps: {}", count_of_chord_size(chart, 1));
-        println!("Total jumps: {}", count_of_chord_size(chart, 2));
-        println!("Total hands: {}", count_of_chord_size(chart, 3));
-        println!("Total quads: {}", count_of_chord_size(chart, 4));
     }
 }
@@ -0,0 +1,267 @@
+use crate::{steps_type_columns, Chart, ChartInfo, Measure, Note, NoteType};
+
+//The window stays put for this long so a pattern isn't split across two places
+const MEASURES_PER_POSITION: usize = 2;
+
+//Derives a chart for another steps type, or None when there's no rule between the two layouts.
+//Wider layouts get the chart as a window sliding across them, like doubles from singles, and
+//layouts a whole number of times narrower get the chart folded onto them, like singles from
+//doubles. The result only depends on the chart, so it's the same every time.
+#[must_use]
+pub fn autogen(
+    info: &ChartInfo,
+    chart: &[Measure],
+    steps_type: &str,
+) -> Option<(ChartInfo, Chart)> {
+    let from = info.columns(chart);
+    let to = steps_type_columns(steps_type)?;
+    let chart = match (from, to) {
+        (0, _) | (_, 0) => return None,
+        _ if from < to => slide(chart, from, to),
+        _ => match from % to {
+            0 => fold(chart, to),
+            _ => return None,
+        },
+    };
+    let mut info = info.clone();
+    info.steps_type = steps_type.to_owned();
+    //The radar values were for the original layout
+    info.radar_values = vec![];
+    Some((info, chart))
+}
+
+//The window bounces from the left side to the right side and back, in steps of half its width.
+//It only moves at the start of a measure with no hold going on.
+fn slide(chart: &[Measure], from: usize, to: usize) -> Chart {
+    let spare = to - from;
+    let mut offsets: Vec<_> = (0..=spare).step_by((from / 2).max(1)).collect();
+    if offsets.last() != Some(&spare) {
+        offsets.push(spare);
+    }
+    let returning = offsets.len() - 2;
+    offsets.extend(offsets.clone().into_iter().rev().skip(1).take(returning));
+    let mut position = 0;
+    let mut since_move = 0;
+    let mut open_holds = 0_usize;
+    chart
+        .iter()
+        .enumerate()
+        .map(|(index, measure)| {
+            if index > 0 {
+                since_move += 1;
+            }
+            if since_move >= MEASURES_PER_POSITION && open_holds == 0 {
+                position += 1;
+                since_move = 0;
+            }
+            let offset = offsets[position % offsets.len()];
+            measure
+                .iter()
+                .map(|(row, time)| {
+                    for note in row {
+                        match note.note_type {
+                            NoteType::Hold | NoteType::Roll => open_holds += 1,
+                            NoteType::HoldEnd => open_holds = open_holds.saturating_sub(1),
+                            _ => {}
+                        }
+                    }
+                    let row = row
+                        .iter()
+                        .map(|note| Note {
+                            column: note.column + offset,
+                            ..*note
+                        })
+                        .collect();
+                    (row, *time)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+//Notes that land on a column that already has a note in their row, or on a hold, are dropped.
+//Their keysounds are kept playing on their own, as are any other keysounds, in their own column or
+//the first one that's free on that row.
+fn fold(chart: &[Measure], to: usize) -> Chart {
+    //The original column of the hold going on in each new column
+    let mut holds = vec![None; to];
+    chart
+        .iter()
+        .map(|measure| {
+            measure
+                .iter()
+                .map(|(row, time)| {
+                    let mut folded: Vec<Note> = vec![];
+                    //Hold ends come first so a hold ending frees its column for the rest of the row
+                    let (ends, notes): (Vec<&Note>, Vec<&Note>) = row
+                        .iter()
+                        .partition(|note| note.note_type == NoteType::HoldEnd);
+                    for note in ends {
+                        let column = note.column % to;
+                        if holds[column] == Some(note.column) {
+                            holds[column] = None;
+                            folded.push(Note { column, ..*note });
+                        }
+                    }
+                    //Keysounds go in after the notes that are hit, so they can't take their place
+                    let mut keysounds = vec![];
+                    for note in notes {
+                        let column = note.column % to;
+                        let taken = holds[column].is_some()
+                            || folded.iter().any(|other| other.column == column);
+                        if note.note_type != NoteType::AutoKeysound && !taken {
+                            if let NoteType::Hold | NoteType::Roll = note.note_type {
+                                holds[column] = Some(note.column);
+                            }
+                            folded.push(Note { column, ..*note });
+                        } else if note.keysound.is_some() {
+                            keysounds.push(note);
+                        }
+                    }
+                    for note in keysounds {
+                        let free = std::iter::once(note.column % to)
+                            .chain(0..to)
+                            .find(|&column| {
+                                holds[column].is_none()
+                                    && folded.iter().all(|other| other.column != column)
+                            });
+                        if let Some(column) = free {
+                            folded.push(Note {
+                                note_type: NoteType::AutoKeysound,
+                                column,
+                                ..*note
+                            });
+                        }
+                    }
+                    folded.sort_by_key(|note| note.column);
+                    (folded, *time)
+                })
+                .filter(|(row, _)| !row.is_empty())
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser_generic::row, Difficulty, Fraction, NoteData};
+
+    fn columns(chart: &[Measure]) -> Vec<Vec<Vec<usize>>> {
+        chart
+            .iter()
+            .map(|measure| {
+                measure
+                    .iter()
+                    .map(|(row, _)| row.iter().map(|note| note.column).collect())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_to_double() {
+        let stream = vec![
+            row(&[(NoteType::Tap, 0)], Fraction::new(0, 1)),
+            row(&[(NoteType::Tap, 3)], Fraction::new(1, 2)),
+        ];
+        let mut chart = vec![stream.clone(); 8];
+        //The hold across measures 3 and 4 keeps the window from moving until measure 5
+        chart[3] = vec![
+            row(&[(NoteType::Hold, 1)], Fraction::new(0, 1)),
+            row(&[], Fraction::new(1, 2)),
+        ];
+        chart[4] = vec![
+            row(
+                &[(NoteType::HoldEnd, 1), (NoteType::Tap, 2)],
+                Fraction::new(0, 1),
+            ),
+            row(&[], Fraction::new(1, 2)),
+        ];
+        let (info, double) = autogen(&ChartInfo::new(), &chart, "dance-double").unwrap();
+        assert_eq!(info.steps_type, "dance-double");
+        assert_eq!(
+            columns(&double),
+            vec![
+                vec![vec![0], vec![3]],
+                vec![vec![0], vec![3]],
+                vec![vec![2], vec![5]],
+                vec![vec![3], vec![]],
+                vec![vec![3, 4], vec![]],
+                vec![vec![4], vec![7]],
+                vec![vec![4], vec![7]],
+                vec![vec![2], vec![5]],
+            ]
+        );
+        let (_, solo) = autogen(&ChartInfo::new(), &chart, "dance-solo").unwrap();
+        assert_eq!(columns(&solo)[2], vec![vec![2], vec![5]]);
+        assert_eq!(
+            autogen(&ChartInfo::new(), &chart, "kb5-single").unwrap().1[2][0].0[0].column,
+            1
+        );
+    }
+
+    #[test]
+    fn double_to_single() {
+        let mut info = ChartInfo::new();
+        info.steps_type = "dance-double".to_owned();
+        let mut chart = vec![vec![
+            row(
+                &[(NoteType::Hold, 0), (NoteType::Tap, 4), (NoteType::Tap, 6)],
+                Fraction::new(0, 1),
+            ),
+            row(&[(NoteType::Tap, 4)], Fraction::new(1, 4)),
+            row(
+                &[(NoteType::HoldEnd, 0), (NoteType::Tap, 4)],
+                Fraction::new(1, 2),
+            ),
+            row(
+                &[(NoteType::Mine, 1), (NoteType::Tap, 5)],
+                Fraction::new(3, 4),
+            ),
+        ]];
+        chart[0][1].0[0].keysound = Some(3);
+        let (_, single) = autogen(&info, &chart, "dance-single").unwrap();
+        assert_eq!(
+            single,
+            vec![vec![
+                (
+                    vec![Note::new(NoteType::Hold, 0), Note::new(NoteType::Tap, 2)],
+                    Fraction::new(0, 1)
+                ),
+                (
+                    vec![Note {
+                        keysound: Some(3),
+                        ..Note::new(NoteType::AutoKeysound, 1)
+                    }],
+                    Fraction::new(1, 4)
+                ),
+                (vec![Note::new(NoteType::HoldEnd, 0)], Fraction::new(1, 2)),
+                (vec![Note::new(NoteType::Mine, 1)], Fraction::new(3, 4)),
+            ]]
+        );
+        assert_eq!(autogen(&info, &chart, "dance-threepanel"), None);
+        assert_eq!(autogen(&info, &chart, "unknown"), None);
+    }
+
+    #[test]
+    fn fill_missing_charts() {
+        let chart = vec![vec![row(&[(NoteType::Tap, 0)], Fraction::new(0, 1))]];
+        let info = |steps_type: &str, difficulty| ChartInfo {
+            steps_type: steps_type.to_owned(),
+            difficulty,
+            ..ChartInfo::new()
+        };
+        let mut data = NoteData::new();
+        data.charts = vec![
+            (info("dance-single", Difficulty::Easy), chart.clone()),
+            (info("dance-single", Difficulty::Hard), chart.clone()),
+            (info("dance-double", Difficulty::Hard), chart.clone()),
+        ];
+        data.autogen("dance-double");
+        assert_eq!(data.charts.len(), 4);
+        assert_eq!(data.charts[3].0, info("dance-double", Difficulty::Easy));
+        data.autogen("dance-double");
+        assert_eq!(data.charts.len(), 4);
+    }
+}
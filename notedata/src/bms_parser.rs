@@ -0,0 +1,720 @@
+use crate::{
+    error::{ParseError, Problem, ValueError},
+    parser_generic::{integer, number, rows_to_chart},
+    steps_type_columns, BeatPair, ChartInfo, Difficulty, Fraction, Note, NoteData, NoteType,
+    TimeSignature,
+};
+use nom::{
+    bytes::complete::{take, take_while1},
+    character::complete::{char, digit1},
+    combinator::{map, map_res, rest},
+    sequence::{pair, preceded, tuple},
+    IResult,
+};
+use std::collections::{BTreeMap, HashMap};
+
+//The BMS specification's default when a file has no #BPM header
+const DEFAULT_BPM: f64 = 130.0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Lane {
+    Normal,
+    Long,
+    Mine,
+}
+
+struct Event {
+    measure: usize,
+    position: Fraction,
+    channel: (char, char),
+    id: u32,
+}
+
+//Every line of note data is #mmmcc:data, a three digit measure and a two character channel
+fn channel_line(input: &str) -> IResult<&str, (usize, &str, &str)> {
+    tuple((
+        preceded(char('#'), map_res(take(3_usize), str::parse)),
+        take(2_usize),
+        preceded(char(':'), map(rest, str::trim)),
+    ))(input)
+}
+
+fn header_line(input: &str) -> IResult<&str, (&str, &str)> {
+    preceded(
+        char('#'),
+        pair(
+            take_while1(|c: char| c.is_ascii_alphanumeric()),
+            map(rest, str::trim),
+        ),
+    )(input)
+}
+
+fn header_integer(value: &str) -> Result<i32, ValueError<'_>> {
+    Ok(integer(value).map_err(ValueError::bad_number)?.1)
+}
+
+fn base36(id: &str) -> Option<u32> {
+    u32::from_str_radix(id, 36).ok()
+}
+
+//Splits a data line into its two character cells, each placed at an even fraction of the measure
+fn cells(data: &str) -> Option<Vec<(Fraction, &str)>> {
+    if !data.is_ascii() {
+        return None;
+    }
+    let count = data.len() / 2;
+    Some(
+        (0..count)
+            .map(|index| {
+                (
+                    Fraction::new(index as i32, count as i32),
+                    &data[index * 2..index * 2 + 2],
+                )
+            })
+            .collect(),
+    )
+}
+
+fn lane_type(channel: char) -> Option<(usize, Lane)> {
+    match channel {
+        '1' => Some((1, Lane::Normal)),
+        '2' => Some((2, Lane::Normal)),
+        '5' => Some((1, Lane::Long)),
+        '6' => Some((2, Lane::Long)),
+        'D' => Some((1, Lane::Mine)),
+        'E' => Some((2, Lane::Mine)),
+        _ => None,
+    }
+}
+
+//BMS puts the scratch on key 6 and the last two keys on 8 and 9, PMS uses keys 1-5 and then 2-5
+fn column(side: usize, key: char, pms: bool) -> Option<usize> {
+    let key = key.to_digit(10)? as usize;
+    if pms {
+        return match (side, key) {
+            (1, 1..=5) => Some(key - 1),
+            (2, 2..=5) => Some(key + 3),
+            _ => None,
+        };
+    }
+    let lane = match key {
+        1..=5 => key,
+        8 | 9 => key - 2,
+        6 => 0,
+        _ => return None,
+    };
+    Some(match (side, lane) {
+        (1, _) => lane,
+        //The second side mirrors the first, with its scratch on the far right
+        (_, 0) => 15,
+        _ => lane + 7,
+    })
+}
+
+fn time_signature(length: Fraction) -> TimeSignature {
+    let (mut numerator, mut denominator) = (*length.numer(), *length.denom());
+    while denominator < 4 {
+        numerator *= 2;
+        denominator *= 2;
+    }
+    TimeSignature {
+        numerator,
+        denominator,
+    }
+}
+
+fn difficulty(level: i32) -> Difficulty {
+    match level {
+        1 => Difficulty::Beginner,
+        2 => Difficulty::Easy,
+        3 => Difficulty::Medium,
+        4 => Difficulty::Hard,
+        5 => Difficulty::Challenge,
+        _ => Difficulty::Edit,
+    }
+}
+
+//Everything in the file that is only known once every line has been read
+struct Definitions {
+    bpm: f64,
+    bpms: HashMap<u32, f64>,
+    stops: HashMap<u32, f64>,
+    keysounds: BTreeMap<u32, String>,
+    long_note_type: i32,
+    long_note_object: Option<u32>,
+    doubles: bool,
+    measure_lengths: HashMap<usize, f64>,
+    events: Vec<Event>,
+}
+
+impl Definitions {
+    //Keysounds are stored in the order of their ids, so a note's sample is found by its position
+    fn keysound(&self, id: u32) -> Option<usize> {
+        self.keysounds.keys().position(|&key| key == id)
+    }
+}
+
+//Measures are stored in the chart as 4/4, so shortened measures shift everything after them
+struct Measures {
+    starts: Vec<Fraction>,
+    lengths: Vec<Fraction>,
+}
+
+impl Measures {
+    fn new(definitions: &Definitions) -> Self {
+        let last_measure = definitions
+            .events
+            .iter()
+            .map(|event| event.measure)
+            .chain(definitions.measure_lengths.keys().copied())
+            .max()
+            .unwrap_or_default();
+        let lengths: Vec<_> = (0..=last_measure)
+            .map(|measure| {
+                definitions
+                    .measure_lengths
+                    .get(&measure)
+                    .map(|length| Fraction::new((length * 192.0).round() as i32, 192))
+                    .filter(|length| *length > Fraction::from(0))
+                    .unwrap_or_else(|| Fraction::from(1))
+            })
+            .collect();
+        let mut starts = vec![Fraction::from(0)];
+        for length in &lengths {
+            starts.push(starts[starts.len() - 1] + length);
+        }
+        Self { starts, lengths }
+    }
+
+    fn position(&self, event: &Event) -> Fraction {
+        self.starts[event.measure] + self.lengths[event.measure] * event.position
+    }
+}
+
+fn row(position: Fraction) -> (usize, Fraction) {
+    (position.to_integer() as usize, position.fract())
+}
+
+fn data_line<'a>(
+    definitions: &mut Definitions,
+    measure: usize,
+    channel_name: &str,
+    data: &'a str,
+) -> Result<(), ValueError<'a>> {
+    let mut channel_chars = channel_name.chars().map(|c| c.to_ascii_uppercase());
+    let channel = match (channel_chars.next(), channel_chars.next()) {
+        (Some(first), Some(second)) => (first, second),
+        _ => return Ok(()),
+    };
+    if channel == ('0', '2') {
+        definitions.measure_lengths.insert(measure, number(data)?);
+        return Ok(());
+    }
+    for (position, cell) in cells(data).ok_or_else(|| ValueError::at(Problem::Syntax, data))? {
+        let id = if channel == ('0', '3') {
+            u32::from_str_radix(cell, 16).ok()
+        } else {
+            base36(cell)
+        }
+        .ok_or_else(|| ValueError::at(Problem::UnknownNote, cell))?;
+        //Long note channels keep their empty cells, MGQ style holds end on them
+        if id != 0 || channel.0 == '5' || channel.0 == '6' {
+            definitions.events.push(Event {
+                measure,
+                position,
+                channel,
+                id,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn header<'a>(
+    nd: &mut NoteData,
+    info: &mut ChartInfo,
+    definitions: &mut Definitions,
+    key: &str,
+    value: &'a str,
+) -> Result<(), ValueError<'a>> {
+    match key {
+        "TITLE" => nd.meta.title = Some(value.to_owned()),
+        "SUBTITLE" => nd.meta.subtitle = Some(value.to_owned()),
+        "ARTIST" => nd.meta.artist = Some(value.to_owned()),
+        "SUBARTIST" => info.credit = Some(value.to_owned()),
+        "GENRE" => nd.meta.genre = Some(value.to_owned()),
+        "BANNER" => nd.meta.banner_path = Some(value.to_owned()),
+        "STAGEFILE" if nd.meta.background_path.is_none() => {
+            nd.meta.background_path = Some(value.to_owned())
+        }
+        "BACKBMP" => nd.meta.background_path = Some(value.to_owned()),
+        "PLAYER" => definitions.doubles = header_integer(value)? == 3,
+        "PLAYLEVEL" => {
+            info.meter = Some(
+                map_res(digit1, str::parse)(value)
+                    .map_err(ValueError::bad_number)?
+                    .1,
+            )
+        }
+        "DIFFICULTY" => info.difficulty = difficulty(header_integer(value)?),
+        "BPM" => definitions.bpm = number(value)?,
+        "LNTYPE" => definitions.long_note_type = header_integer(value)?,
+        "LNOBJ" => definitions.long_note_object = base36(value),
+        _ => {
+            let definition = |prefix: &str| {
+                key.strip_prefix(prefix)
+                    .filter(|id| id.len() == 2)
+                    .and_then(base36)
+            };
+            if let Some(id) = definition("WAV") {
+                definitions.keysounds.insert(id, value.to_owned());
+            } else if let Some(id) = definition("BPM") {
+                definitions.bpms.insert(id, number(value)?);
+            } else if let Some(id) = definition("STOP") {
+                definitions.stops.insert(id, number(value)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn structure(nd: &mut NoteData, definitions: &Definitions, measures: &Measures) {
+    let mut bpms = BTreeMap::new();
+    bpms.insert(Fraction::from(0), definitions.bpm);
+    for event in &definitions.events {
+        let value = match event.channel {
+            ('0', '3') => f64::from(event.id),
+            ('0', '8') => match definitions.bpms.get(&event.id) {
+                Some(value) => *value,
+                None => continue,
+            },
+            _ => continue,
+        };
+        bpms.insert(measures.position(event), value);
+    }
+    //Stops are measured in 192nds of a 4/4 measure at the bpm they happen at
+    let mut stops = BTreeMap::new();
+    for event in &definitions.events {
+        if let (('0', '9'), Some(length)) = (event.channel, definitions.stops.get(&event.id)) {
+            let at = measures.position(event);
+            let bpm = bpms
+                .range(..=at)
+                .next_back()
+                .map_or(definitions.bpm, |(_, bpm)| *bpm);
+            *stops.entry(at).or_insert(0.0) += length / 192.0 * 240.0 / bpm;
+        }
+    }
+    nd.structure.offset = Some(0.0);
+    nd.structure.bpms = bpms
+        .into_iter()
+        .map(|(at, value)| BeatPair::at_position(at, value))
+        .collect();
+    if !stops.is_empty() {
+        nd.structure.stops = Some(
+            stops
+                .into_iter()
+                .map(|(at, length)| BeatPair::at_position(at, length))
+                .collect(),
+        );
+    }
+    if measures
+        .lengths
+        .iter()
+        .any(|length| *length != Fraction::from(1))
+    {
+        let mut signatures = vec![];
+        let mut previous = None;
+        for (start, length) in measures.starts.iter().zip(&measures.lengths) {
+            if previous != Some(length) {
+                signatures.push(BeatPair::at_position(*start, time_signature(*length)));
+                previous = Some(length);
+            }
+        }
+        nd.structure.time_signatures = Some(signatures);
+    }
+}
+
+fn chart(
+    definitions: &Definitions,
+    measures: &Measures,
+    pms: bool,
+) -> BTreeMap<(usize, Fraction), Vec<Note>> {
+    let mut rows: BTreeMap<(usize, Fraction), Vec<Note>> = BTreeMap::new();
+    let mut last_taps = vec![None; 16];
+    //MGQ style holds also end at the first measure without data for their channel
+    let mut open_holds: Vec<Option<usize>> = vec![None; 16];
+    let end_hold = |chart: &mut BTreeMap<_, Vec<_>>, measure: usize, column| {
+        chart
+            .entry(row(measures.starts[measure + 1]))
+            .or_default()
+            .push(Note::new(NoteType::HoldEnd, column))
+    };
+    for event in &definitions.events {
+        let (column, lane) = match lane_type(event.channel.0)
+            .and_then(|(side, lane)| Some((column(side, event.channel.1, pms)?, lane)))
+        {
+            Some(lane) => lane,
+            None => continue,
+        };
+        let note_type = match lane {
+            Lane::Mine => NoteType::Mine,
+            Lane::Long if definitions.long_note_type == 2 => match open_holds[column] {
+                Some(measure) if event.measure > measure + 1 => {
+                    end_hold(&mut rows, measure, column);
+                    open_holds[column] = None;
+                    if event.id == 0 {
+                        continue;
+                    }
+                    open_holds[column] = Some(event.measure);
+                    NoteType::Hold
+                }
+                Some(_) if event.id == 0 => {
+                    open_holds[column] = None;
+                    NoteType::HoldEnd
+                }
+                Some(_) => {
+                    open_holds[column] = Some(event.measure);
+                    continue;
+                }
+                None if event.id == 0 => continue,
+                None => {
+                    open_holds[column] = Some(event.measure);
+                    NoteType::Hold
+                }
+            },
+            Lane::Long if event.id != 0 => {
+                if open_holds[column].take().is_some() {
+                    NoteType::HoldEnd
+                } else {
+                    open_holds[column] = Some(event.measure);
+                    NoteType::Hold
+                }
+            }
+            Lane::Long => continue,
+            Lane::Normal if Some(event.id) == definitions.long_note_object => {
+                match last_taps[column].take() {
+                    Some(start) => {
+                        for note in rows.entry(start).or_default() {
+                            if note.column == column {
+                                note.note_type = NoteType::Hold;
+                            }
+                        }
+                        NoteType::HoldEnd
+                    }
+                    None => continue,
+                }
+            }
+            Lane::Normal => {
+                last_taps[column] = Some(row(measures.position(event)));
+                NoteType::Tap
+            }
+        };
+        let keysound = match note_type {
+            NoteType::Tap | NoteType::Hold => definitions.keysound(event.id),
+            _ => None,
+        };
+        rows.entry(row(measures.position(event)))
+            .or_default()
+            .push(Note {
+                keysound,
+                ..Note::new(note_type, column)
+            });
+    }
+    if definitions.long_note_type == 2 {
+        for (column, open) in open_holds.into_iter().enumerate() {
+            if let Some(measure) = open {
+                end_hold(&mut rows, measure, column);
+            }
+        }
+    }
+    rows
+}
+
+//Whether a hold in the column is still going on at the position, not counting one starting there
+fn in_hold(
+    rows: &BTreeMap<(usize, Fraction), Vec<Note>>,
+    position: (usize, Fraction),
+    column: usize,
+) -> bool {
+    rows.range(..position)
+        .rev()
+        .flat_map(|(_, row)| row.iter().filter(|note| note.column == column))
+        .find_map(|note| match note.note_type {
+            NoteType::Hold | NoteType::Roll => Some(true),
+            NoteType::HoldEnd => Some(false),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+//Background keysounds have no column of their own, so like StepMania they take the first free one.
+//A column is free when it has no note on that row and no hold going through it.
+fn background_keysounds(
+    rows: &mut BTreeMap<(usize, Fraction), Vec<Note>>,
+    definitions: &Definitions,
+    measures: &Measures,
+    columns: usize,
+) {
+    for event in &definitions.events {
+        let keysound = match (event.channel, definitions.keysound(event.id)) {
+            (('0', '1'), Some(keysound)) => keysound,
+            _ => continue,
+        };
+        let position = row(measures.position(event));
+        let taken: Vec<_> = rows
+            .get(&position)
+            .into_iter()
+            .flatten()
+            .map(|note| note.column)
+            .collect();
+        if let Some(column) = (0..columns)
+            .find(|&column| !taken.contains(&column) && !in_hold(rows, position, column))
+        {
+            rows.entry(position).or_default().push(Note {
+                keysound: Some(keysound),
+                ..Note::new(NoteType::AutoKeysound, column)
+            });
+        }
+    }
+}
+
+pub fn parse(input: &str, pms: bool) -> Result<NoteData, ParseError> {
+    let mut nd = NoteData::new();
+    let mut info = ChartInfo::new();
+    let mut definitions = Definitions {
+        bpm: DEFAULT_BPM,
+        bpms: HashMap::new(),
+        stops: HashMap::new(),
+        keysounds: BTreeMap::new(),
+        long_note_type: 1,
+        long_note_object: None,
+        doubles: false,
+        measure_lengths: HashMap::new(),
+        events: vec![],
+    };
+    //Random blocks always take their first branch, so loading a chart is deterministic
+    let mut branches = vec![];
+    for line in input.lines().map(str::trim) {
+        if !line.starts_with('#') {
+            continue;
+        }
+        let active = !branches.contains(&false);
+        if let Ok((_, (measure, channel, data))) = channel_line(line) {
+            if active {
+                let tag = line[1..].split(':').next().unwrap_or("");
+                data_line(&mut definitions, measure, channel, data)
+                    .map_err(|error| error.locate(input, tag))?;
+            }
+            continue;
+        }
+        let (_, (key, value)) =
+            header_line(line).map_err(|error| ValueError::from(error).locate(input, line))?;
+        let locate = |error: ValueError| error.locate(input, key);
+        match key.to_uppercase().as_str() {
+            "IF" => branches.push(header_integer(value).map_err(locate)? == 1),
+            "ELSE" => {
+                if let Some(branch) = branches.last_mut() {
+                    *branch = !*branch;
+                }
+            }
+            "ENDIF" => {
+                branches.pop();
+            }
+            key if active => {
+                header(&mut nd, &mut info, &mut definitions, key, value).map_err(locate)?
+            }
+            _ => {}
+        }
+    }
+    nd.meta.keysounds = definitions.keysounds.values().cloned().collect();
+
+    let measures = Measures::new(&definitions);
+    definitions
+        .events
+        .sort_by_key(|event| measures.position(event));
+    structure(&mut nd, &definitions, &measures);
+    let mut rows = chart(&definitions, &measures, pms);
+    let doubles =
+        definitions.doubles || !pms && rows.values().flatten().any(|note| note.column >= 8);
+    info.steps_type = match (pms, doubles) {
+        (true, _) => "popn-nine",
+        (false, true) => "beat-double7",
+        (false, false) => "beat-single7",
+    }
+    .to_owned();
+    let columns = steps_type_columns(&info.steps_type).unwrap_or_default();
+    background_keysounds(&mut rows, &definitions, &measures, columns);
+    nd.charts.push((info, rows_to_chart(rows)));
+    Ok(nd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyed(note_type: NoteType, column: usize, keysound: usize) -> Note {
+        Note {
+            keysound: Some(keysound),
+            ..Note::new(note_type, column)
+        }
+    }
+
+    #[test]
+    fn parse_bms() {
+        let nd = parse(
+            "#PLAYER 1
+#TITLE Song
+#ARTIST Someone
+#SUBARTIST obj: mapper
+#PLAYLEVEL 7
+#DIFFICULTY 3
+#BPM 120
+#BPM01 150
+#STOP01 96
+#LNOBJ ZZ
+#WAV01 kick.wav
+#WAV0A snare.wav
+#00102:0.75
+#00103:00F0
+#00108:01
+#00109:01
+#00111:01000000
+#00116:0001
+#00155:0101
+#002D9:0A00
+#00218:01ZZ
+#00101:0A0001
+",
+            false,
+        )
+        .unwrap();
+        assert_eq!(nd.meta.title, Some("Song".to_owned()));
+        assert_eq!(nd.meta.keysounds, vec!["kick.wav", "snare.wav"]);
+        let info = &nd.charts[0].0;
+        assert_eq!(info.steps_type, "beat-single7");
+        assert_eq!(info.meter, Some(7));
+        assert_eq!(info.difficulty, Difficulty::Medium);
+        assert_eq!(info.credit, Some("obj: mapper".to_owned()));
+        assert_eq!(
+            nd.structure.bpms,
+            vec![
+                BeatPair::at_start(120.0),
+                BeatPair::at_position(Fraction::from(1), 150.0),
+                BeatPair::at_position(Fraction::new(11, 8), 240.0),
+            ]
+        );
+        assert_eq!(
+            nd.structure.stops,
+            Some(vec![BeatPair::at_position(Fraction::from(1), 0.8)])
+        );
+        assert_eq!(
+            nd.structure.time_signatures,
+            Some(vec![
+                BeatPair::at_start(time_signature(Fraction::from(1))),
+                BeatPair::at_position(Fraction::from(1), time_signature(Fraction::new(3, 4))),
+                BeatPair::at_position(Fraction::new(7, 4), time_signature(Fraction::from(1))),
+            ])
+        );
+        assert_eq!(
+            nd.charts[0].1,
+            vec![
+                vec![],
+                vec![
+                    (
+                        vec![
+                            keyed(NoteType::AutoKeysound, 0, 1),
+                            keyed(NoteType::Tap, 1, 0),
+                            keyed(NoteType::Hold, 5, 0)
+                        ],
+                        Fraction::from(0)
+                    ),
+                    (
+                        vec![keyed(NoteType::Tap, 0, 0), Note::new(NoteType::HoldEnd, 5)],
+                        Fraction::new(3, 8)
+                    ),
+                    (
+                        vec![keyed(NoteType::AutoKeysound, 0, 0)],
+                        Fraction::new(1, 2)
+                    ),
+                    (
+                        vec![keyed(NoteType::Hold, 6, 0), Note::new(NoteType::Mine, 7)],
+                        Fraction::new(3, 4)
+                    ),
+                ],
+                vec![(vec![Note::new(NoteType::HoldEnd, 6)], Fraction::new(1, 4))],
+            ]
+        );
+    }
+
+    #[test]
+    fn background_keysounds_around_holds() {
+        let nd = parse("#WAV02 hat.wav\n#00156:01000100\n#00101:00020000\n", false).unwrap();
+        assert_eq!(
+            nd.charts[0].1[1],
+            vec![
+                (vec![Note::new(NoteType::Hold, 0)], Fraction::from(0)),
+                (
+                    vec![keyed(NoteType::AutoKeysound, 1, 0)],
+                    Fraction::new(1, 4)
+                ),
+                (vec![Note::new(NoteType::HoldEnd, 0)], Fraction::new(1, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_doubles_and_pms() {
+        let doubles = parse("#00126:01\n#00121:01\n", false).unwrap();
+        assert_eq!(doubles.charts[0].0.steps_type, "beat-double7");
+        assert_eq!(
+            doubles.charts[0].1[1],
+            vec![(
+                vec![Note::new(NoteType::Tap, 8), Note::new(NoteType::Tap, 15)],
+                Fraction::from(0)
+            )]
+        );
+        let pms = parse("#00111:01\n#00125:01\n", true).unwrap();
+        assert_eq!(pms.charts[0].0.steps_type, "popn-nine");
+        assert_eq!(
+            pms.charts[0].1[1],
+            vec![(
+                vec![Note::new(NoteType::Tap, 0), Note::new(NoteType::Tap, 8)],
+                Fraction::from(0)
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_random_and_mgq_holds() {
+        let nd = parse(
+            "#LNTYPE 2
+#RANDOM 2
+#IF 1
+#00011:01
+#ELSE
+#00012:01
+#ENDIF
+#ENDRANDOM
+#00053:00010101
+#00153:01000000
+#00054:0100
+",
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            nd.charts[0].1,
+            vec![
+                vec![
+                    (
+                        vec![Note::new(NoteType::Tap, 1), Note::new(NoteType::Hold, 4)],
+                        Fraction::from(0)
+                    ),
+                    (vec![Note::new(NoteType::Hold, 3)], Fraction::new(1, 4)),
+                    (vec![Note::new(NoteType::HoldEnd, 4)], Fraction::new(1, 2)),
+                ],
+                vec![(vec![Note::new(NoteType::HoldEnd, 3)], Fraction::new(1, 4))],
+            ]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    sm_writer::notetype_to_char,
+    timingdata::{CalcInfo, Rectangle, TimingData},
+    Measure, NoteType, StructureData,
+};
+use sha1::{Digest, Sha1};
+use std::fmt::Write;
+
+//Hashes when each note is hit rather than where it sits in the chart, so any format that times
+//the notes the same way gives the same key. Times are relative to the first note, so resyncing
+//a chart's offset keeps its scores. Background keysounds have nothing to hit, so they're left out.
+#[must_use]
+pub fn chart_key(chart: &[Measure], structure: &StructureData, columns: usize) -> String {
+    let timing = TimingData::<CalcInfo>::from_chartdata(
+        chart,
+        structure,
+        columns,
+        &|_, _, _, _, _| Rectangle::default(),
+        1.0,
+    );
+    let start = timing
+        .notes
+        .iter()
+        .flat_map(|column| &column.notes)
+        .filter(|note| note.1 != NoteType::AutoKeysound)
+        .map(|note| note.0)
+        .min()
+        .unwrap_or_default();
+    let mut notes = String::new();
+    for column in &timing.notes {
+        for CalcInfo(time, note_type) in &column.notes {
+            if *note_type == NoteType::AutoKeysound {
+                continue;
+            }
+            write!(notes, "{}{},", notetype_to_char(*note_type), time - start)
+                .expect("Writing to a string can't fail");
+        }
+        notes.push(';');
+    }
+    Sha1::digest(notes.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{dwi_parser, sm_parser, sm_writer, NoteData, ParseOptions};
+
+    const SIMFILE: &str = "#TITLE:foo;
+#OFFSET:-0.25;
+#BPMS:0.000=120.000,4.000=240.000;
+#STOPS:2.000=0.500;
+#NOTES:dance-single::Hard:5::
+1000
+0100
+0010
+0001
+,
+2000
+0000
+3000
+0000
+,
+1001
+;
+#NOTES:dance-single::Easy:3::
+1000
+0000
+0001
+0000
+;";
+
+    fn parse(input: &str) -> NoteData {
+        sm_parser::parse(input, ParseOptions::default()).unwrap().0
+    }
+
+    #[test]
+    fn stable_keys() {
+        let data = parse(SIMFILE);
+        let keys = data.chart_keys();
+        assert_eq!(keys[0].len(), 40);
+        assert_ne!(keys[0], keys[1]);
+        assert_eq!(
+            parse(&sm_writer::write_sm(&data).unwrap()).chart_keys(),
+            keys
+        );
+        let dwi = data.to_dwi_string().unwrap();
+        assert_eq!(
+            dwi_parser::parse(&dwi, ParseOptions::default())
+                .unwrap()
+                .0
+                .chart_keys(),
+            keys
+        );
+        //Metadata and offset edits keep the key, moving a note doesn't
+        assert_eq!(
+            parse(&SIMFILE.replace("#TITLE:foo;\n#OFFSET:-0.25;", "#OFFSET:0.1;")).chart_keys(),
+            keys
+        );
+        assert_ne!(
+            parse(&SIMFILE.replacen("0001\n,", "0000\n,", 1)).chart_keys()[0],
+            keys[0]
+        );
+        assert_ne!(
+            parse(&SIMFILE.replace("2.000=0.500", "2.000=0.250")).chart_keys()[0],
+            keys[0]
+        );
+    }
+
+    #[cfg(feature = "rm-format")]
+    #[test]
+    fn stable_through_rm() {
+        use crate::RmEncoding;
+        let data = parse(SIMFILE);
+        for encoding in &[RmEncoding::Binary, RmEncoding::Text] {
+            let mut rm = vec![];
+            data.to_rm_writer(&mut rm, *encoding).unwrap();
+            assert_eq!(
+                NoteData::from_rm_reader(&rm[..]).unwrap().chart_keys(),
+                data.chart_keys()
+            );
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{parser_generic::rows_to_chart, Chart, Fraction, Measure, Note, NoteRow, NoteType};
+use std::{collections::BTreeMap, convert::TryFrom, slice};
+
+//Walks a chart by absolute position, in beats from its start, instead of measure by measure
+pub trait ChartNotes {
+    fn rows(&self) -> Rows<'_>;
+    fn notes(&self) -> Notes<'_>;
+    //Every hold and roll paired with its end, in order of their heads. Heads that never end are
+    //left out.
+    fn holds(&self) -> Vec<Hold>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Hold {
+    //The head, either a Hold or a Roll
+    pub note: Note,
+    pub start: Fraction,
+    pub end: Fraction,
+}
+
+pub struct Rows<'a> {
+    measures: std::iter::Enumerate<slice::Iter<'a, Measure>>,
+    measure: Option<(Fraction, slice::Iter<'a, (NoteRow, Fraction)>)>,
+}
+
+pub struct Notes<'a> {
+    rows: Rows<'a>,
+    row: Option<(Fraction, slice::Iter<'a, Note>)>,
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = (Fraction, &'a NoteRow);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((start, rows)) = &mut self.measure {
+                if let Some((row, position)) = rows.next() {
+                    return Some(((*start + position) * 4, row));
+                }
+            }
+            let (index, measure) = self.measures.next()?;
+            let start = Fraction::from(i32::try_from(index).ok()?);
+            self.measure = Some((start, measure.iter()));
+        }
+    }
+}
+
+impl<'a> Iterator for Notes<'a> {
+    type Item = (Fraction, &'a Note);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((beat, notes)) = &mut self.row {
+                if let Some(note) = notes.next() {
+                    return Some((*beat, note));
+                }
+            }
+            let (beat, row) = self.rows.next()?;
+            self.row = Some((beat, row.iter()));
+        }
+    }
+}
+
+impl ChartNotes for [Measure] {
+    fn rows(&self) -> Rows<'_> {
+        Rows {
+            measures: self.iter().enumerate(),
+            measure: None,
+        }
+    }
+
+    fn notes(&self) -> Notes<'_> {
+        Notes {
+            rows: self.rows(),
+            row: None,
+        }
+    }
+
+    fn holds(&self) -> Vec<Hold> {
+        //Each head with its start and end, if it's had one yet
+        let mut heads: Vec<(Note, Fraction, Option<Fraction>)> = vec![];
+        //The index in heads of the hold going on in each column
+        let mut open: BTreeMap<usize, usize> = BTreeMap::new();
+        for (beat, note) in self.notes() {
+            match note.note_type {
+                NoteType::Hold | NoteType::Roll => {
+                    open.insert(note.column, heads.len());
+                    heads.push((*note, beat, None));
+                }
+                NoteType::HoldEnd => {
+                    if let Some(index) = open.remove(&note.column) {
+                        heads[index].2 = Some(beat);
+                    }
+                }
+                _ => {}
+            }
+        }
+        heads
+            .into_iter()
+            .filter_map(|(note, start, end)| {
+                Some(Hold {
+                    note,
+                    start,
+                    end: end?,
+                })
+            })
+            .collect()
+    }
+}
+
+//The reverse of notes, the notes can come in any order. Notes before the start of the chart are
+//dropped, as are any after the first at the same position and column.
+#[must_use]
+pub fn chart_from_notes(notes: impl IntoIterator<Item = (Fraction, Note)>) -> Chart {
+    let mut rows: BTreeMap<(usize, Fraction), Vec<Note>> = BTreeMap::new();
+    for (beat, note) in notes {
+        let measures = beat / 4;
+        if let Ok(measure) = usize::try_from(measures.floor().to_integer()) {
+            rows.entry((measure, measures.fract()))
+                .or_default()
+                .push(note);
+        }
+    }
+    rows_to_chart(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chart() -> Chart {
+        vec![
+            vec![
+                (
+                    vec![Note::new(NoteType::Tap, 0), Note::new(NoteType::Hold, 2)],
+                    Fraction::new(0, 1),
+                ),
+                (vec![Note::new(NoteType::Roll, 1)], Fraction::new(3, 4)),
+            ],
+            vec![],
+            vec![
+                (vec![Note::new(NoteType::HoldEnd, 2)], Fraction::new(1, 8)),
+                (
+                    vec![
+                        Note::new(NoteType::HoldEnd, 1),
+                        Note::new(NoteType::Hold, 3),
+                    ],
+                    Fraction::new(1, 2),
+                ),
+            ],
+        ]
+    }
+
+    #[test]
+    fn absolute_positions() {
+        let chart = chart();
+        assert_eq!(
+            chart
+                .rows()
+                .map(|(beat, row)| (beat, row.len()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Fraction::from(0), 2),
+                (Fraction::from(3), 1),
+                (Fraction::new(17, 2), 1),
+                (Fraction::from(10), 2),
+            ]
+        );
+        assert_eq!(
+            chart
+                .notes()
+                .map(|(beat, note)| (beat, note.column))
+                .collect::<Vec<_>>(),
+            vec![
+                (Fraction::from(0), 0),
+                (Fraction::from(0), 2),
+                (Fraction::from(3), 1),
+                (Fraction::new(17, 2), 2),
+                (Fraction::from(10), 1),
+                (Fraction::from(10), 3),
+            ]
+        );
+        assert_eq!(
+            chart.holds(),
+            vec![
+                Hold {
+                    note: Note::new(NoteType::Hold, 2),
+                    start: Fraction::from(0),
+                    end: Fraction::new(17, 2),
+                },
+                Hold {
+                    note: Note::new(NoteType::Roll, 1),
+                    start: Fraction::from(3),
+                    end: Fraction::from(10),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let chart = chart();
+        let mut notes: Vec<_> = chart.notes().map(|(beat, note)| (beat, *note)).collect();
+        notes.reverse();
+        notes.push((Fraction::from(-1), Note::new(NoteType::Tap, 0)));
+        notes.push((Fraction::from(0), Note::new(NoteType::Mine, 0)));
+        assert_eq!(chart_from_notes(notes), chart);
+    }
+}
@@ -1,18 +1,21 @@
 use crate::{
-    parser_generic::{beat_pair, comma_separated, stepmania_tag, ws_trimmed},
-    BeatPair, DisplayBpm, Fraction, Measure, Note, NoteData, NoteRow, NoteType,
+    error::{ParseError, Problem, ValueError},
+    parser_generic::{for_each_tag, number, ws_trimmed, Warnings},
+    sm_parser::into_difficulty,
+    BeatPair, ChartInfo, DisplayBpm, Fraction, Measure, Note, NoteData, NoteRow, NoteType,
+    ParseOptions,
 };
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_until},
-    character::complete::{anychar, char, multispace0},
-    combinator::{map, map_opt},
-    error::ErrorKind,
-    multi::{count, fold_many0, fold_many_m_n, many0},
+    character::complete::{anychar, char, digit1, multispace0},
+    combinator::{map, map_opt, map_res, opt},
+    multi::{fold_many0, fold_many_m_n, many0},
     number::complete::double,
-    sequence::{preceded, separated_pair, terminated},
-    Err, IResult,
+    sequence::{pair, preceded, separated_pair, terminated, tuple},
+    IResult,
 };
+use std::collections::{BTreeMap, HashSet};
 
 #[allow(clippy::unnecessary_wraps)]
 fn display_bpm_dwi(input: &str) -> IResult<&str, DisplayBpm> {
@@ -28,41 +31,44 @@ fn display_bpm_dwi(input: &str) -> IResult<&str, DisplayBpm> {
     .unwrap_or(("", DisplayBpm::Random)))
 }
 
-fn dwi_noterow(input: &str) -> IResult<&str, NoteRow> {
-    alt((
-        map(
-            separated_pair(
-                dwi_noterow_type(NoteType::Tap),
-                char('!'),
-                dwi_noterow_type(NoteType::Hold),
-            ),
-            |(mut full, holds)| {
-                for hold in holds.iter().map(|note| note.column) {
-                    if let Some(note) = full.iter_mut().find(|note| note.column == hold) {
-                        note.note_type = NoteType::Hold
+//Maps a .dwi step character to the panels it presses
+pub type Panels = fn(char) -> Option<Vec<usize>>;
+
+fn dwi_noterow<'a>(panels: Panels) -> impl Fn(&'a str) -> IResult<&'a str, NoteRow> {
+    move |input| {
+        alt((
+            map(
+                separated_pair(
+                    dwi_noterow_type(NoteType::Tap, panels),
+                    char('!'),
+                    dwi_noterow_type(NoteType::Hold, panels),
+                ),
+                |(mut full, holds)| {
+                    for hold in holds.iter().map(|note| note.column) {
+                        if let Some(note) = full.iter_mut().find(|note| note.column == hold) {
+                            note.note_type = NoteType::Hold
+                        }
                     }
-                }
-                full
-            },
-        ),
-        dwi_noterow_type(NoteType::Tap),
-    ))(input)
+                    full
+                },
+            ),
+            dwi_noterow_type(NoteType::Tap, panels),
+        ))(input)
+    }
 }
 
-fn dwi_noterow_type<'a>(note: NoteType) -> impl Fn(&'a str) -> IResult<&'a str, NoteRow> {
+fn dwi_noterow_type<'a>(
+    note: NoteType,
+    panels: Panels,
+) -> impl Fn(&'a str) -> IResult<&'a str, NoteRow> {
     move |input| {
-        map(map_opt(anychar, char_to_columns_list), |row| {
-            row.iter()
-                .map(|&column| Note {
-                    note_type: note,
-                    column,
-                })
-                .collect()
+        map(map_opt(anychar, panels), |row| {
+            row.iter().map(|&column| Note::new(note, column)).collect()
         })(input)
     }
 }
 
-fn char_to_columns_list(input: char) -> Option<Vec<usize>> {
+pub fn four_panel_columns(input: char) -> Option<Vec<usize>> {
     match input {
         //5 should not appear in normal dwi files, but it can be parsed by stepmania 5
         '0' | '5' => Some(vec![]),
@@ -80,12 +86,46 @@ fn char_to_columns_list(input: char) -> Option<Vec<usize>> {
     }
 }
 
-fn dwi_measure_n<'a>(n: usize) -> impl Fn(&'a str) -> IResult<&'a str, Measure> {
+//Solo charts use the dance-solo column order: left, up-left, down, up, up-right, right
+pub fn six_panel_columns(input: char) -> Option<Vec<usize>> {
+    match input {
+        '0' | '5' => Some(vec![]),
+        '4' => Some(vec![0]),
+        'C' => Some(vec![1]),
+        '2' => Some(vec![2]),
+        '8' => Some(vec![3]),
+        'D' => Some(vec![4]),
+        '6' => Some(vec![5]),
+        'E' => Some(vec![0, 1]),
+        '1' => Some(vec![0, 2]),
+        '7' => Some(vec![0, 3]),
+        'I' => Some(vec![0, 4]),
+        'B' => Some(vec![0, 5]),
+        'F' => Some(vec![1, 2]),
+        'G' => Some(vec![1, 3]),
+        'M' => Some(vec![1, 4]),
+        'H' => Some(vec![1, 5]),
+        'A' => Some(vec![2, 3]),
+        'J' => Some(vec![2, 4]),
+        '3' => Some(vec![2, 5]),
+        'K' => Some(vec![3, 4]),
+        '9' => Some(vec![3, 5]),
+        'L' => Some(vec![4, 5]),
+        _ => None,
+    }
+}
+
+//Only the last measure of a chart can be cut short, the rest of it is empty
+fn dwi_measure_n<'a>(
+    n: usize,
+    partial: bool,
+    panels: Panels,
+) -> impl Fn(&'a str) -> IResult<&'a str, Measure> {
     move |input| {
         fold_many_m_n(
+            if partial { 1 } else { n },
             n,
-            n,
-            alt((dwi_noterow, dwi_chord)),
+            alt((dwi_noterow(panels), dwi_chord(panels))),
             (vec![], 0),
             |(mut acc, idx), item| {
                 if !item.is_empty() {
@@ -98,62 +138,167 @@ fn dwi_measure_n<'a>(n: usize) -> impl Fn(&'a str) -> IResult<&'a str, Measure>
     }
 }
 
-fn dwi_measure(input: &str) -> IResult<&str, Measure> {
-    alt((
-        preceded(char('('), terminated(dwi_measure_n(16), char(')'))),
-        preceded(char('['), terminated(dwi_measure_n(24), char(']'))),
-        preceded(char('{'), terminated(dwi_measure_n(64), char('}'))),
-        preceded(char('`'), terminated(dwi_measure_n(192), char('\''))),
-        dwi_measure_n(8),
-    ))(input)
+fn dwi_measure<'a>(partial: bool, panels: Panels) -> impl Fn(&'a str) -> IResult<&'a str, Measure> {
+    move |input| {
+        alt((
+            preceded(
+                char('('),
+                terminated(dwi_measure_n(16, partial, panels), char(')')),
+            ),
+            preceded(
+                char('['),
+                terminated(dwi_measure_n(24, partial, panels), char(']')),
+            ),
+            preceded(
+                char('{'),
+                terminated(dwi_measure_n(64, partial, panels), char('}')),
+            ),
+            preceded(
+                char('`'),
+                terminated(dwi_measure_n(192, partial, panels), char('\'')),
+            ),
+            dwi_measure_n(8, partial, panels),
+        ))(input)
+    }
 }
 
-fn dwi_chart(input: &str) -> IResult<&str, Vec<Measure>> {
-    many0(preceded(multispace0, dwi_measure))(input)
+fn dwi_chart<'a>(panels: Panels) -> impl Fn(&'a str) -> IResult<&'a str, Vec<Measure>> {
+    move |input| {
+        map(
+            pair(
+                many0(preceded(multispace0, dwi_measure(false, panels))),
+                opt(preceded(multispace0, dwi_measure(true, panels))),
+            ),
+            |(mut measures, last)| {
+                measures.extend(last);
+                measures
+            },
+        )(input)
+    }
 }
 
-fn dwi_chord(input: &str) -> IResult<&str, NoteRow> {
-    terminated(
-        preceded(
-            char('<'),
-            fold_many0(dwi_noterow, vec![], |mut acc, item| {
-                if !item.is_empty() {
-                    acc.push(item);
-                }
-                acc
-            }),
+//Doubles and couples give each pad its own pattern, the second pad's columns come after the first's
+fn dwi_two_pad_chart(input: &str) -> IResult<&str, Vec<Measure>> {
+    map(
+        separated_pair(
+            dwi_chart(four_panel_columns),
+            ws_trimmed(char(':')),
+            dwi_chart(four_panel_columns),
         ),
-        char('>'),
+        |(first, second)| merge_pads(first, second, 4),
     )(input)
-    .map(|(input, output)| {
-        let mut collected: Vec<Note> = output.into_iter().flatten().collect();
-        collected.sort_by(|x, y| x.column.cmp(&y.column));
-        collected.dedup_by(|x, y| x.column == y.column);
-        (input, collected)
-    })
 }
 
-pub fn parse(input: &str) -> Result<NoteData, Err<(&str, ErrorKind)>> {
-    notedata(input).map(|notedata| notedata.1)
+fn merge_pads(first: Vec<Measure>, second: Vec<Measure>, pad_width: usize) -> Vec<Measure> {
+    let length = first.len().max(second.len());
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    (0..length)
+        .map(|_| {
+            let mut rows: BTreeMap<Fraction, NoteRow> = BTreeMap::new();
+            for (row, time) in first.next().unwrap_or_default() {
+                rows.entry(time).or_default().extend(row);
+            }
+            for (row, time) in second.next().unwrap_or_default() {
+                rows.entry(time)
+                    .or_default()
+                    .extend(row.into_iter().map(|note| Note {
+                        column: note.column + pad_width,
+                        ..note
+                    }));
+            }
+            rows.into_iter().map(|(time, row)| (row, time)).collect()
+        })
+        .collect()
 }
 
-fn notedata(input: &str) -> IResult<&str, NoteData> {
-    let mut input = input;
-    let mut nd = NoteData::new();
+fn dwi_chart_info<'a>(steps_type: &'a str) -> impl Fn(&'a str) -> IResult<&'a str, ChartInfo> {
+    move |input| {
+        map(
+            separated_pair(
+                ws_trimmed(take_until(":")),
+                char(':'),
+                terminated(ws_trimmed(map_res(digit1, str::parse)), char(':')),
+            ),
+            |(label, meter)| ChartInfo {
+                steps_type: steps_type.to_owned(),
+                description: None,
+                difficulty: into_difficulty(label.trim()),
+                meter: Some(meter),
+                radar_values: vec![],
+                chart_name: None,
+                credit: None,
+                structure: None,
+            },
+        )(input)
+    }
+}
+
+fn dwi_chord<'a>(panels: Panels) -> impl Fn(&'a str) -> IResult<&'a str, NoteRow> {
+    move |input| {
+        terminated(
+            preceded(
+                char('<'),
+                fold_many0(dwi_noterow(panels), vec![], |mut acc, item| {
+                    if !item.is_empty() {
+                        acc.push(item);
+                    }
+                    acc
+                }),
+            ),
+            char('>'),
+        )(input)
+        .map(|(input, output)| {
+            let mut collected: Vec<Note> = output.into_iter().flatten().collect();
+            collected.sort_by(|x, y| x.column.cmp(&y.column));
+            collected.dedup_by(|x, y| x.column == y.column);
+            (input, collected)
+        })
+    }
+}
+
+//Anything left after the chart is a note or measure that couldn't be read, lenient parsing keeps
+//the chart up to there
+fn dwi_steps<'a>(
+    value: &'a str,
+    steps_type: &'a str,
+    chart: impl Fn(&'a str) -> IResult<&'a str, Vec<Measure>>,
+    panels: Panels,
+    warnings: &mut Warnings<'a>,
+) -> Result<(ChartInfo, Vec<Measure>), ValueError<'a>> {
+    let (rest, steps) = tuple((terminated(dwi_chart_info(steps_type), multispace0), chart))(value)?;
+    let rest = rest.trim_start();
+    let problem = match rest.chars().next() {
+        None => return Ok(steps),
+        Some(c) if panels(c).is_some() || "([{`<!".contains(c) => Problem::Syntax,
+        Some(_) => Problem::UnknownNote,
+    };
+    warnings.recover(ValueError::at(problem, rest))?;
+    Ok(steps)
+}
 
-    while let Ok((output, (tag, value))) = preceded(take_until("#"), stepmania_tag)(input) {
-        input = output;
+pub fn parse(
+    input: &str,
+    options: ParseOptions,
+) -> Result<(NoteData, Vec<ParseError>), ParseError> {
+    let mut nd = NoteData::new();
+    let mut seen = HashSet::new();
 
+    let warnings = for_each_tag(input, options, |tag, value, warnings| {
         if !value.trim().is_empty() {
+            //There's a tag for each chart, so only the others can be duplicates
+            if !matches!(tag, "SINGLE" | "DOUBLE" | "COUPLE" | "SOLO") {
+                warnings.duplicate(&mut seen, tag, value);
+            }
             match tag {
                 "TITLE" => nd.meta.title = Some(value.to_owned()),
                 "ARTIST" => nd.meta.artist = Some(value.to_owned()),
                 "GENRE" => nd.meta.genre = Some(value.to_owned()),
                 "CDTITLE" => nd.meta.cd_title = Some(value.to_owned()),
                 "FILE" => nd.meta.music_path = Some(value.to_owned()),
-                "GAP" => nd.structure.offset = Some(ws_trimmed(double)(value)?.1 / 1000.0),
+                "GAP" => nd.structure.offset = Some(number(value)? / 1000.0),
                 "BPM" => {
-                    let beat_pair = BeatPair::at_start(ws_trimmed(double)(value)?.1);
+                    let beat_pair = BeatPair::at_start(number(value)?);
                     if let Some(bpm) = nd.structure.bpms.get_mut(0) {
                         *bpm = beat_pair
                     } else {
@@ -170,31 +315,51 @@ fn notedata(input: &str) -> IResult<&str, NoteData> {
                     }
                     nd.structure
                         .bpms
-                        .append(&mut ws_trimmed(comma_separated(beat_pair(double, 16.0)))(value)?.1)
+                        .append(&mut warnings.pairs(double, 16.0, value)?)
                 }
                 "FREEZE" => {
+                    //.dwi freezes are in milliseconds where .sm stops are in seconds
                     nd.structure.stops =
-                        Some(ws_trimmed(comma_separated(beat_pair(double, 16.0)))(value)?.1)
+                        Some(warnings.pairs(map(double, |freeze| freeze / 1000.0), 16.0, value)?)
                 }
-                "SAMPLESTART" => nd.meta.sample_start = Some(ws_trimmed(double)(value)?.1),
-                "SAMPLELENGTH" => nd.meta.sample_length = Some(ws_trimmed(double)(value)?.1),
+                "SAMPLESTART" => nd.meta.sample_start = Some(number(value)?),
+                "SAMPLELENGTH" => nd.meta.sample_length = Some(number(value)?),
                 "DISPLAYBPM" => nd.meta.display_bpm = Some(ws_trimmed(display_bpm_dwi)(value)?.1),
-                "SINGLE" => nd.charts.push(
-                    preceded(
-                        terminated(
-                            count(terminated(take_until(":"), char(':')), 2),
-                            multispace0,
-                        ),
-                        dwi_chart,
-                    )(value)?
-                    .1,
-                ),
+                "SINGLE" => nd.charts.push(dwi_steps(
+                    value,
+                    "dance-single",
+                    dwi_chart(four_panel_columns),
+                    four_panel_columns,
+                    warnings,
+                )?),
+                "SOLO" => nd.charts.push(dwi_steps(
+                    value,
+                    "dance-solo",
+                    dwi_chart(six_panel_columns),
+                    six_panel_columns,
+                    warnings,
+                )?),
+                "DOUBLE" => nd.charts.push(dwi_steps(
+                    value,
+                    "dance-double",
+                    dwi_two_pad_chart,
+                    four_panel_columns,
+                    warnings,
+                )?),
+                "COUPLE" => nd.charts.push(dwi_steps(
+                    value,
+                    "dance-couple",
+                    dwi_two_pad_chart,
+                    four_panel_columns,
+                    warnings,
+                )?),
                 _ => {}
             }
         }
-    }
-    let mut active_holds = [false; 4];
-    for chart in &mut nd.charts {
+        Ok(())
+    })?;
+    for (info, chart) in &mut nd.charts {
+        let mut active_holds = vec![false; info.columns(chart)];
         for measure in chart {
             for (row, _time) in measure {
                 for note in row {
@@ -212,18 +377,18 @@ fn notedata(input: &str) -> IResult<&str, NoteData> {
             }
         }
     }
-    Ok((input, nd))
+    Ok((nd, warnings))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{ChartMetadata, StructureData};
+    use crate::{ChartMetadata, Difficulty, StructureData};
 
     #[test]
     fn parse_notedata() {
         assert_eq!(
-            notedata(
+            parse(
                 "content that is
 
         #TITLE:bar1;
@@ -239,42 +404,65 @@ mod tests {
         #CHANGEBPM:23.4=56.7,256=128;
         #SINGLE:SMANIC:17:
         00004008
-        (<42>000100060000000);"
-            ),
-            Ok((
-                "",
-                NoteData {
-                    meta: ChartMetadata {
-                        title: Some("bar1".to_owned()),
-                        subtitle: None,
-                        artist: Some("bar3".to_owned()),
-                        title_translit: None,
-                        subtitle_translit: None,
-                        artist_translit: None,
-                        genre: Some("bar4".to_owned()),
+        (<42>000100060000000);",
+                ParseOptions::default()
+            )
+            .unwrap()
+            .0,
+            NoteData {
+                meta: ChartMetadata {
+                    title: Some("bar1".to_owned()),
+                    subtitle: None,
+                    artist: Some("bar3".to_owned()),
+                    title_translit: None,
+                    subtitle_translit: None,
+                    artist_translit: None,
+                    genre: Some("bar4".to_owned()),
+                    credit: None,
+                    banner_path: None,
+                    background_path: None,
+                    lyrics_path: None,
+                    cd_title: Some("bar5".to_owned()),
+                    music_path: Some("bar6.mp3".to_owned()),
+                    sample_start: None,
+                    sample_length: None,
+                    display_bpm: Some(DisplayBpm::Range(100.0, 200.)),
+                    background_changes: None,
+                    foreground_changes: None,
+                    attacks: None,
+                    keysounds: vec![],
+                    selectable: None,
+                },
+                structure: StructureData {
+                    bpms: vec![
+                        BeatPair::from_pair(0.0, 123.4).unwrap(),
+                        BeatPair::from_pair(23.4 / 16.0, 56.7).unwrap(),
+                        BeatPair::from_pair(256.0 / 16.0, 128.0).unwrap()
+                    ],
+                    stops: None,
+                    offset: None,
+                    delays: None,
+                    warps: None,
+                    time_signatures: None,
+                    tick_counts: None,
+                    combos: None,
+                    speeds: None,
+                    scrolls: None,
+                    fakes: None,
+                    labels: None,
+                },
+                charts: vec![(
+                    ChartInfo {
+                        steps_type: "dance-single".to_owned(),
+                        description: None,
+                        difficulty: Difficulty::Edit,
+                        meter: Some(17),
+                        radar_values: vec![],
+                        chart_name: None,
                         credit: None,
-                        banner_path: None,
-                        background_path: None,
-                        lyrics_path: None,
-                        cd_title: Some("bar5".to_owned()),
-                        music_path: Some("bar6.mp3".to_owned()),
-                        sample_start: None,
-                        sample_length: None,
-                        display_bpm: Some(DisplayBpm::Range(100.0, 200.)),
-                        background_changes: None,
-                        foreground_changes: None,
-                        selectable: None,
-                    },
-                    structure: StructureData {
-                        bpms: vec![
-                            BeatPair::from_pair(0.0, 123.4).unwrap(),
-                            BeatPair::from_pair(23.4 / 16.0, 56.7).unwrap(),
-                            BeatPair::from_pair(256.0 / 16.0, 128.0).unwrap()
-                        ],
-                        stops: None,
-                        offset: None,
+                        structure: None,
                     },
-                    charts: vec![vec![
+                    vec![
                         vec![
                             (vec![Note::new(NoteType::Tap, 0)], Fraction::new(1, 2)),
                             (vec![Note::new(NoteType::Tap, 2)], Fraction::new(7, 8)),
@@ -290,11 +478,142 @@ mod tests {
                             ),
                             (vec![Note::new(NoteType::Tap, 3)], Fraction::new(1, 2))
                         ]
-                    ]],
-                }
-            ))
+                    ]
+                )],
+            }
+        );
+    }
+    #[test]
+    fn parse_styles() {
+        let charts = parse(
+            "#BPM:120;
+            #DOUBLE:MANIAC:12:
+            40002!2000
+            00020000:
+            (0000000000000006);
+            #COUPLE:BASIC:3:
+            00000000:
+            8;
+            #SOLO:ANOTHER:6:
+            C0D0<E6>000;",
+            ParseOptions::default(),
+        )
+        .unwrap()
+        .0
+        .charts;
+        assert_eq!(
+            charts
+                .iter()
+                .map(|(info, _)| (info.steps_type.as_str(), info.difficulty, info.meter))
+                .collect::<Vec<_>>(),
+            vec![
+                ("dance-double", Difficulty::Hard, Some(12)),
+                ("dance-couple", Difficulty::Easy, Some(3)),
+                ("dance-solo", Difficulty::Medium, Some(6)),
+            ]
+        );
+        assert_eq!(
+            charts[0].1,
+            vec![
+                vec![
+                    (vec![Note::new(NoteType::Tap, 0)], Fraction::new(0, 1)),
+                    (vec![Note::new(NoteType::Hold, 1)], Fraction::new(1, 2)),
+                    (vec![Note::new(NoteType::Tap, 7)], Fraction::new(15, 16))
+                ],
+                vec![(vec![Note::new(NoteType::HoldEnd, 1)], Fraction::new(3, 8))]
+            ]
+        );
+        //The couple chart's second pad has a partial measure, which is padded out
+        assert_eq!(
+            charts[1].1,
+            vec![vec![(
+                vec![Note::new(NoteType::Tap, 6)],
+                Fraction::new(0, 1)
+            )]]
         );
+        assert_eq!(
+            charts[2].1,
+            vec![vec![
+                (vec![Note::new(NoteType::Tap, 1)], Fraction::new(0, 1)),
+                (vec![Note::new(NoteType::Tap, 4)], Fraction::new(1, 4)),
+                (
+                    vec![
+                        Note::new(NoteType::Tap, 0),
+                        Note::new(NoteType::Tap, 1),
+                        Note::new(NoteType::Tap, 5)
+                    ],
+                    Fraction::new(1, 2)
+                )
+            ]]
+        );
+        assert!(dwi_chord(four_panel_columns)("<C>").is_err());
     }
+
+    #[test]
+    fn chart_remainder() {
+        let error = parse(
+            "#SINGLE:BASIC:3:\n00000000\n0X000000;",
+            ParseOptions::default(),
+        )
+        .unwrap_err();
+        match error {
+            ParseError::UnknownNote(location) => {
+                assert_eq!(
+                    (location.tag.as_str(), location.line, location.column),
+                    ("SINGLE", 3, 2)
+                )
+            }
+            error => panic!("unexpected error {}", error),
+        }
+        assert!(matches!(
+            parse("#SINGLE:BASIC:3:\n(0000;", ParseOptions::default()),
+            Err(ParseError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn chart_remainder_leniently() {
+        let lenient = ParseOptions {
+            lenient: true,
+            ..ParseOptions::default()
+        };
+        let (nd, warnings) = NoteData::from_dwi_reader_with_options(
+            "#SINGLE:BASIC:3:\n00000000\n4X000000;".as_bytes(),
+            lenient,
+        )
+        .unwrap();
+        assert_eq!(
+            nd.charts[0].1,
+            vec![
+                vec![],
+                vec![(vec![Note::new(NoteType::Tap, 0)], Fraction::new(0, 1))]
+            ]
+        );
+        let summaries: Vec<_> = warnings
+            .iter()
+            .map(|warning| warning.to_string().lines().next().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            summaries,
+            vec!["unknown note in SINGLE at line 3, column 2"]
+        );
+    }
+
+    #[test]
+    fn parse_freeze() {
+        assert_eq!(
+            parse("#BPM:120;#FREEZE:8=500,24=250;", ParseOptions::default())
+                .unwrap()
+                .0
+                .structure
+                .stops,
+            Some(vec![
+                BeatPair::from_pair(0.5, 0.5).unwrap(),
+                BeatPair::from_pair(1.5, 0.25).unwrap()
+            ])
+        );
+    }
+
     #[test]
     fn parse_measure() {
         assert_eq!(
@@ -316,7 +635,7 @@ mod tests {
                     (vec![Note::new(NoteType::Tap, 2)], Fraction::new(3, 4)),
                 ]
             )),
-            dwi_measure("100<49>5080\n")
+            dwi_measure(false, four_panel_columns)("100<49>5080\n")
         );
         assert_eq!(
             Ok((
@@ -336,7 +655,7 @@ mod tests {
                     ),
                 ]
             )),
-            dwi_measure("(0000000<94>005000B0)\n")
+            dwi_measure(false, four_panel_columns)("(0000000<94>005000B0)\n")
         );
         assert_eq!(
             Ok((
@@ -346,21 +665,23 @@ mod tests {
                     Fraction::new(1, 24)
                 ),]
             )),
-            dwi_measure("[01!20000000000000000000000]\n")
+            dwi_measure(false, four_panel_columns)("[01!20000000000000000000000]\n")
         );
         assert_eq!(
             Ok((
                 "\n",
                 vec![(vec![Note::new(NoteType::Tap, 1)], Fraction::new(1, 64))]
             )),
-            dwi_measure("{0200000000000000000000000000000000000000000000000000000000000000}\n")
+            dwi_measure(false, four_panel_columns)(
+                "{0200000000000000000000000000000000000000000000000000000000000000}\n"
+            )
         );
         assert_eq!(
             Ok((
                 "\n",
                 vec![(vec![Note::new(NoteType::Tap, 1)], Fraction::new(1, 192))]
             )),
-            dwi_measure(
+            dwi_measure(false, four_panel_columns)(
                 "`02000000000000000000000000000000000000000000000000000000000000000\
             00000000000000000000000000000000000000000000000000000000000000000000000000000000000000\
             00000000000000000000000000000000000000000\'\n"
@@ -368,9 +689,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_chart_info() {
+        assert_eq!(
+            dwi_chart_info("dance-single")("ANOTHER:7:\n0000"),
+            Ok((
+                "\n0000",
+                ChartInfo {
+                    steps_type: "dance-single".to_owned(),
+                    description: None,
+                    difficulty: Difficulty::Medium,
+                    meter: Some(7),
+                    radar_values: vec![],
+                    chart_name: None,
+                    credit: None,
+                    structure: None,
+                }
+            ))
+        );
+        assert!(dwi_chart_info("dance-single")("MANIAC:x:0000").is_err());
+    }
+
     #[test]
     fn parse_chord() {
-        assert_eq!(Ok(("", vec![])), dwi_chord("<>"));
+        assert_eq!(Ok(("", vec![])), dwi_chord(four_panel_columns)("<>"));
         let hand_example = Ok((
             "",
             vec![
@@ -379,12 +721,12 @@ mod tests {
                 Note::new(NoteType::Tap, 3),
             ],
         ));
-        assert_eq!(hand_example, dwi_chord("<16>"));
-        assert_eq!(hand_example, dwi_chord("<61>"));
-        assert_eq!(hand_example, dwi_chord("<34>"));
-        assert_eq!(hand_example, dwi_chord("<B2>"));
-        assert_eq!(hand_example, dwi_chord("<31>"));
-        assert_eq!(hand_example, dwi_chord("<426>"));
+        assert_eq!(hand_example, dwi_chord(four_panel_columns)("<16>"));
+        assert_eq!(hand_example, dwi_chord(four_panel_columns)("<61>"));
+        assert_eq!(hand_example, dwi_chord(four_panel_columns)("<34>"));
+        assert_eq!(hand_example, dwi_chord(four_panel_columns)("<B2>"));
+        assert_eq!(hand_example, dwi_chord(four_panel_columns)("<31>"));
+        assert_eq!(hand_example, dwi_chord(four_panel_columns)("<426>"));
         let quad_example = Ok((
             "",
             vec![
@@ -394,13 +736,13 @@ mod tests {
                 Note::new(NoteType::Tap, 3),
             ],
         ));
-        assert_eq!(quad_example, dwi_chord("<BA>"));
-        assert_eq!(quad_example, dwi_chord("<AB>"));
-        assert_eq!(quad_example, dwi_chord("<91>"));
-        assert_eq!(quad_example, dwi_chord("<816>"));
-        assert_eq!(quad_example, dwi_chord("<6428>"));
-        assert_eq!(quad_example, dwi_chord("<97A>"));
-        assert_eq!(quad_example, dwi_chord("<B50A>"));
-        assert!(dwi_chord("246").is_err());
+        assert_eq!(quad_example, dwi_chord(four_panel_columns)("<BA>"));
+        assert_eq!(quad_example, dwi_chord(four_panel_columns)("<AB>"));
+        assert_eq!(quad_example, dwi_chord(four_panel_columns)("<91>"));
+        assert_eq!(quad_example, dwi_chord(four_panel_columns)("<816>"));
+        assert_eq!(quad_example, dwi_chord(four_panel_columns)("<6428>"));
+        assert_eq!(quad_example, dwi_chord(four_panel_columns)("<97A>"));
+        assert_eq!(quad_example, dwi_chord(four_panel_columns)("<B50A>"));
+        assert!(dwi_chord(four_panel_columns)("246").is_err());
     }
 }
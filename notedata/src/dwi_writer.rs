@@ -0,0 +1,466 @@
+use crate::{
+    dwi_parser::{four_panel_columns, six_panel_columns, Panels},
+    sm_writer::{beat_to_float, write_tag},
+    BeatPair, ChartInfo, Difficulty, DisplayBpm, Fraction, Measure, Note, NoteData, NoteType,
+    StructureData, WriteError,
+};
+use std::convert::TryFrom;
+
+//Rows per measure for each kind of DWI measure, along with the brackets that mark it
+const MEASURE_BRACKETS: [(i32, &str, &str); 5] = [
+    (8, "", ""),
+    (16, "(", ")"),
+    (24, "[", "]"),
+    (64, "{", "}"),
+    (192, "`", "'"),
+];
+
+fn unrepresentable(reason: impl Into<String>) -> WriteError {
+    WriteError::Unrepresentable(reason.into())
+}
+
+pub fn write_dwi(data: &NoteData) -> Result<String, WriteError> {
+    let mut output = metadata_string(data);
+    output.push_str(&structure_string(&data.structure)?);
+    for (info, chart) in &data.charts {
+        output.push_str(&chart_string(info, chart)?);
+    }
+    Ok(output)
+}
+
+fn metadata_string(data: &NoteData) -> String {
+    let meta = &data.meta;
+    let mut output = String::new();
+    let mut string_tag = |tag_name: &str, from_location: &Option<String>| {
+        if let Some(tag) = from_location {
+            output.push_str(&write_tag(tag_name, tag))
+        }
+    };
+    string_tag("TITLE", &meta.title);
+    string_tag("ARTIST", &meta.artist);
+    string_tag("GENRE", &meta.genre);
+    string_tag("CDTITLE", &meta.cd_title);
+    string_tag("FILE", &meta.music_path);
+    let mut number_tag = |tag_name: &str, from_location: &Option<f64>| {
+        if let Some(tag) = from_location {
+            output.push_str(&write_tag(tag_name, &tag.to_string()))
+        }
+    };
+    number_tag("SAMPLESTART", &meta.sample_start);
+    number_tag("SAMPLELENGTH", &meta.sample_length);
+    if let Some(tag) = &meta.display_bpm {
+        output.push_str(&write_tag(
+            "DISPLAYBPM",
+            &match tag {
+                DisplayBpm::Static(value) => value.to_string(),
+                DisplayBpm::Range(lower, upper) => format!("{}..{}", lower, upper),
+                DisplayBpm::Random => "*".to_string(),
+            },
+        ))
+    }
+    output
+}
+
+fn has_segments<T>(segments: &Option<Vec<T>>) -> bool {
+    matches!(segments, Some(segments) if !segments.is_empty())
+}
+
+//.dwi positions are counted in sixteenth notes
+fn pair_tag<F>(list: &[BeatPair<f64>], value_string: F) -> String
+where
+    F: Fn(f64) -> String,
+{
+    list.iter()
+        .map(|pair| format!("{}={}", beat_to_float(pair) * 4.0, value_string(pair.value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn structure_string(structure: &StructureData) -> Result<String, WriteError> {
+    for &(name, used) in &[
+        ("delays", has_segments(&structure.delays)),
+        ("warps", has_segments(&structure.warps)),
+        ("time signatures", has_segments(&structure.time_signatures)),
+        ("tick counts", has_segments(&structure.tick_counts)),
+        ("combos", has_segments(&structure.combos)),
+        ("speeds", has_segments(&structure.speeds)),
+        ("scrolls", has_segments(&structure.scrolls)),
+        ("fakes", has_segments(&structure.fakes)),
+        ("labels", has_segments(&structure.labels)),
+    ] {
+        if used {
+            return Err(unrepresentable(format!(".dwi files have no {}", name)));
+        }
+    }
+    let mut output = String::new();
+    if let Some(offset) = structure.offset {
+        output.push_str(&write_tag("GAP", &(offset * 1000.0).to_string()));
+    }
+    if let Some((first_bpm, bpm_changes)) = structure.bpms.split_first() {
+        if first_bpm.beat != 0 || *first_bpm.sub_beat.numer() != 0 {
+            return Err(unrepresentable(
+                "the first bpm must be at the start of the song",
+            ));
+        }
+        output.push_str(&write_tag("BPM", &first_bpm.value.to_string()));
+        if !bpm_changes.is_empty() {
+            output.push_str(&write_tag(
+                "CHANGEBPM",
+                &pair_tag(bpm_changes, |bpm| bpm.to_string()),
+            ));
+        }
+    }
+    if has_segments(&structure.stops) {
+        //.dwi freezes are in milliseconds where .sm stops are in seconds
+        output.push_str(&write_tag(
+            "FREEZE",
+            &pair_tag(structure.stops.as_deref().unwrap_or_default(), |stop| {
+                (stop * 1000.0).to_string()
+            }),
+        ));
+    }
+    Ok(output)
+}
+
+fn difficulty_label(difficulty: Difficulty) -> Result<&'static str, WriteError> {
+    Ok(match difficulty {
+        Difficulty::Beginner => "BEGINNER",
+        Difficulty::Easy => "BASIC",
+        Difficulty::Medium => "ANOTHER",
+        Difficulty::Hard => "MANIAC",
+        Difficulty::Challenge => "SMANIAC",
+        Difficulty::Edit => return Err(unrepresentable(".dwi files have no edit charts")),
+    })
+}
+
+fn chart_string(info: &ChartInfo, chart: &[Measure]) -> Result<String, WriteError> {
+    if info.structure.is_some() {
+        return Err(unrepresentable(".dwi charts can't have their own timing"));
+    }
+    let (style, panels, width, pads): (_, Panels, _, _) = match info.steps_type.as_str() {
+        "dance-single" => ("SINGLE", four_panel_columns, 4, 1),
+        "dance-solo" => ("SOLO", six_panel_columns, 6, 1),
+        "dance-double" => ("DOUBLE", four_panel_columns, 4, 2),
+        "dance-couple" => ("COUPLE", four_panel_columns, 4, 2),
+        other => {
+            return Err(unrepresentable(format!(
+                ".dwi files have no {} charts",
+                other
+            )))
+        }
+    };
+    if let Some(note) = chart
+        .iter()
+        .flatten()
+        .flat_map(|(row, _)| row)
+        .find(|note| note.column >= width * pads)
+    {
+        return Err(unrepresentable(format!(
+            "column {} is outside of the chart",
+            note.column
+        )));
+    }
+    let patterns = (0..pads)
+        .map(|pad| pad_string(&split_pad(chart, pad * width, width), panels, width))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(write_tag(
+        style,
+        &format!(
+            "{}:{}:\n{}",
+            difficulty_label(info.difficulty)?,
+            info.meter.unwrap_or(1),
+            patterns.join(":\n")
+        ),
+    ))
+}
+
+//Takes the notes from one pad of a chart, moving them to start at column 0
+fn split_pad(chart: &[Measure], first_column: usize, width: usize) -> Vec<Measure> {
+    chart
+        .iter()
+        .map(|measure| {
+            measure
+                .iter()
+                .map(|(row, time)| {
+                    (
+                        row.iter()
+                            .filter(|note| {
+                                (first_column..first_column + width).contains(&note.column)
+                            })
+                            .map(|note| Note::new(note.note_type, note.column - first_column))
+                            .collect::<Vec<_>>(),
+                        *time,
+                    )
+                })
+                .filter(|(row, _)| !row.is_empty())
+                .collect()
+        })
+        .collect()
+}
+
+fn pad_string(chart: &[Measure], panels: Panels, width: usize) -> Result<String, WriteError> {
+    let mut active_holds = vec![false; width];
+    let measures = chart
+        .iter()
+        .map(|measure| measure_string(measure, panels, &mut active_holds))
+        .collect::<Result<Vec<_>, _>>()?;
+    if active_holds.contains(&true) {
+        return Err(unrepresentable("a hold never ends"));
+    }
+    Ok(measures.join("\n"))
+}
+
+fn measure_string(
+    measure: &Measure,
+    panels: Panels,
+    active_holds: &mut [bool],
+) -> Result<String, WriteError> {
+    let &(rows, open, close) = MEASURE_BRACKETS
+        .iter()
+        .find(|(rows, _, _)| {
+            measure
+                .iter()
+                .all(|(_, time)| (time * Fraction::from(*rows)).is_integer())
+        })
+        .ok_or_else(|| unrepresentable("a row isn't on a 192nd note grid"))?;
+    let mut cells = vec!["0".to_owned(); rows as usize];
+    for (row, time) in measure {
+        let cell = usize::try_from((time * Fraction::from(rows)).to_integer())
+            .ok()
+            .and_then(|index| cells.get_mut(index))
+            .ok_or_else(|| unrepresentable("a row is outside of its measure"))?;
+        *cell = row_string(row, panels, active_holds)?;
+    }
+    Ok(format!("{}{}{}", open, cells.concat(), close))
+}
+
+fn row_string(
+    row: &[Note],
+    panels: Panels,
+    active_holds: &mut [bool],
+) -> Result<String, WriteError> {
+    let mut columns = vec![];
+    let mut holds = vec![];
+    for note in row {
+        let active = active_holds.get_mut(note.column).ok_or_else(|| {
+            unrepresentable(format!("column {} is outside of the chart", note.column))
+        })?;
+        //Hold ends are written as taps, the parser turns them back into hold ends
+        match (note.note_type, *active) {
+            (NoteType::Tap, false) => {}
+            (NoteType::Hold, false) => {
+                *active = true;
+                holds.push(note.column);
+            }
+            (NoteType::HoldEnd, true) => *active = false,
+            (NoteType::Tap, true) | (NoteType::Hold, true) => {
+                return Err(unrepresentable("a note is inside of a hold"))
+            }
+            (NoteType::HoldEnd, false) => return Err(unrepresentable("a hold end has no hold")),
+            //.dwi files have no keysounds, so there is nothing for a background one to play
+            (NoteType::AutoKeysound, _) => continue,
+            (note_type, _) => {
+                return Err(unrepresentable(format!(
+                    ".dwi files have no {:?} notes",
+                    note_type
+                )))
+            }
+        }
+        columns.push(note.column);
+    }
+    columns.sort_unstable();
+    columns.dedup();
+    Ok(match columns.len() {
+        0 => "0".to_owned(),
+        1 | 2 => noterow_string(&columns, &holds, panels),
+        _ => format!(
+            "<{}>",
+            columns
+                .iter()
+                .map(|&column| noterow_string(&[column], &holds, panels))
+                .collect::<String>()
+        ),
+    })
+}
+
+fn noterow_string(columns: &[usize], holds: &[usize], panels: Panels) -> String {
+    let mut output = columns_to_char(columns, panels).to_string();
+    let mut holds: Vec<_> = holds
+        .iter()
+        .copied()
+        .filter(|hold| columns.contains(hold))
+        .collect();
+    holds.sort_unstable();
+    if !holds.is_empty() {
+        output.push('!');
+        output.push(columns_to_char(&holds, panels));
+    }
+    output
+}
+
+//Every single panel and pair of panels has a character, so this only misses on bad input
+fn columns_to_char(columns: &[usize], panels: Panels) -> char {
+    "0123456789ABCDEFGHIJKLM"
+        .chars()
+        .find(|&step| panels(step).as_deref() == Some(columns))
+        .unwrap_or('0')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dwi_parser, ParseOptions};
+
+    fn dance_single(difficulty: Difficulty, meter: u32) -> ChartInfo {
+        ChartInfo {
+            difficulty,
+            meter: Some(meter),
+            ..ChartInfo::default()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut data = NoteData::new();
+        data.meta.title = Some("foo".to_owned());
+        data.meta.display_bpm = Some(DisplayBpm::Range(100.0, 200.0));
+        data.structure.offset = Some(0.25);
+        data.structure.bpms = vec![
+            BeatPair::from_pair(0.0, 120.0).unwrap(),
+            BeatPair::from_pair(1.5, 240.0).unwrap(),
+        ];
+        data.structure.stops = Some(vec![BeatPair::from_pair(0.25, 0.5).unwrap()]);
+        data.charts = vec![(
+            dance_single(Difficulty::Hard, 9),
+            vec![
+                vec![
+                    (vec![Note::new(NoteType::Tap, 0)], Fraction::new(0, 1)),
+                    (
+                        vec![Note::new(NoteType::Hold, 1), Note::new(NoteType::Tap, 2)],
+                        Fraction::new(1, 4),
+                    ),
+                ],
+                vec![(vec![Note::new(NoteType::HoldEnd, 1)], Fraction::new(1, 16))],
+                vec![(
+                    vec![
+                        Note::new(NoteType::Tap, 0),
+                        Note::new(NoteType::Hold, 2),
+                        Note::new(NoteType::Tap, 3),
+                    ],
+                    Fraction::new(1, 3),
+                )],
+                vec![(vec![Note::new(NoteType::HoldEnd, 2)], Fraction::new(1, 192))],
+                vec![],
+            ],
+        )];
+        let output = write_dwi(&data).unwrap();
+        assert_eq!(
+            dwi_parser::parse(&output, ParseOptions::default())
+                .unwrap()
+                .0,
+            data
+        );
+        assert!(output.ends_with(&format!(
+            "#SINGLE:MANIAC:9:\n40A!200000\n(0200000000000000)\n[00000000<48!86>000000000000000]\n`08{}'\n00000000;\n",
+            "0".repeat(190)
+        )));
+    }
+
+    #[test]
+    fn round_trip_styles() {
+        let mut data = NoteData::new();
+        data.structure.bpms = vec![BeatPair::from_pair(0.0, 150.0).unwrap()];
+        let chart = |steps_type: &str, difficulty, columns: &[usize]| {
+            (
+                ChartInfo {
+                    steps_type: steps_type.to_owned(),
+                    ..dance_single(difficulty, 4)
+                },
+                vec![
+                    vec![
+                        (
+                            columns
+                                .iter()
+                                .map(|&column| Note::new(NoteType::Tap, column))
+                                .collect(),
+                            Fraction::new(0, 1),
+                        ),
+                        (
+                            vec![Note::new(NoteType::Hold, columns[0])],
+                            Fraction::new(1, 2),
+                        ),
+                    ],
+                    vec![(
+                        vec![Note::new(NoteType::HoldEnd, columns[0])],
+                        Fraction::new(1, 16),
+                    )],
+                ],
+            )
+        };
+        data.charts = vec![
+            chart("dance-solo", Difficulty::Easy, &[1, 3, 4]),
+            chart("dance-double", Difficulty::Medium, &[2, 5, 7]),
+            chart("dance-couple", Difficulty::Challenge, &[6]),
+        ];
+        let output = write_dwi(&data).unwrap();
+        assert_eq!(
+            dwi_parser::parse(&output, ParseOptions::default())
+                .unwrap()
+                .0,
+            data
+        );
+        assert!(output.contains("#SOLO:BASIC:4:\n<C8D>000C!C000\n(0C00000000000000);"));
+        assert!(output
+            .contains("#DOUBLE:ANOTHER:4:\n80008!8000\n(0800000000000000):\n30000000\n00000000;"));
+        assert!(output
+            .contains("#COUPLE:SMANIAC:4:\n00000000\n00000000:\n80008!8000\n(0800000000000000);"));
+    }
+
+    #[test]
+    fn timing_tags() {
+        let structure = StructureData {
+            offset: Some(-0.5),
+            bpms: vec![
+                BeatPair::from_pair(0.0, 150.0).unwrap(),
+                BeatPair::from_pair(2.0, 75.0).unwrap(),
+            ],
+            stops: Some(vec![BeatPair::from_pair(1.25, 0.125).unwrap()]),
+            ..StructureData::default()
+        };
+        assert_eq!(
+            structure_string(&structure).unwrap(),
+            "#GAP:-500;\n#BPM:150;\n#CHANGEBPM:32=75;\n#FREEZE:20=125;\n"
+        );
+    }
+
+    #[test]
+    fn unrepresentable_charts() {
+        let single = |row: Vec<Note>, time: Fraction| {
+            let mut data = NoteData::new();
+            data.structure.bpms = vec![BeatPair::from_pair(0.0, 120.0).unwrap()];
+            data.charts = vec![(dance_single(Difficulty::Easy, 3), vec![vec![(row, time)]])];
+            data
+        };
+        for data in &[
+            single(vec![Note::new(NoteType::Mine, 0)], Fraction::new(0, 1)),
+            single(vec![Note::new(NoteType::Tap, 4)], Fraction::new(0, 1)),
+            single(vec![Note::new(NoteType::Tap, 0)], Fraction::new(1, 5)),
+            single(vec![Note::new(NoteType::Hold, 0)], Fraction::new(0, 1)),
+            single(vec![Note::new(NoteType::HoldEnd, 0)], Fraction::new(0, 1)),
+        ] {
+            assert!(matches!(
+                write_dwi(data),
+                Err(WriteError::Unrepresentable(_))
+            ));
+        }
+        let mut edit = single(vec![], Fraction::new(0, 1));
+        edit.charts[0].0.difficulty = Difficulty::Edit;
+        assert!(write_dwi(&edit).is_err());
+        let mut warped = single(vec![], Fraction::new(0, 1));
+        warped.structure.warps = Some(vec![BeatPair::from_pair(1.0, 1.0).unwrap()]);
+        assert!(write_dwi(&warped).is_err());
+        let mut pump = single(vec![], Fraction::new(0, 1));
+        pump.charts[0].0.steps_type = "pump-single".to_owned();
+        assert!(write_dwi(&pump).is_err());
+    }
+}
@@ -1,18 +1,109 @@
+#[cfg(feature = "rm-format")]
+use crate::RmError;
 use std::{error, fmt, io};
 
-type NomError<T = String> = nom::Err<(T, nom::error::ErrorKind)>;
+type NomError<'a> = nom::Err<(&'a str, nom::error::ErrorKind)>;
+
+//How much of the offending line to show on either side of an error
+const EXCERPT_WIDTH: usize = 40;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub tag: String,
+    pub line: usize,
+    pub column: usize,
+    excerpt: String,
+    caret: usize,
+}
+
+impl Location {
+    //position has to be a slice of input, anything else is treated as the end of the file
+    pub(crate) fn new(input: &str, tag: &str, position: &str) -> Self {
+        let offset = (position.as_ptr() as usize)
+            .checked_sub(input.as_ptr() as usize)
+            .filter(|&offset| input.is_char_boundary(offset))
+            .unwrap_or(input.len());
+        let line_start = input[..offset].rfind('\n').map_or(0, |index| index + 1);
+        let column = input[line_start..offset].chars().count();
+        let skipped = column.saturating_sub(EXCERPT_WIDTH);
+        let excerpt = input[line_start..]
+            .lines()
+            .next()
+            .unwrap_or("")
+            .chars()
+            .skip(skipped)
+            .take(EXCERPT_WIDTH * 2)
+            .map(|c| if c.is_whitespace() { ' ' } else { c })
+            .collect::<String>()
+            .trim_end()
+            .to_owned();
+        Self {
+            tag: tag.to_owned(),
+            line: input[..offset].matches('\n').count() + 1,
+            column: column + 1,
+            excerpt,
+            caret: column - skipped,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "in {} at line {}, column {}\n  | {}\n  | {:>caret$}",
+            self.tag,
+            self.line,
+            self.column,
+            self.excerpt,
+            "^",
+            caret = self.caret + 1
+        )
+    }
+}
 
 #[derive(Debug)]
 pub enum ParseError {
     Io(io::Error),
-    Parse(NomError),
+    BadNumber(Location),
+    UnknownNote(Location),
+    //A beat=value list, like #BPMS or #STOPS, that isn't made of pairs
+    MalformedPair(Location),
+    UnterminatedTag(Location),
+    //Only ever a warning, the last value of a repeated tag is the one that's kept
+    DuplicateTag(Location),
+    //Anything else that doesn't follow the format's syntax
+    Syntax(Location),
+    //The file is well formed but holds something the game can't play
+    Unsupported(String),
+    //The text isn't UTF-8 or any of the fallback encodings
+    Encoding,
+    #[cfg(feature = "serde-formats")]
+    Yaml(serde_yaml::Error),
+    #[cfg(feature = "serde-formats")]
+    Json(serde_json::Error),
+    #[cfg(feature = "rm-format")]
+    Rm(RmError),
 }
 
 impl error::Error for ParseError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Self::Io(error) => error.source(),
-            Self::Parse(error) => error.source(),
+            Self::BadNumber(_)
+            | Self::UnknownNote(_)
+            | Self::MalformedPair(_)
+            | Self::UnterminatedTag(_)
+            | Self::DuplicateTag(_)
+            | Self::Syntax(_)
+            | Self::Unsupported(_)
+            | Self::Encoding => None,
+            #[cfg(feature = "serde-formats")]
+            Self::Yaml(error) => error.source(),
+            #[cfg(feature = "serde-formats")]
+            Self::Json(error) => error.source(),
+            #[cfg(feature = "rm-format")]
+            Self::Rm(error) => error.source(),
         }
     }
 }
@@ -21,7 +112,20 @@ impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Self::Io(error) => error.fmt(f),
-            Self::Parse(error) => error.fmt(f),
+            Self::BadNumber(location) => write!(f, "bad number {}", location),
+            Self::UnknownNote(location) => write!(f, "unknown note {}", location),
+            Self::MalformedPair(location) => write!(f, "malformed beat=value pair {}", location),
+            Self::UnterminatedTag(location) => write!(f, "unterminated tag {}", location),
+            Self::DuplicateTag(location) => write!(f, "duplicate tag {}", location),
+            Self::Syntax(location) => write!(f, "syntax error {}", location),
+            Self::Unsupported(reason) => write!(f, "unsupported chart: {}", reason),
+            Self::Encoding => write!(f, "text is in an unknown encoding"),
+            #[cfg(feature = "serde-formats")]
+            Self::Yaml(error) => error.fmt(f),
+            #[cfg(feature = "serde-formats")]
+            Self::Json(error) => error.fmt(f),
+            #[cfg(feature = "rm-format")]
+            Self::Rm(error) => error.fmt(f),
         }
     }
 }
@@ -32,8 +136,129 @@ impl From<io::Error> for ParseError {
     }
 }
 
-impl From<NomError<&str>> for ParseError {
-    fn from(error: NomError<&str>) -> Self {
-        Self::Parse(error.to_owned())
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Problem {
+    BadNumber,
+    UnknownNote,
+    MalformedPair,
+    DuplicateTag,
+    Syntax,
+}
+
+//An error inside a single tag's value, which only becomes a ParseError once the tag is known
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ValueError<'a> {
+    problem: Problem,
+    position: &'a str,
+}
+
+impl<'a> ValueError<'a> {
+    #[allow(clippy::needless_pass_by_value)]
+    pub(crate) fn new(problem: Problem, error: NomError<'a>) -> Self {
+        Self {
+            problem,
+            position: match error {
+                nom::Err::Error((position, _)) | nom::Err::Failure((position, _)) => position,
+                nom::Err::Incomplete(_) => "",
+            },
+        }
+    }
+
+    pub(crate) fn at(problem: Problem, position: &'a str) -> Self {
+        Self { problem, position }
+    }
+
+    pub(crate) fn bad_number(error: NomError<'a>) -> Self {
+        Self::new(Problem::BadNumber, error)
+    }
+
+    pub(crate) fn unknown_note(error: NomError<'a>) -> Self {
+        Self::new(Problem::UnknownNote, error)
+    }
+
+    pub(crate) fn malformed_pair(error: NomError<'a>) -> Self {
+        Self::new(Problem::MalformedPair, error)
+    }
+
+    pub(crate) fn locate(self, input: &str, tag: &str) -> ParseError {
+        let location = Location::new(input, tag, self.position);
+        match self.problem {
+            Problem::BadNumber => ParseError::BadNumber(location),
+            Problem::UnknownNote => ParseError::UnknownNote(location),
+            Problem::MalformedPair => ParseError::MalformedPair(location),
+            Problem::DuplicateTag => ParseError::DuplicateTag(location),
+            Problem::Syntax => ParseError::Syntax(location),
+        }
+    }
+}
+
+impl<'a> From<NomError<'a>> for ValueError<'a> {
+    fn from(error: NomError<'a>) -> Self {
+        Self::new(Problem::Syntax, error)
+    }
+}
+
+#[cfg(feature = "serde-formats")]
+impl From<serde_yaml::Error> for ParseError {
+    fn from(error: serde_yaml::Error) -> Self {
+        Self::Yaml(error)
+    }
+}
+
+#[cfg(feature = "serde-formats")]
+impl From<serde_json::Error> for ParseError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Json(error)
+    }
+}
+
+#[cfg(feature = "rm-format")]
+impl From<RmError> for ParseError {
+    fn from(error: RmError) -> Self {
+        Self::Rm(error)
+    }
+}
+
+#[derive(Debug)]
+pub enum WriteError {
+    Io(io::Error),
+    #[cfg(feature = "rm-format")]
+    Rm(RmError),
+    //The data has no equivalent in the target format, so writing it would lose information
+    Unrepresentable(String),
+}
+
+impl error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(error) => error.source(),
+            #[cfg(feature = "rm-format")]
+            Self::Rm(error) => error.source(),
+            Self::Unrepresentable(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(error) => error.fmt(f),
+            #[cfg(feature = "rm-format")]
+            Self::Rm(error) => error.fmt(f),
+            Self::Unrepresentable(reason) => write!(f, "can't be represented: {}", reason),
+        }
+    }
+}
+
+impl From<io::Error> for WriteError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+#[cfg(feature = "rm-format")]
+impl From<RmError> for WriteError {
+    fn from(error: RmError) -> Self {
+        Self::Rm(error)
     }
 }
@@ -39,21 +39,47 @@
     clippy::used_underscore_binding
 )]
 
+mod autogen;
+mod bms_parser;
+mod chart_key;
+mod chart_notes;
 mod dwi_parser;
+mod dwi_writer;
+#[cfg(feature = "serde-formats")]
+mod mc_parser;
+mod osu_parser;
 mod parser_generic;
+#[cfg(feature = "serde-formats")]
+mod qua_parser;
+#[cfg(feature = "rm-format")]
+mod rm;
 mod sm_parser;
 mod sm_writer;
+mod ssc_parser;
+mod ssc_writer;
+mod stats;
+mod text;
+mod timed_notes;
 pub mod timingdata;
+pub mod transforms;
+mod validate;
 
-pub const NOTEFIELD_SIZE: usize = 4;
-
+pub use encoding_rs::{self, Encoding};
 pub use num_rational::Rational32 as Fraction;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
-use std::io;
+use std::{io, path::Path};
 
 mod error;
-pub use error::ParseError;
+pub use autogen::autogen;
+pub use chart_key::chart_key;
+pub use chart_notes::{chart_from_notes, ChartNotes, Hold, Notes, Rows};
+pub use error::{Location, ParseError, WriteError};
+#[cfg(feature = "rm-format")]
+pub use rm::{RmEncoding, RmError};
+pub use stats::{ChartStats, RadarValues};
+pub use transforms::Transform;
+pub use validate::Diagnostic;
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -73,6 +99,8 @@ pub enum NoteType {
     Lift,
     Fake,
     HoldEnd,
+    //Plays its keysound when the song reaches it, without being shown or hit
+    AutoKeysound,
 }
 
 impl Default for NoteType {
@@ -94,6 +122,9 @@ pub enum DisplayBpm {
 pub struct Note {
     pub note_type: NoteType,
     pub column: usize,
+    //An index into the song's keysounds, played when the note is hit
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub keysound: Option<usize>,
 }
 
 pub type NoteRow = Vec<Note>;
@@ -101,6 +132,34 @@ pub type Measure = Vec<(NoteRow, Fraction)>;
 pub type Chart = Vec<Measure>;
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Difficulty {
+    #[default]
+    Beginner,
+    Easy,
+    Medium,
+    Hard,
+    Challenge,
+    Edit,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChartInfo {
+    pub steps_type: String,
+    pub description: Option<String>,
+    pub difficulty: Difficulty,
+    pub meter: Option<u32>,
+    pub radar_values: Vec<f64>,
+    pub chart_name: Option<String>,
+    pub credit: Option<String>,
+    //only .ssc charts can carry their own timing, everything else uses the song's StructureData
+    pub structure: Option<StructureData>,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct ChartMetadata {
     pub title: Option<String>,
@@ -120,25 +179,131 @@ pub struct ChartMetadata {
     pub sample_length: Option<f64>,
     pub display_bpm: Option<DisplayBpm>,
     pub selectable: Option<String>,
-    //it is unclear how this is used in practice, may be better as Option<bool>
-    pub background_changes: Option<Vec<BeatPair<String>>>,
-    pub foreground_changes: Option<Vec<BeatPair<String>>>,
+    pub background_changes: Option<Vec<BeatPair<BackgroundChange>>>,
+    pub foreground_changes: Option<Vec<BeatPair<BackgroundChange>>>,
+    pub attacks: Option<Vec<Attack>>,
+    //The samples notes can play, from SM5 #KEYSOUNDS or BMS #WAVxx in the order of their ids
+    pub keysounds: Vec<String>,
 }
 
+//Everything but the file is optional in .sm files, StepMania fills in the rest from its theme
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackgroundChange {
+    pub file: String,
+    pub rate: f64,
+    pub crossfade: bool,
+    pub stretch_rewind: bool,
+    pub stretch_no_loop: bool,
+    pub effect: Option<String>,
+    pub file2: Option<String>,
+    pub transition: Option<String>,
+    //Colors are written as r^g^b^a, since commas separate the changes
+    pub color1: Option<String>,
+    pub color2: Option<String>,
+}
+
+//Modifiers forced on the player for a while, timed in seconds rather than beats
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attack {
+    pub time: f64,
+    pub length: f64,
+    pub mods: Vec<String>,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimeSignature {
+    pub numerator: i32,
+    pub denominator: i32,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpeedUnit {
+    Beats,
+    Seconds,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Speed {
+    pub ratio: f64,
+    pub length: f64,
+    pub unit: SpeedUnit,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Combo {
+    pub hit: i32,
+    pub miss: i32,
+}
+
+//Stops and delays are in seconds, warps and fakes are lengths in beats as they are in .ssc files
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 #[derive(Debug, Default, PartialEq, Clone)]
 pub struct StructureData {
     pub offset: Option<f64>,
     pub bpms: Vec<BeatPair<f64>>,
     pub stops: Option<Vec<BeatPair<f64>>>,
+    pub delays: Option<Vec<BeatPair<f64>>>,
+    pub warps: Option<Vec<BeatPair<f64>>>,
+    pub time_signatures: Option<Vec<BeatPair<TimeSignature>>>,
+    pub tick_counts: Option<Vec<BeatPair<i32>>>,
+    pub combos: Option<Vec<BeatPair<Combo>>>,
+    pub speeds: Option<Vec<BeatPair<Speed>>>,
+    pub scrolls: Option<Vec<BeatPair<f64>>>,
+    pub fakes: Option<Vec<BeatPair<f64>>>,
+    pub labels: Option<Vec<BeatPair<String>>>,
 }
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 #[derive(Debug, Default, PartialEq, Clone)]
 pub struct NoteData {
-    pub charts: Vec<Chart>,
+    pub charts: Vec<(ChartInfo, Chart)>,
+    pub meta: ChartMetadata,
+    pub structure: StructureData,
+}
+
+//Strict parsing stops at the first problem, lenient parsing skips what it can't read and warns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub lenient: bool,
+    //Skips detection, though a byte order mark is still honoured
+    pub encoding: Option<&'static Encoding>,
+    //Tried in order when a file isn't UTF-8
+    pub fallback_encodings: &'static [&'static Encoding],
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            lenient: false,
+            encoding: None,
+            fallback_encodings: &text::LEGACY_ENCODINGS,
+        }
+    }
+}
+
+//A chart's info without its notes, for listing songs without loading every chart
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChartSummary {
+    pub info: ChartInfo,
+    pub note_count: usize,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct SongHeader {
     pub meta: ChartMetadata,
     pub structure: StructureData,
+    pub charts: Vec<ChartSummary>,
 }
 
 impl<T> BeatPair<T> {
@@ -152,20 +317,129 @@ impl<T> BeatPair<T> {
     }
 
     #[must_use]
-    fn from_pair(beat: f64, value: T) -> Option<Self> {
-        let ratio = Fraction::approximate_float(beat)?;
-        Some(Self {
-            beat: ratio.to_integer(),
-            sub_beat: ratio.fract(),
+    fn at_position(position: Fraction, value: T) -> Self {
+        Self {
+            beat: position.to_integer(),
+            sub_beat: position.fract(),
             value,
-        })
+        }
+    }
+
+    #[must_use]
+    fn from_pair(beat: f64, value: T) -> Option<Self> {
+        Some(Self::at_position(Fraction::approximate_float(beat)?, value))
     }
 }
 
 impl Note {
     #[must_use]
     pub fn new(note_type: NoteType, column: usize) -> Self {
-        Self { note_type, column }
+        Self {
+            note_type,
+            column,
+            keysound: None,
+        }
+    }
+}
+
+impl NoteType {
+    //Mines and fakes are never hit, and hold ends belong to the hold they finish
+    pub(crate) fn is_hit(self) -> bool {
+        match self {
+            Self::Tap | Self::Hold | Self::Roll | Self::Lift => true,
+            Self::Mine | Self::Fake | Self::HoldEnd | Self::AutoKeysound => false,
+        }
+    }
+}
+
+impl Default for ChartInfo {
+    fn default() -> Self {
+        Self {
+            steps_type: "dance-single".to_owned(),
+            description: None,
+            difficulty: Difficulty::default(),
+            meter: None,
+            radar_values: vec![],
+            chart_name: None,
+            credit: None,
+            structure: None,
+        }
+    }
+}
+
+impl ChartInfo {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Falls back to the widest row of the chart for steps types we don't know about
+    #[must_use]
+    pub fn columns(&self, chart: &[Measure]) -> usize {
+        steps_type_columns(&self.steps_type).unwrap_or_else(|| {
+            chart
+                .iter()
+                .flatten()
+                .flat_map(|(row, _)| row.iter().map(|note| note.column + 1))
+                .max()
+                .unwrap_or_default()
+        })
+    }
+
+    #[must_use]
+    pub fn structure_or<'a>(&'a self, song_structure: &'a StructureData) -> &'a StructureData {
+        self.structure.as_ref().unwrap_or(song_structure)
+    }
+}
+
+//Keyboard modes other than the ones StepMania names are written as kb<keys>-single
+#[must_use]
+pub fn steps_type_columns(steps_type: &str) -> Option<usize> {
+    let steps_type = steps_type.to_lowercase();
+    Some(match steps_type.as_str() {
+        "dance-threepanel" => 3,
+        "dance-single" | "techno-single4" | "maniax-single" => 4,
+        "pump-single" | "techno-single5" | "popn-five" | "para-single" | "ez2-single" => 5,
+        "dance-solo" | "pump-halfdouble" | "beat-single5" => 6,
+        "ez2-real" => 7,
+        "dance-double" | "dance-couple" | "dance-routine" | "techno-single8" | "techno-double4"
+        | "beat-single7" | "maniax-double" | "lights-cabinet" => 8,
+        "popn-nine" => 9,
+        "pump-double" | "pump-couple" | "pump-routine" | "techno-double5" | "ez2-double" => 10,
+        "beat-double5" => 12,
+        "beat-double7" | "techno-double8" => 16,
+        _ => steps_type
+            .strip_prefix("kb")?
+            .strip_suffix("-single")?
+            .parse()
+            .ok()?,
+    })
+}
+
+//The inverse of steps_type_columns for keyboard modes, used by formats that only store a key count
+#[must_use]
+pub fn keyboard_steps_type(columns: usize) -> String {
+    match columns {
+        4 => "dance-single".to_owned(),
+        _ => format!("kb{}-single", columns),
+    }
+}
+
+impl BackgroundChange {
+    #[must_use]
+    pub fn new(file: String) -> Self {
+        Self {
+            file,
+            rate: 1.0,
+            crossfade: false,
+            stretch_rewind: false,
+            stretch_no_loop: false,
+            effect: None,
+            file2: None,
+            transition: None,
+            color1: None,
+            color2: None,
+        }
     }
 }
 
@@ -182,7 +456,7 @@ impl NoteData {
         Self::default()
     }
 
-    pub fn charts(&mut self, charts: Vec<Chart>) -> &mut Self {
+    pub fn charts(&mut self, charts: Vec<(ChartInfo, Chart)>) -> &mut Self {
         self.charts = charts;
         self
     }
@@ -192,24 +466,209 @@ impl NoteData {
         self
     }
 
-    #[must_use]
-    pub fn to_sm_string(&self) -> String {
+    //Adds a chart of the steps type for every difficulty that doesn't have one, derived from the
+    //first chart of that difficulty autogen has a rule for
+    pub fn autogen(&mut self, steps_type: &str) -> &mut Self {
+        let mut generated: Vec<(ChartInfo, Chart)> = vec![];
+        for (info, chart) in &self.charts {
+            let covered = self.charts.iter().chain(&generated).any(|(other, _)| {
+                other.difficulty == info.difficulty
+                    && other.description == info.description
+                    && other.steps_type.eq_ignore_ascii_case(steps_type)
+            });
+            if !covered {
+                generated.extend(autogen(info, chart, steps_type));
+            }
+        }
+        self.charts.extend(generated);
+        self
+    }
+
+    pub fn transform(&mut self, transforms: &[Transform]) -> &mut Self {
+        for (info, chart) in &mut self.charts {
+            let columns = info.columns(chart);
+            for transform in transforms {
+                transform.apply(chart, columns);
+            }
+        }
+        self
+    }
+
+    pub fn to_sm_string(&self) -> Result<String, WriteError> {
         sm_writer::write_sm(&self)
     }
 
-    pub fn from_sm_reader(mut reader: impl io::Read) -> Result<Self, ParseError> {
-        let mut sm_string = String::new();
-        reader.read_to_string(&mut sm_string)?;
-        Ok(sm_parser::parse(&sm_string)?)
+    pub fn from_sm_reader(reader: impl io::Read) -> Result<Self, ParseError> {
+        Ok(Self::from_sm_reader_with_options(reader, ParseOptions::default())?.0)
+    }
+
+    //Returns the warnings lenient parsing recovered from alongside the data
+    pub fn from_sm_reader_with_options(
+        reader: impl io::Read,
+        options: ParseOptions,
+    ) -> Result<(Self, Vec<ParseError>), ParseError> {
+        sm_parser::parse(&text::read_text(reader, options)?, options)
+    }
+
+    pub fn to_sm_writer(&self, mut writer: impl io::Write) -> Result<(), WriteError> {
+        Ok(writer.write_all(&self.to_sm_string()?.into_bytes())?)
+    }
+
+    pub fn to_ssc_string(&self) -> Result<String, WriteError> {
+        ssc_writer::write_ssc(self)
+    }
+
+    pub fn from_ssc_reader(reader: impl io::Read) -> Result<Self, ParseError> {
+        Ok(Self::from_ssc_reader_with_options(reader, ParseOptions::default())?.0)
+    }
+
+    //Returns the warnings lenient parsing recovered from alongside the data
+    pub fn from_ssc_reader_with_options(
+        reader: impl io::Read,
+        options: ParseOptions,
+    ) -> Result<(Self, Vec<ParseError>), ParseError> {
+        ssc_parser::parse(&text::read_text(reader, options)?, options)
+    }
+
+    pub fn to_ssc_writer(&self, mut writer: impl io::Write) -> Result<(), WriteError> {
+        Ok(writer.write_all(&self.to_ssc_string()?.into_bytes())?)
     }
 
-    pub fn to_sm_writer(&self, mut writer: impl io::Write) -> io::Result<()> {
-        writer.write_all(&self.to_sm_string().into_bytes())
+    pub fn from_dwi_reader(reader: impl io::Read) -> Result<Self, ParseError> {
+        Ok(Self::from_dwi_reader_with_options(reader, ParseOptions::default())?.0)
     }
 
-    pub fn from_dwi_reader(mut reader: impl io::Read) -> Result<Self, ParseError> {
-        let mut dwi_string = String::new();
-        reader.read_to_string(&mut dwi_string)?;
-        Ok(dwi_parser::parse(&dwi_string)?)
+    //Returns the warnings lenient parsing recovered from alongside the data
+    pub fn from_dwi_reader_with_options(
+        reader: impl io::Read,
+        options: ParseOptions,
+    ) -> Result<(Self, Vec<ParseError>), ParseError> {
+        dwi_parser::parse(&text::read_text(reader, options)?, options)
+    }
+
+    //BMS files are nearly always Shift-JIS, so they go through the same detection as simfiles
+    pub fn from_bms_reader(reader: impl io::Read) -> Result<Self, ParseError> {
+        let bms_string = text::read_text(reader, ParseOptions::default())?;
+        bms_parser::parse(&bms_string, false)
+    }
+
+    //PMS files share the BMS syntax but lay their nine buttons out over both players' channels
+    pub fn from_pms_reader(reader: impl io::Read) -> Result<Self, ParseError> {
+        let pms_string = text::read_text(reader, ParseOptions::default())?;
+        bms_parser::parse(&pms_string, true)
+    }
+
+    pub fn from_osu_reader(mut reader: impl io::Read) -> Result<Self, ParseError> {
+        let mut osu_string = String::new();
+        reader.read_to_string(&mut osu_string)?;
+        osu_parser::parse(&osu_string)
+    }
+
+    #[cfg(feature = "serde-formats")]
+    pub fn from_qua_reader(mut reader: impl io::Read) -> Result<Self, ParseError> {
+        let mut qua_string = String::new();
+        reader.read_to_string(&mut qua_string)?;
+        Ok(qua_parser::parse(&qua_string)?)
+    }
+
+    #[cfg(feature = "serde-formats")]
+    pub fn from_mc_reader(mut reader: impl io::Read) -> Result<Self, ParseError> {
+        let mut mc_string = String::new();
+        reader.read_to_string(&mut mc_string)?;
+        Ok(mc_parser::parse(&mc_string)?)
+    }
+
+    #[cfg(feature = "rm-format")]
+    pub fn from_rm_reader(mut reader: impl io::Read) -> Result<Self, ParseError> {
+        let mut rm_bytes = vec![];
+        reader.read_to_end(&mut rm_bytes)?;
+        Ok(rm::read_rm(&rm_bytes)?)
+    }
+
+    #[cfg(feature = "rm-format")]
+    pub fn to_rm_writer(
+        &self,
+        writer: impl io::Write,
+        encoding: RmEncoding,
+    ) -> Result<(), WriteError> {
+        Ok(rm::write_rm(self, writer, encoding)?)
+    }
+
+    pub fn to_dwi_string(&self) -> Result<String, WriteError> {
+        dwi_writer::write_dwi(self)
+    }
+
+    pub fn to_dwi_writer(&self, mut writer: impl io::Write) -> Result<(), WriteError> {
+        Ok(writer.write_all(&self.to_dwi_string()?.into_bytes())?)
+    }
+
+    //One chart_key per chart, using the chart's own timing where it has it
+    #[must_use]
+    pub fn chart_keys(&self) -> Vec<String> {
+        self.charts
+            .iter()
+            .map(|(info, chart)| {
+                chart_key(
+                    chart,
+                    info.structure_or(&self.structure),
+                    info.columns(chart),
+                )
+            })
+            .collect()
+    }
+
+    //Stats for each chart, using the chart's own timing where it has it
+    #[must_use]
+    pub fn chart_stats(&self) -> Vec<Option<ChartStats>> {
+        self.charts
+            .iter()
+            .map(|(info, chart)| ChartStats::new(chart, info.structure_or(&self.structure)))
+            .collect()
+    }
+
+    //Structural problems worth fixing before the song is played. folder is the one the simfile is
+    //in, and song_length is the music's length in seconds when it's known.
+    #[must_use]
+    pub fn validate(&self, folder: &Path, song_length: Option<f64>) -> Vec<Diagnostic> {
+        validate::validate(self, folder, song_length)
+    }
+}
+
+//Reading only the header skips building the notes, which is most of the work of a full parse
+impl SongHeader {
+    pub fn from_sm_reader(
+        reader: impl io::Read,
+        options: ParseOptions,
+    ) -> Result<(Self, Vec<ParseError>), ParseError> {
+        sm_parser::parse_header(&text::read_text(reader, options)?, options)
+    }
+
+    pub fn from_ssc_reader(
+        reader: impl io::Read,
+        options: ParseOptions,
+    ) -> Result<(Self, Vec<ParseError>), ParseError> {
+        ssc_parser::parse_header(&text::read_text(reader, options)?, options)
+    }
+}
+
+impl From<&NoteData> for SongHeader {
+    fn from(data: &NoteData) -> Self {
+        Self {
+            meta: data.meta.clone(),
+            structure: data.structure.clone(),
+            charts: data
+                .charts
+                .iter()
+                .map(|(info, chart)| ChartSummary {
+                    info: info.clone(),
+                    note_count: chart
+                        .iter()
+                        .flatten()
+                        .flat_map(|(row, _)| row)
+                        .filter(|note| note.note_type.is_hit())
+                        .count(),
+                })
+                .collect(),
+        }
     }
 }
@@ -0,0 +1,229 @@
+use crate::{
+    keyboard_steps_type,
+    parser_generic::{rows_to_chart, with_translit},
+    BeatPair, ChartInfo, Difficulty, Fraction, Note, NoteData, NoteType,
+};
+use serde::{de::Error, Deserialize};
+use std::collections::BTreeMap;
+
+//Malody's key mode, its other modes aren't column based
+const KEY_MODE: i32 = 0;
+
+#[derive(Deserialize)]
+struct Mc {
+    meta: McMeta,
+    #[serde(default)]
+    time: Vec<McTime>,
+    #[serde(default)]
+    effect: Vec<McEffect>,
+    #[serde(default)]
+    note: Vec<McNote>,
+}
+
+#[derive(Deserialize)]
+struct McMeta {
+    creator: Option<String>,
+    background: Option<String>,
+    version: Option<String>,
+    preview: Option<f64>,
+    #[serde(default)]
+    mode: i32,
+    song: McSong,
+    mode_ext: Option<McModeExt>,
+}
+
+//title and artist are romanized, the org fields hold the original script
+#[derive(Deserialize)]
+struct McSong {
+    title: Option<String>,
+    artist: Option<String>,
+    titleorg: Option<String>,
+    artistorg: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct McModeExt {
+    column: usize,
+}
+
+//Beats are stored as [beat, numerator, denominator]
+type McBeat = [i32; 3];
+
+#[derive(Deserialize)]
+struct McTime {
+    beat: McBeat,
+    bpm: f64,
+}
+
+#[derive(Deserialize)]
+struct McEffect {
+    beat: McBeat,
+    scroll: Option<f64>,
+}
+
+//The music is a note with a sound and no column
+#[derive(Deserialize)]
+struct McNote {
+    beat: McBeat,
+    endbeat: Option<McBeat>,
+    column: Option<usize>,
+    sound: Option<String>,
+    #[serde(default)]
+    offset: f64,
+}
+
+fn position([beat, numerator, denominator]: McBeat) -> Fraction {
+    if denominator == 0 {
+        Fraction::new(beat, 4)
+    } else {
+        Fraction::new(beat * denominator + numerator, denominator * 4)
+    }
+}
+
+fn row(position: Fraction) -> (usize, Fraction) {
+    (position.to_integer() as usize, position.fract())
+}
+
+pub fn parse(input: &str) -> Result<NoteData, serde_json::Error> {
+    let mc: Mc = serde_json::from_str(input)?;
+    if mc.meta.mode != KEY_MODE {
+        return Err(serde_json::Error::custom(format!(
+            "unsupported mode {}",
+            mc.meta.mode
+        )));
+    }
+    let columns = mc
+        .meta
+        .mode_ext
+        .ok_or_else(|| serde_json::Error::missing_field("mode_ext"))?
+        .column;
+    let mut nd = NoteData::new();
+    let (title, title_translit) = with_translit(mc.meta.song.titleorg, mc.meta.song.title);
+    let (artist, artist_translit) = with_translit(mc.meta.song.artistorg, mc.meta.song.artist);
+    nd.meta.title = title;
+    nd.meta.title_translit = title_translit;
+    nd.meta.artist = artist;
+    nd.meta.artist_translit = artist_translit;
+    nd.meta.background_path = mc.meta.background;
+    nd.meta.sample_start = mc.meta.preview.map(|time| time / 1000.0);
+    let info = ChartInfo {
+        steps_type: keyboard_steps_type(columns),
+        description: mc.meta.version,
+        difficulty: Difficulty::Edit,
+        credit: mc.meta.creator,
+        ..ChartInfo::default()
+    };
+
+    let mut bpms: Vec<_> = mc
+        .time
+        .iter()
+        .map(|time| BeatPair::at_position(position(time.beat), time.bpm))
+        .collect();
+    bpms.sort_by_key(|bpm| (bpm.beat, bpm.sub_beat));
+    if let Some(first) = bpms.first_mut() {
+        *first = BeatPair::at_start(first.value);
+    }
+    nd.structure.bpms = bpms;
+    let scrolls: Vec<_> = mc
+        .effect
+        .iter()
+        .filter_map(|effect| Some(BeatPair::at_position(position(effect.beat), effect.scroll?)))
+        .collect();
+    if !scrolls.is_empty() {
+        nd.structure.scrolls = Some(scrolls);
+    }
+
+    let mut rows: BTreeMap<(usize, Fraction), Vec<Note>> = BTreeMap::new();
+    for note in mc.note {
+        let column = match (note.column, note.sound) {
+            (Some(column), _) if column < columns => column,
+            //The music's offset is where the first beat falls in the audio
+            (None, Some(sound)) => {
+                nd.meta.music_path = Some(sound);
+                nd.structure.offset = Some(note.offset / 1000.0);
+                continue;
+            }
+            _ => continue,
+        };
+        let start = position(note.beat);
+        let note_type = match note.endbeat.map(position) {
+            Some(end) if end > start => {
+                rows.entry(row(end))
+                    .or_default()
+                    .push(Note::new(NoteType::HoldEnd, column));
+                NoteType::Hold
+            }
+            _ => NoteType::Tap,
+        };
+        rows.entry(row(start))
+            .or_default()
+            .push(Note::new(note_type, column));
+    }
+    nd.charts.push((info, rows_to_chart(rows)));
+    Ok(nd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mc() {
+        let nd = parse(
+            r#"{
+                "meta": {
+                    "creator": "mapper",
+                    "background": "bg.jpg",
+                    "version": "4K Hard",
+                    "preview": 30000,
+                    "mode": 0,
+                    "song": {"title": "Romanized", "titleorg": "Original", "artist": "Someone"},
+                    "mode_ext": {"column": 4}
+                },
+                "time": [{"beat": [0, 0, 1], "bpm": 120}, {"beat": [6, 0, 1], "bpm": 240}],
+                "effect": [{"beat": [2, 1, 2], "scroll": 0.5}],
+                "note": [
+                    {"beat": [0, 1, 3], "column": 0},
+                    {"beat": [4, 0, 1], "endbeat": [5, 2, 4], "column": 3},
+                    {"beat": [0, 0, 1], "sound": "song.ogg", "vol": 100, "offset": 120, "type": 1}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(nd.meta.title, Some("Original".to_owned()));
+        assert_eq!(nd.meta.title_translit, Some("Romanized".to_owned()));
+        assert_eq!(nd.meta.artist, Some("Someone".to_owned()));
+        assert_eq!(nd.meta.music_path, Some("song.ogg".to_owned()));
+        assert_eq!(nd.meta.sample_start, Some(30.0));
+        assert_eq!(nd.structure.offset, Some(0.12));
+        assert_eq!(
+            nd.structure.bpms,
+            vec![
+                BeatPair::at_start(120.0),
+                BeatPair::at_position(Fraction::new(3, 2), 240.0)
+            ]
+        );
+        assert_eq!(
+            nd.structure.scrolls,
+            Some(vec![BeatPair::at_position(Fraction::new(5, 8), 0.5)])
+        );
+        let (info, chart) = &nd.charts[0];
+        assert_eq!(info.steps_type, "dance-single");
+        assert_eq!(info.description, Some("4K Hard".to_owned()));
+        assert_eq!(
+            chart,
+            &vec![
+                vec![(vec![Note::new(NoteType::Tap, 0)], Fraction::new(1, 12))],
+                vec![
+                    (vec![Note::new(NoteType::Hold, 3)], Fraction::from(0)),
+                    (vec![Note::new(NoteType::HoldEnd, 3)], Fraction::new(3, 8)),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_other_modes() {
+        assert!(parse(r#"{"meta": {"mode": 3, "song": {}}}"#).is_err());
+    }
+}
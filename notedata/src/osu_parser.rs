@@ -0,0 +1,271 @@
+use crate::{
+    error::{ParseError, Problem, ValueError},
+    keyboard_steps_type,
+    parser_generic::{with_translit, ws_trimmed},
+    timed_notes::{TimedChart, TimedNote, TimingPoint},
+    ChartInfo, Difficulty, NoteData,
+};
+use nom::{
+    combinator::{all_consuming, map_res},
+    number::complete::double,
+};
+use std::convert::TryFrom;
+
+fn number(field: &str) -> Result<f64, ValueError<'_>> {
+    Ok(all_consuming(ws_trimmed(double))(field)
+        .map_err(ValueError::bad_number)?
+        .1)
+}
+
+fn integer(field: &str) -> Result<i32, ValueError<'_>> {
+    Ok(all_consuming(ws_trimmed(map_res(double, |value: f64| {
+        i32::try_from(value as i64)
+    })))(field)
+    .map_err(ValueError::bad_number)?
+    .1)
+}
+
+fn key_value(line: &str) -> Option<(&str, &str)> {
+    let colon = line.find(':')?;
+    Some((line[..colon].trim(), line[colon + 1..].trim()))
+}
+
+//Background events are written as 0,0,"filename" with optional offsets after them
+fn background(line: &str) -> Option<String> {
+    let mut fields = line.split(',');
+    if fields.next()? == "0" && fields.next()? == "0" {
+        Some(fields.next()?.trim_matches('"').to_owned())
+    } else {
+        None
+    }
+}
+
+fn timing_point(line: &str) -> Result<Option<TimingPoint>, ValueError<'_>> {
+    let fields: Vec<_> = line.split(',').collect();
+    let time = number(fields[0])?;
+    let beat_length = number(
+        fields
+            .get(1)
+            .ok_or_else(|| ValueError::at(Problem::Syntax, line))?,
+    )?;
+    //Inherited points only change scroll speed, old files without the field are all uninherited
+    let uninherited = match fields.get(6) {
+        Some(field) => integer(field)? == 1,
+        None => true,
+    };
+    Ok(if uninherited && beat_length > 0.0 {
+        Some(TimingPoint {
+            time,
+            bpm: 60_000.0 / beat_length,
+        })
+    } else {
+        None
+    })
+}
+
+fn hit_object(line: &str, keys: usize) -> Result<TimedNote, ValueError<'_>> {
+    let fields: Vec<_> = line.split(',').collect();
+    if fields.len() < 5 {
+        return Err(ValueError::at(Problem::Syntax, line));
+    }
+    let x = number(fields[0])?;
+    let column = ((x * keys as f64 / 512.0).floor().max(0.0) as usize).min(keys - 1);
+    let time = number(fields[2])?;
+    //Type bit 7 marks a mania hold, whose end time is the first part of its extra parameters
+    let end_time = if integer(fields[3])? & 128 == 0 {
+        None
+    } else {
+        let parameters = fields
+            .get(5)
+            .ok_or_else(|| ValueError::at(Problem::Syntax, line))?;
+        Some(number(parameters.split(':').next().unwrap_or(parameters))?)
+    };
+    Ok(TimedNote {
+        column,
+        time,
+        end_time,
+    })
+}
+
+pub fn parse(input: &str) -> Result<NoteData, ParseError> {
+    let mut nd = NoteData::new();
+    let mut info = ChartInfo {
+        difficulty: Difficulty::Edit,
+        ..ChartInfo::default()
+    };
+    let mut title = None;
+    let mut title_unicode = None;
+    let mut artist = None;
+    let mut artist_unicode = None;
+    let mut keys = 4;
+    let mut mode = 0;
+    let mut timing_points = vec![];
+    let mut hit_objects = vec![];
+    let mut section = "";
+
+    for line in input.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = &line[1..line.len() - 1];
+            continue;
+        }
+        match section {
+            "General" | "Metadata" | "Difficulty" => {
+                let (key, value) = match key_value(line) {
+                    Some(pair) => pair,
+                    None => continue,
+                };
+                match key {
+                    "AudioFilename" => nd.meta.music_path = Some(value.to_owned()),
+                    "PreviewTime" => {
+                        let start = number(value).map_err(|error| error.locate(input, key))?;
+                        nd.meta.sample_start = Some(start / 1000.0).filter(|start| *start >= 0.0)
+                    }
+                    "Mode" => mode = integer(value).map_err(|error| error.locate(input, key))?,
+                    "Title" => title = Some(value.to_owned()),
+                    "TitleUnicode" => title_unicode = Some(value.to_owned()),
+                    "Artist" => artist = Some(value.to_owned()),
+                    "ArtistUnicode" => artist_unicode = Some(value.to_owned()),
+                    "Creator" => info.credit = Some(value.to_owned()),
+                    "Version" => info.description = Some(value.to_owned()),
+                    "CircleSize" => {
+                        keys = integer(value)
+                            .ok()
+                            .and_then(|count| usize::try_from(count).ok())
+                            .filter(|&count| count > 0)
+                            .ok_or_else(|| {
+                                ValueError::at(Problem::BadNumber, value).locate(input, key)
+                            })?
+                    }
+                    _ => {}
+                }
+            }
+            "Events" => {
+                if let Some(path) = background(line) {
+                    nd.meta.background_path = Some(path);
+                }
+            }
+            "TimingPoints" => {
+                let point = timing_point(line).map_err(|error| error.locate(input, section))?;
+                if let Some(point) = point {
+                    timing_points.push(point);
+                }
+            }
+            //CircleSize always comes before the hit objects, so the key count is known here
+            "HitObjects" => hit_objects
+                .push(hit_object(line, keys).map_err(|error| error.locate(input, section))?),
+            _ => {}
+        }
+    }
+    if mode != 3 {
+        return Err(ParseError::Unsupported(format!("osu! mode {}", mode)));
+    }
+    let (title, title_translit) = with_translit(title_unicode, title);
+    let (artist, artist_translit) = with_translit(artist_unicode, artist);
+    nd.meta.title = title;
+    nd.meta.title_translit = title_translit;
+    nd.meta.artist = artist;
+    nd.meta.artist_translit = artist_translit;
+    info.steps_type = keyboard_steps_type(keys);
+
+    let chart = match TimedChart::new(timing_points, &hit_objects) {
+        Some(timed_chart) => {
+            timed_chart.write_structure(&mut nd.structure);
+            timed_chart.chart(&hit_objects)
+        }
+        None => vec![],
+    };
+    nd.charts.push((info, chart));
+    Ok(nd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BeatPair, Fraction, Note, NoteType};
+
+    const BEATMAP: &str = "osu file format v14
+
+[General]
+AudioFilename: audio.mp3
+AudioLeadIn: 0
+PreviewTime: 12000
+Mode: 3
+
+[Metadata]
+Title:Romanized
+TitleUnicode:Unicode
+Artist:Someone
+ArtistUnicode:Someone
+Creator:mapper
+Version:7K Hard
+
+[Difficulty]
+HPDrainRate:8
+CircleSize:7
+OverallDifficulty:8
+
+[Events]
+//Background and Video events
+0,0,\"bg.jpg\",0,0
+
+[TimingPoints]
+1000,500,4,2,0,60,1,0
+2000,-50,4,2,0,60,0,0
+3000,250,4,2,0,60,1,0
+
+[HitObjects]
+36,192,500,1,0,0:0:0:0:
+109,192,1000,1,0,0:0:0:0:
+475,192,1250,128,0,2000:0:0:0:0:
+256,192,3125,5,0,0:0:0:0:
+182,192,3188,1,0,0:0:0:0:
+";
+
+    #[test]
+    fn parse_beatmap() {
+        let nd = parse(BEATMAP).unwrap();
+        assert_eq!(nd.meta.title, Some("Unicode".to_owned()));
+        assert_eq!(nd.meta.title_translit, Some("Romanized".to_owned()));
+        assert_eq!(nd.meta.artist, Some("Someone".to_owned()));
+        assert_eq!(nd.meta.artist_translit, None);
+        assert_eq!(nd.meta.music_path, Some("audio.mp3".to_owned()));
+        assert_eq!(nd.meta.background_path, Some("bg.jpg".to_owned()));
+        assert_eq!(nd.meta.sample_start, Some(12.0));
+        //The note at 500ms comes before the first timing point, so a measure is added before it
+        assert_eq!(nd.structure.offset, Some(-1.0));
+        assert_eq!(
+            nd.structure.bpms,
+            vec![
+                BeatPair::at_start(120.0),
+                BeatPair::from_pair(2.0, 240.0).unwrap()
+            ]
+        );
+        let (info, chart) = &nd.charts[0];
+        assert_eq!(info.steps_type, "kb7-single");
+        assert_eq!(info.description, Some("7K Hard".to_owned()));
+        assert_eq!(info.credit, Some("mapper".to_owned()));
+        assert_eq!(
+            chart,
+            &vec![
+                vec![(vec![Note::new(NoteType::Tap, 0)], Fraction::new(3, 4))],
+                vec![
+                    (vec![Note::new(NoteType::Tap, 1)], Fraction::new(0, 1)),
+                    (vec![Note::new(NoteType::Hold, 6)], Fraction::new(1, 8)),
+                    (vec![Note::new(NoteType::HoldEnd, 6)], Fraction::new(1, 2)),
+                ],
+                vec![
+                    (vec![Note::new(NoteType::Tap, 3)], Fraction::new(1, 8)),
+                    (vec![Note::new(NoteType::Tap, 2)], Fraction::new(3, 16)),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_other_modes() {
+        assert!(parse(&BEATMAP.replace("Mode: 3", "Mode: 0")).is_err());
+    }
+}
@@ -1,13 +1,17 @@
-use crate::BeatPair;
+use crate::{
+    error::{Location, ParseError, Problem, ValueError},
+    BeatPair, Fraction, Measure, Note, ParseOptions,
+};
 use nom::{
-    bytes::complete::{is_not, take_until},
-    character::complete::{char, multispace0},
-    combinator::{map, map_opt},
+    bytes::complete::is_not,
+    character::complete::{char, digit1, multispace0},
+    combinator::{all_consuming, map, map_opt, map_res, opt, recognize},
     multi::separated_nonempty_list,
     number::complete::double,
     sequence::{preceded, separated_pair, terminated},
     IResult,
 };
+use std::collections::{BTreeMap, HashSet};
 
 pub fn comma_separated<'a, P, O>(parser: P) -> impl Fn(&'a str) -> IResult<&str, Vec<O>>
 where
@@ -28,6 +32,10 @@ where
     }
 }
 
+pub fn integer(input: &str) -> IResult<&str, i32> {
+    map_res(recognize(preceded(opt(char('-')), digit1)), str::parse)(input)
+}
+
 pub fn ws_trimmed<'a, P, O>(parser: P) -> impl Fn(&'a str) -> IResult<&str, O>
 where
     P: Fn(&'a str) -> IResult<&str, O>,
@@ -35,14 +43,202 @@ where
     move |input: &str| preceded(multispace0, terminated(&parser, multispace0))(input)
 }
 
-pub fn stepmania_tag(input: &str) -> IResult<&str, (&str, &str)> {
-    separated_pair(
-        preceded(char('#'), ws_trimmed(is_not(": \t\r\n"))),
+pub fn number(value: &str) -> Result<f64, ValueError<'_>> {
+    Ok(ws_trimmed(double)(value).map_err(ValueError::bad_number)?.1)
+}
+
+pub fn pairs<'a, P, O>(
+    parser: P,
+    scale: f64,
+    value: &'a str,
+) -> Result<Vec<BeatPair<O>>, ValueError<'a>>
+where
+    P: Fn(&'a str) -> IResult<&str, O>,
+{
+    //A trailing comma is harmless, anything else left over is a pair that didn't parse
+    Ok(all_consuming(terminated(
+        ws_trimmed(comma_separated(beat_pair(parser, scale))),
+        opt(ws_trimmed(char(','))),
+    ))(value)
+    .map_err(ValueError::malformed_pair)?
+    .1)
+}
+
+//Problems found inside a tag's value, which lenient parsing records and carries on past
+pub struct Warnings<'a> {
+    lenient: bool,
+    found: Vec<ValueError<'a>>,
+}
+
+impl<'a> Warnings<'a> {
+    pub fn new(options: ParseOptions) -> Self {
+        Self {
+            lenient: options.lenient,
+            found: vec![],
+        }
+    }
+
+    //Errors end a strict parse, a lenient one keeps them as warnings and carries on
+    pub fn recover(&mut self, error: ValueError<'a>) -> Result<(), ValueError<'a>> {
+        if self.lenient {
+            self.found.push(error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    pub fn duplicate(&mut self, seen: &mut HashSet<&'a str>, tag: &'a str, value: &'a str) {
+        if !seen.insert(tag) {
+            self.found
+                .push(ValueError::at(Problem::DuplicateTag, value));
+        }
+    }
+
+    //Lenient mode keeps the pairs that parse, skipping each malformed one
+    pub fn pairs<P, O>(
+        &mut self,
+        parser: P,
+        scale: f64,
+        value: &'a str,
+    ) -> Result<Vec<BeatPair<O>>, ValueError<'a>>
+    where
+        P: Fn(&'a str) -> IResult<&str, O>,
+    {
+        match pairs(&parser, scale, value) {
+            Err(_) if self.lenient => {}
+            result => return result,
+        }
+        Ok(value
+            .split(',')
+            .filter(|pair| !pair.trim().is_empty())
+            .filter_map(|pair| {
+                let parsed = all_consuming(ws_trimmed(beat_pair(&parser, scale)))(pair);
+                if parsed.is_err() {
+                    self.found
+                        .push(ValueError::at(Problem::MalformedPair, pair));
+                }
+                parsed.ok().map(|(_, parsed_pair)| parsed_pair)
+            })
+            .collect())
+    }
+}
+
+//A tag missing its semicolon runs into the next one, which always starts its own line
+fn next_line_tag(body: &str) -> Option<usize> {
+    body.match_indices('\n')
+        .map(|(index, _)| index + body[index..].len() - body[index..].trim_start().len())
+        .find(|&index| body[index..].starts_with('#'))
+}
+
+fn tag_name(input: &str) -> IResult<&str, &str> {
+    terminated(
+        preceded(char('#'), ws_trimmed(is_not(": \t\r\n#"))),
         char(':'),
-        terminated(take_until(";"), char(';')),
     )(input)
 }
 
+//Warnings::recover for errors outside of a tag's value, which are located already
+fn recover(
+    options: ParseOptions,
+    warnings: &mut Vec<ParseError>,
+    error: ParseError,
+) -> Result<(), ParseError> {
+    if options.lenient {
+        warnings.push(error);
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+//Calls handle with the name and value of every tag in a StepMania style file, returning warnings
+pub fn for_each_tag<'a>(
+    input: &'a str,
+    options: ParseOptions,
+    mut handle: impl FnMut(&'a str, &'a str, &mut Warnings<'a>) -> Result<(), ValueError<'a>>,
+) -> Result<Vec<ParseError>, ParseError> {
+    let mut warnings = Warnings::new(options);
+    let mut located = vec![];
+    let mut rest = input;
+    while let Some(index) = rest.find('#') {
+        let start = &rest[index..];
+        //A stray # that doesn't start a tag is skipped over
+        let (body, tag) = if let Ok(parts) = tag_name(start) {
+            parts
+        } else {
+            recover(
+                options,
+                &mut located,
+                ParseError::Syntax(Location::new(input, "", start)),
+            )?;
+            rest = &start[1..];
+            continue;
+        };
+        let (value, output) = match (body.find(';'), next_line_tag(body)) {
+            (Some(end), next) if next.filter(|&next| next < end).is_none() => {
+                (&body[..end], &body[end + 1..])
+            }
+            (_, next) => {
+                let location = Location::new(input, tag, start);
+                recover(options, &mut located, ParseError::UnterminatedTag(location))?;
+                body.split_at(next.unwrap_or(body.len()))
+            }
+        };
+        if let Err(error) = handle(tag, value, &mut warnings) {
+            warnings
+                .recover(error)
+                .map_err(|error| error.locate(input, tag))?;
+        }
+        located.extend(
+            warnings
+                .found
+                .drain(..)
+                .map(|error| error.locate(input, tag)),
+        );
+        rest = output;
+    }
+    Ok(located)
+}
+
+//Formats that place notes by time build their rows out of order, keyed by measure and position
+pub fn rows_to_chart(rows: BTreeMap<(usize, Fraction), Vec<Note>>) -> Vec<Measure> {
+    let mut chart: Vec<Measure> = vec![];
+    for ((measure, time), mut row) in rows {
+        row.sort_by_key(|note| note.column);
+        row.dedup_by_key(|note| note.column);
+        chart.resize_with(chart.len().max(measure + 1), Vec::new);
+        chart[measure].push((row, time));
+    }
+    chart
+}
+
+pub(crate) fn value(fraction: Fraction) -> f64 {
+    f64::from(*fraction.numer()) / f64::from(*fraction.denom())
+}
+
+#[cfg(test)]
+pub(crate) fn row(notes: &[(crate::NoteType, usize)], position: Fraction) -> (Vec<Note>, Fraction) {
+    (
+        notes
+            .iter()
+            .map(|&(note_type, column)| Note::new(note_type, column))
+            .collect(),
+        position,
+    )
+}
+
+//The romanized fields match StepMania's transliterations when a unicode version exists
+pub fn with_translit(
+    unicode: Option<String>,
+    romanized: Option<String>,
+) -> (Option<String>, Option<String>) {
+    match unicode.filter(|unicode| Some(unicode) != romanized.as_ref()) {
+        Some(unicode) => (Some(unicode), romanized),
+        None => (romanized, None),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -60,12 +256,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_integer() {
+        assert_eq!(integer("12foo"), Ok(("foo", 12)));
+        assert_eq!(integer("-3.5"), Ok((".5", -3)));
+        assert!(integer("foo").is_err());
+    }
+
+    fn tags(input: &str, lenient: bool) -> Result<Vec<(&str, &str)>, ParseError> {
+        let mut tags = vec![];
+        let options = ParseOptions {
+            lenient,
+            ..ParseOptions::default()
+        };
+        for_each_tag(input, options, |tag, value, _| {
+            tags.push((tag, value));
+            Ok(())
+        })?;
+        Ok(tags)
+    }
+
     #[test]
     fn parse_sm_tag() {
         assert_eq!(
-            stepmania_tag("# foo  : bar  ;  baz"),
-            Ok(("  baz", ("foo", " bar  ")))
+            tags("# foo  : bar  ;  baz", false).unwrap(),
+            vec![("foo", " bar  ")]
+        );
+        assert_eq!(tags("#foo:bar;baz", false).unwrap(), vec![("foo", "bar")]);
+    }
+
+    #[test]
+    fn recover_unterminated_tags() {
+        let input = "#foo:bar\n  #baz:qux;#stray # \n#end:";
+        assert!(matches!(
+            tags(input, false),
+            Err(ParseError::UnterminatedTag(_))
+        ));
+        assert_eq!(
+            tags(input, true).unwrap(),
+            vec![("foo", "bar\n  "), ("baz", "qux"), ("end", "")]
         );
-        assert_eq!(stepmania_tag("#foo:bar;baz"), Ok(("baz", ("foo", "bar"))));
     }
 }
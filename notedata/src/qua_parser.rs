@@ -0,0 +1,195 @@
+use crate::{
+    keyboard_steps_type,
+    timed_notes::{TimedChart, TimedNote, TimingPoint},
+    BeatPair, ChartInfo, Difficulty, Fraction, NoteData,
+};
+use serde::{de::Error, Deserialize};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Qua {
+    audio_file: Option<String>,
+    background_file: Option<String>,
+    preview_time: Option<f64>,
+    mode: String,
+    #[serde(default)]
+    has_scratch_key: bool,
+    title: Option<String>,
+    artist: Option<String>,
+    source: Option<String>,
+    creator: Option<String>,
+    difficulty_name: Option<String>,
+    #[serde(default)]
+    timing_points: Vec<QuaTimingPoint>,
+    initial_scroll_velocity: Option<f64>,
+    #[serde(default)]
+    slider_velocities: Vec<QuaSliderVelocity>,
+    #[serde(default)]
+    hit_objects: Vec<QuaHitObject>,
+}
+
+//Quaver leaves out any field that is zero, so every number here has a default
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct QuaTimingPoint {
+    #[serde(default)]
+    start_time: f64,
+    #[serde(default)]
+    bpm: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct QuaSliderVelocity {
+    #[serde(default)]
+    start_time: f64,
+    #[serde(default)]
+    multiplier: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct QuaHitObject {
+    #[serde(default)]
+    start_time: f64,
+    lane: usize,
+    #[serde(default)]
+    end_time: f64,
+}
+
+pub fn parse(input: &str) -> Result<NoteData, serde_yaml::Error> {
+    let qua: Qua = serde_yaml::from_str(input)?;
+    //The scratch lane, when there is one, comes after the regular keys
+    let keys = qua
+        .mode
+        .strip_prefix("Keys")
+        .and_then(|keys| keys.parse::<usize>().ok())
+        .ok_or_else(|| serde_yaml::Error::custom(format!("unknown mode {}", qua.mode)))?
+        + usize::from(qua.has_scratch_key);
+    let mut nd = NoteData::new();
+    nd.meta.title = qua.title;
+    nd.meta.subtitle = qua.source;
+    nd.meta.artist = qua.artist;
+    nd.meta.music_path = qua.audio_file;
+    nd.meta.background_path = qua.background_file;
+    nd.meta.sample_start = qua.preview_time.map(|time| time / 1000.0);
+    let info = ChartInfo {
+        steps_type: keyboard_steps_type(keys),
+        description: qua.difficulty_name,
+        difficulty: Difficulty::Edit,
+        credit: qua.creator,
+        ..ChartInfo::default()
+    };
+
+    let notes: Vec<_> = qua
+        .hit_objects
+        .iter()
+        .filter(|object| (1..=keys).contains(&object.lane))
+        .map(|object| TimedNote {
+            column: object.lane - 1,
+            time: object.start_time,
+            end_time: Some(object.end_time).filter(|end| *end > 0.0),
+        })
+        .collect();
+    let timing_points = qua
+        .timing_points
+        .iter()
+        .map(|point| TimingPoint {
+            time: point.start_time,
+            bpm: point.bpm,
+        })
+        .collect();
+    let chart = match TimedChart::new(timing_points, &notes) {
+        Some(timed_chart) => {
+            timed_chart.write_structure(&mut nd.structure);
+            //Velocities can only start from the first measure, the last one before it applies there
+            let mut scrolls = vec![BeatPair::at_start(
+                qua.initial_scroll_velocity.unwrap_or(1.0),
+            )];
+            for velocity in &qua.slider_velocities {
+                let position = timed_chart
+                    .position(velocity.start_time)
+                    .max(Fraction::from(0));
+                if position == Fraction::from(0) {
+                    scrolls.clear();
+                }
+                scrolls.push(BeatPair::at_position(position, velocity.multiplier));
+            }
+            if !qua.slider_velocities.is_empty() {
+                nd.structure.scrolls = Some(scrolls);
+            }
+            timed_chart.chart(&notes)
+        }
+        None => vec![],
+    };
+    nd.charts.push((info, chart));
+    Ok(nd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Note, NoteType};
+
+    #[test]
+    fn parse_qua() {
+        let nd = parse(
+            "AudioFile: audio.mp3
+BackgroundFile: bg.png
+PreviewTime: 5000
+Mode: Keys7
+HasScratchKey: true
+Title: Song
+Artist: Someone
+Source: Game
+Creator: mapper
+DifficultyName: Hard
+InitialScrollVelocity: 1
+TimingPoints:
+- StartTime: 1000
+  Bpm: 120
+SliderVelocities:
+- StartTime: 1000
+  Multiplier: 0.5
+- StartTime: 2000
+  Multiplier: 2
+HitObjects:
+- StartTime: 1000
+  Lane: 8
+- StartTime: 1500
+  Lane: 1
+  EndTime: 2000
+  KeySounds: []
+",
+        )
+        .unwrap();
+        assert_eq!(nd.meta.title, Some("Song".to_owned()));
+        assert_eq!(nd.meta.subtitle, Some("Game".to_owned()));
+        assert_eq!(nd.meta.sample_start, Some(5.0));
+        assert_eq!(nd.structure.offset, Some(1.0));
+        assert_eq!(nd.structure.bpms, vec![BeatPair::at_start(120.0)]);
+        assert_eq!(
+            nd.structure.scrolls,
+            Some(vec![
+                BeatPair::at_start(0.5),
+                BeatPair::at_position(Fraction::new(1, 2), 2.0)
+            ])
+        );
+        let (info, chart) = &nd.charts[0];
+        assert_eq!(info.steps_type, "kb8-single");
+        assert_eq!(info.description, Some("Hard".to_owned()));
+        assert_eq!(
+            chart,
+            &vec![vec![
+                (vec![Note::new(NoteType::Tap, 7)], Fraction::from(0)),
+                (vec![Note::new(NoteType::Hold, 0)], Fraction::new(1, 4)),
+                (vec![Note::new(NoteType::HoldEnd, 0)], Fraction::new(1, 2)),
+            ]]
+        );
+    }
+
+    #[test]
+    fn reject_unknown_modes() {
+        assert!(parse("Mode: Osu\n").is_err());
+    }
+}
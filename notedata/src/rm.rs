@@ -0,0 +1,380 @@
+use crate::{
+    BackgroundChange, BeatPair, ChartInfo, ChartMetadata, DisplayBpm, Fraction, NoteData, NoteType,
+    StructureData,
+};
+use serde_cbor::Value;
+use std::{error, fmt, io};
+
+//Adding a field doesn't need a new version, readers default missing fields and skip unknown ones.
+//Bump this and add a step to upgrade when a field changes its meaning or shape.
+pub const FORMAT_VERSION: u16 = 2;
+const BINARY_MAGIC: &[u8] = b"RMCH";
+//The text header is a YAML comment, so the rest of the file stays a plain YAML document
+const TEXT_MAGIC: &str = "#rustmania chart ";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RmEncoding {
+    Binary,
+    Text,
+}
+
+#[derive(Debug)]
+pub enum RmError {
+    Io(io::Error),
+    UnsupportedVersion(u16),
+    Binary(serde_cbor::Error),
+    Text(serde_yaml::Error),
+    Legacy(bincode::Error),
+}
+
+impl error::Error for RmError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(error) => error.source(),
+            Self::UnsupportedVersion(_) => None,
+            Self::Binary(error) => error.source(),
+            Self::Text(error) => error.source(),
+            Self::Legacy(error) => error.source(),
+        }
+    }
+}
+
+impl fmt::Display for RmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(error) => error.fmt(f),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "chart format version {} is not supported", version)
+            }
+            Self::Binary(error) => error.fmt(f),
+            Self::Text(error) => error.fmt(f),
+            Self::Legacy(error) => error.fmt(f),
+        }
+    }
+}
+
+impl From<io::Error> for RmError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<serde_cbor::Error> for RmError {
+    fn from(error: serde_cbor::Error) -> Self {
+        Self::Binary(error)
+    }
+}
+
+impl From<serde_yaml::Error> for RmError {
+    fn from(error: serde_yaml::Error) -> Self {
+        Self::Text(error)
+    }
+}
+
+impl From<bincode::Error> for RmError {
+    fn from(error: bincode::Error) -> Self {
+        Self::Legacy(error)
+    }
+}
+
+pub fn write_rm(
+    data: &NoteData,
+    mut writer: impl io::Write,
+    encoding: RmEncoding,
+) -> Result<(), RmError> {
+    match encoding {
+        RmEncoding::Binary => {
+            let mut output = BINARY_MAGIC.to_vec();
+            output.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+            output.extend(serde_cbor::to_vec(data)?);
+            writer.write_all(&output)?;
+        }
+        RmEncoding::Text => {
+            let document = serde_yaml::to_string(data)?;
+            write!(writer, "{}{}\n{}\n", TEXT_MAGIC, FORMAT_VERSION, document)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_rm(input: &[u8]) -> Result<NoteData, RmError> {
+    if let Some(rest) = input.strip_prefix(BINARY_MAGIC) {
+        let (version, document) = match rest {
+            [low, high, document @ ..] => (u16::from_le_bytes([*low, *high]), document),
+            _ => return Err(RmError::UnsupportedVersion(0)),
+        };
+        return upgrade(version, serde_cbor::from_slice(document)?);
+    }
+    let text = std::str::from_utf8(input).ok();
+    if let Some(rest) = text.and_then(|text| text.strip_prefix(TEXT_MAGIC)) {
+        let (version, document) = rest.split_at(rest.find('\n').unwrap_or(rest.len()));
+        let version = version
+            .trim()
+            .parse()
+            .map_err(|_| RmError::UnsupportedVersion(0))?;
+        return upgrade(version, serde_yaml::from_str(document)?);
+    }
+    //Files without a header are raw dumps from before the format had a version
+    Ok(bincode::deserialize::<legacy::NoteData>(input)?.into())
+}
+
+//Upgrades a document written by an older version of the format, one version at a time
+fn upgrade(version: u16, document: Value) -> Result<NoteData, RmError> {
+    match version {
+        1 => upgrade(2, upgrade_background_changes(document)?),
+        FORMAT_VERSION => Ok(serde_cbor::value::from_value(document)?),
+        _ => Err(RmError::UnsupportedVersion(version)),
+    }
+}
+
+//Version 1 background changes were only a file name
+fn upgrade_background_changes(mut document: Value) -> Result<Value, RmError> {
+    let key = |name: &str| Value::Text(name.to_owned());
+    if let Value::Map(data) = &mut document {
+        if let Some(Value::Map(meta)) = data.get_mut(&key("meta")) {
+            for tag in &["background_changes", "foreground_changes"] {
+                if let Some(changes) = meta.get_mut(&key(tag)) {
+                    let files = serde_cbor::value::from_value(changes.clone())?;
+                    *changes = serde_cbor::value::to_value(from_files(files))?;
+                }
+            }
+        }
+    }
+    Ok(document)
+}
+
+fn from_files(files: Option<Vec<BeatPair<String>>>) -> Option<Vec<BeatPair<BackgroundChange>>> {
+    files.map(|files| {
+        files
+            .into_iter()
+            .map(|pair| BeatPair {
+                beat: pair.beat,
+                sub_beat: pair.sub_beat,
+                value: BackgroundChange::new(pair.value),
+            })
+            .collect()
+    })
+}
+
+//The layout .rm files had when they were a plain bincode dump, frozen so they can still be read
+mod legacy {
+    use super::{BeatPair, DisplayBpm, Fraction, NoteType};
+    use serde::Deserialize;
+    #[cfg(test)]
+    use serde::Serialize;
+
+    #[derive(Deserialize)]
+    #[cfg_attr(test, derive(Serialize))]
+    pub struct Note {
+        pub note_type: NoteType,
+        pub column: usize,
+    }
+
+    pub type Chart = Vec<Vec<(Vec<Note>, Fraction)>>;
+
+    #[derive(Deserialize)]
+    #[cfg_attr(test, derive(Serialize, Default))]
+    pub struct ChartMetadata {
+        pub title: Option<String>,
+        pub subtitle: Option<String>,
+        pub artist: Option<String>,
+        pub title_translit: Option<String>,
+        pub subtitle_translit: Option<String>,
+        pub artist_translit: Option<String>,
+        pub genre: Option<String>,
+        pub credit: Option<String>,
+        pub banner_path: Option<String>,
+        pub background_path: Option<String>,
+        pub lyrics_path: Option<String>,
+        pub cd_title: Option<String>,
+        pub music_path: Option<String>,
+        pub sample_start: Option<f64>,
+        pub sample_length: Option<f64>,
+        pub display_bpm: Option<DisplayBpm>,
+        pub selectable: Option<String>,
+        pub background_changes: Option<Vec<BeatPair<String>>>,
+        pub foreground_changes: Option<Vec<BeatPair<String>>>,
+    }
+
+    #[derive(Deserialize)]
+    #[cfg_attr(test, derive(Serialize))]
+    pub struct StructureData {
+        pub offset: Option<f64>,
+        pub bpms: Vec<BeatPair<f64>>,
+        pub stops: Option<Vec<BeatPair<f64>>>,
+    }
+
+    #[derive(Deserialize)]
+    #[cfg_attr(test, derive(Serialize))]
+    pub struct NoteData {
+        pub charts: Vec<Chart>,
+        pub meta: ChartMetadata,
+        pub structure: StructureData,
+    }
+}
+
+impl From<legacy::NoteData> for NoteData {
+    fn from(data: legacy::NoteData) -> Self {
+        let meta = data.meta;
+        Self {
+            charts: data
+                .charts
+                .into_iter()
+                .map(|chart| {
+                    let chart = chart
+                        .into_iter()
+                        .map(|measure| {
+                            measure
+                                .into_iter()
+                                .map(|(row, time)| {
+                                    let row = row
+                                        .into_iter()
+                                        .map(|note| crate::Note::new(note.note_type, note.column))
+                                        .collect();
+                                    (row, time)
+                                })
+                                .collect()
+                        })
+                        .collect();
+                    (ChartInfo::new(), chart)
+                })
+                .collect(),
+            meta: ChartMetadata {
+                title: meta.title,
+                subtitle: meta.subtitle,
+                artist: meta.artist,
+                title_translit: meta.title_translit,
+                subtitle_translit: meta.subtitle_translit,
+                artist_translit: meta.artist_translit,
+                genre: meta.genre,
+                credit: meta.credit,
+                banner_path: meta.banner_path,
+                background_path: meta.background_path,
+                lyrics_path: meta.lyrics_path,
+                cd_title: meta.cd_title,
+                music_path: meta.music_path,
+                sample_start: meta.sample_start,
+                sample_length: meta.sample_length,
+                display_bpm: meta.display_bpm,
+                selectable: meta.selectable,
+                background_changes: from_files(meta.background_changes),
+                foreground_changes: from_files(meta.foreground_changes),
+                ..ChartMetadata::default()
+            },
+            structure: StructureData {
+                offset: data.structure.offset,
+                bpms: data.structure.bpms,
+                stops: data.structure.stops,
+                ..StructureData::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Difficulty, Note};
+
+    fn notedata() -> NoteData {
+        let mut data = NoteData::new();
+        data.meta.title = Some("Song".to_owned());
+        data.meta.display_bpm = Some(DisplayBpm::Range(120.0, 240.0));
+        data.structure.offset = Some(-0.25);
+        data.structure.bpms = vec![BeatPair::at_start(120.0)];
+        data.structure.warps = Some(vec![BeatPair::at_position(Fraction::new(3, 4), 0.5)]);
+        data.charts.push((
+            ChartInfo {
+                difficulty: Difficulty::Hard,
+                meter: Some(9),
+                ..ChartInfo::new()
+            },
+            vec![vec![
+                (vec![Note::new(NoteType::Hold, 2)], Fraction::new(1, 3)),
+                (vec![Note::new(NoteType::HoldEnd, 2)], Fraction::new(2, 3)),
+            ]],
+        ));
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        for &encoding in &[RmEncoding::Binary, RmEncoding::Text] {
+            let mut output = vec![];
+            write_rm(&notedata(), &mut output, encoding).unwrap();
+            assert_eq!(read_rm(&output).unwrap(), notedata());
+        }
+    }
+
+    #[test]
+    fn missing_and_unknown_fields() {
+        let text = format!(
+            "{}{}\nmeta:\n  title: Song\n  rating: 5\nstructure:\n  bpms: []\n",
+            TEXT_MAGIC, FORMAT_VERSION
+        );
+        let mut expected = NoteData::new();
+        expected.meta.title = Some("Song".to_owned());
+        assert_eq!(read_rm(text.as_bytes()).unwrap(), expected);
+    }
+
+    #[test]
+    fn read_version_1_background_changes() {
+        let text = format!(
+            "{}1\nmeta:\n  background_changes:\n    - beat: 1\n      sub_beat: [1, 2]\n      \
+             value: movie.avi\nstructure:\n  bpms: []\n",
+            TEXT_MAGIC
+        );
+        let mut expected = NoteData::new();
+        expected.meta.background_changes = Some(vec![BeatPair::at_position(
+            Fraction::new(3, 2),
+            BackgroundChange::new("movie.avi".to_owned()),
+        )]);
+        assert_eq!(read_rm(text.as_bytes()).unwrap(), expected);
+    }
+
+    #[test]
+    fn reject_newer_versions() {
+        let mut output = BINARY_MAGIC.to_vec();
+        output.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        output.extend(serde_cbor::to_vec(&notedata()).unwrap());
+        assert!(matches!(
+            read_rm(&output),
+            Err(RmError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn read_legacy_dumps() {
+        let legacy_dump = bincode::serialize(&legacy::NoteData {
+            charts: vec![vec![vec![(
+                vec![legacy::Note {
+                    note_type: NoteType::Tap,
+                    column: 1,
+                }],
+                Fraction::new(1, 4),
+            )]]],
+            meta: legacy::ChartMetadata {
+                title: Some("Song".to_owned()),
+                ..legacy::ChartMetadata::default()
+            },
+            structure: legacy::StructureData {
+                offset: Some(0.5),
+                bpms: vec![BeatPair::at_start(150.0)],
+                stops: None,
+            },
+        })
+        .unwrap();
+        let mut expected = NoteData::new();
+        expected.meta.title = Some("Song".to_owned());
+        expected.structure.offset = Some(0.5);
+        expected.structure.bpms = vec![BeatPair::at_start(150.0)];
+        expected.charts.push((
+            ChartInfo::new(),
+            vec![vec![(
+                vec![Note::new(NoteType::Tap, 1)],
+                Fraction::new(1, 4),
+            )]],
+        ));
+        assert_eq!(read_rm(&legacy_dump).unwrap(), expected);
+    }
+}
@@ -1,21 +1,25 @@
 use crate::{
-    parser_generic::{beat_pair, comma_separated, stepmania_tag, ws_trimmed},
-    Chart, DisplayBpm, Measure, Note, NoteData, NoteRow, NoteType,
+    error::{ParseError, Problem, ValueError},
+    parser_generic::{comma_separated, for_each_tag, integer, number, ws_trimmed, Warnings},
+    Attack, BackgroundChange, Chart, ChartInfo, ChartMetadata, ChartSummary, Combo, Difficulty,
+    DisplayBpm, Measure, Note, NoteData, NoteRow, NoteType, ParseOptions, SongHeader, Speed,
+    SpeedUnit, StructureData, TimeSignature,
 };
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until},
-    character::complete::{char, multispace1, none_of, not_line_ending},
-    combinator::map,
+    bytes::complete::{is_not, tag, take_until},
+    character::complete::{char, digit1, multispace1, none_of, not_line_ending},
+    combinator::{all_consuming, map, map_res, opt},
     error::ErrorKind,
-    multi::{count, fold_many0, fold_many1, many0, separated_nonempty_list},
+    multi::{fold_many0, fold_many1, many0, many1, separated_nonempty_list},
     number::complete::double,
-    sequence::{preceded, separated_pair, terminated},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     Err, IResult,
 };
 use num_rational::Rational32;
+use std::collections::HashSet;
 
-fn display_bpm(input: &str) -> IResult<&str, DisplayBpm> {
+pub fn display_bpm(input: &str) -> IResult<&str, DisplayBpm> {
     alt((
         map(
             separated_pair(double, ws_trimmed(char(':')), double),
@@ -26,12 +30,193 @@ fn display_bpm(input: &str) -> IResult<&str, DisplayBpm> {
     ))(input)
 }
 
+pub fn into_difficulty(name: &str) -> Difficulty {
+    match name.to_lowercase().as_str() {
+        "beginner" => Difficulty::Beginner,
+        "easy" | "basic" | "light" => Difficulty::Easy,
+        "medium" | "another" | "trick" | "standard" | "difficult" => Difficulty::Medium,
+        "hard" | "ssr" | "maniac" | "heavy" => Difficulty::Hard,
+        "challenge" | "smaniac" | "expert" | "oni" => Difficulty::Challenge,
+        _ => Difficulty::Edit,
+    }
+}
+
+fn header_field(input: &str) -> IResult<&str, &str> {
+    map(terminated(take_until(":"), char(':')), str::trim)(input)
+}
+
+fn chart_info(input: &str) -> Result<(&str, ChartInfo), ValueError<'_>> {
+    let (input, (steps_type, description, difficulty, meter, radar_values)) = tuple((
+        header_field,
+        header_field,
+        header_field,
+        header_field,
+        header_field,
+    ))(input)?;
+    Ok((
+        input,
+        ChartInfo {
+            steps_type: steps_type.to_owned(),
+            description: if description.is_empty() {
+                None
+            } else {
+                Some(description.to_owned())
+            },
+            difficulty: into_difficulty(difficulty),
+            meter: if meter.is_empty() {
+                None
+            } else {
+                Some(
+                    all_consuming(map_res(digit1, str::parse))(meter)
+                        .map_err(ValueError::bad_number)?
+                        .1,
+                )
+            },
+            radar_values: if radar_values.is_empty() {
+                vec![]
+            } else {
+                all_consuming(comma_separated(double))(radar_values)
+                    .map_err(ValueError::bad_number)?
+                    .1
+            },
+            chart_name: None,
+            credit: None,
+            structure: None,
+        },
+    ))
+}
+
+fn time_signature(input: &str) -> IResult<&str, TimeSignature> {
+    map(
+        separated_pair(integer, ws_trimmed(char('=')), integer),
+        |(numerator, denominator)| TimeSignature {
+            numerator,
+            denominator,
+        },
+    )(input)
+}
+
+fn combo(input: &str) -> IResult<&str, Combo> {
+    map(
+        tuple((integer, opt(preceded(ws_trimmed(char('=')), integer)))),
+        |(hit, miss)| Combo {
+            hit,
+            miss: miss.unwrap_or(hit),
+        },
+    )(input)
+}
+
+fn speed(input: &str) -> IResult<&str, Speed> {
+    map(
+        tuple((
+            double,
+            preceded(ws_trimmed(char('=')), double),
+            opt(preceded(ws_trimmed(char('=')), integer)),
+        )),
+        |(ratio, length, unit)| Speed {
+            ratio,
+            length,
+            unit: if unit == Some(1) {
+                SpeedUnit::Seconds
+            } else {
+                SpeedUnit::Beats
+            },
+        },
+    )(input)
+}
+
+fn label(input: &str) -> IResult<&str, String> {
+    map(is_not(","), |label: &str| label.trim().to_owned())(input)
+}
+
+//The file comes first and the rest of a change is optional, like beat=file=rate=crossfade=
+//stretchrewind=stretchnoloop=effect=file2=transition=color1=color2 in StepMania 5
+fn background_change(input: &str) -> IResult<&str, BackgroundChange> {
+    let (rest, change) = is_not(",")(input)?;
+    let fields: Vec<_> = change.split('=').map(str::trim).collect();
+    let field = |index| fields.get(index).copied().unwrap_or("");
+    let text = |index| {
+        Some(field(index))
+            .filter(|text| !text.is_empty())
+            .map(str::to_owned)
+    };
+    let flag = |index| !matches!(field(index), "" | "0");
+    let rate = match field(1) {
+        "" => 1.0,
+        rate => rate
+            .parse()
+            .map_err(|_| Err::Error((input, ErrorKind::Float)))?,
+    };
+    Ok((
+        rest,
+        BackgroundChange {
+            file: field(0).to_owned(),
+            rate,
+            crossfade: flag(2),
+            stretch_rewind: flag(3),
+            stretch_no_loop: flag(4),
+            effect: text(5),
+            file2: text(6),
+            transition: text(7),
+            color1: text(8),
+            color2: text(9),
+        },
+    ))
+}
+
+//Attacks are KEY=value fields separated by colons, where each TIME starts a new attack and its
+//length is given either as LEN or as the END time
+fn attacks<'a>(value: &'a str, warnings: &mut Warnings<'a>) -> Result<Vec<Attack>, ValueError<'a>> {
+    let mut attacks: Vec<Attack> = vec![];
+    for field in value.split(':').filter(|field| !field.trim().is_empty()) {
+        let parsed = match field.find('=').map(|index| field.split_at(index)) {
+            Some((key, rest)) => {
+                let argument = rest[1..].trim();
+                let number = || argument.parse::<f64>().ok();
+                match (key.trim().to_uppercase().as_str(), attacks.last_mut()) {
+                    ("TIME", _) => number().map(|time| {
+                        attacks.push(Attack {
+                            time,
+                            length: 0.0,
+                            mods: vec![],
+                        })
+                    }),
+                    ("LEN", Some(attack)) => number().map(|length| attack.length = length),
+                    ("END", Some(attack)) => number().map(|end| attack.length = end - attack.time),
+                    ("MODS", Some(attack)) => {
+                        attack.mods = argument
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|name| !name.is_empty())
+                            .map(str::to_owned)
+                            .collect();
+                        Some(())
+                    }
+                    _ => None,
+                }
+            }
+            None => None,
+        };
+        if parsed.is_none() {
+            warnings.recover(ValueError::at(Problem::MalformedPair, field))?;
+        }
+    }
+    Ok(attacks)
+}
+
+//Unknown characters are a failure, so they can't be mistaken for the end of a measure
 fn notetype(input: &str) -> IResult<&str, Option<NoteType>> {
-    map(none_of("\r\n,"), into_sm_notetype)(input)
+    let (rest, sm_char) = none_of(" \t\r\n,")(input)?;
+    match into_sm_notetype(sm_char) {
+        Some(notetype) => Ok((rest, notetype)),
+        None => Err(Err::Failure((input, ErrorKind::Char))),
+    }
 }
 
-fn into_sm_notetype(sm_char: char) -> Option<NoteType> {
-    match sm_char {
+//Attack (A) notes are read as empty until they can be played
+fn into_sm_notetype(sm_char: char) -> Option<Option<NoteType>> {
+    Some(match sm_char {
+        '0' | 'A' => None,
         '1' => Some(NoteType::Tap),
         '2' => Some(NoteType::Hold),
         '3' => Some(NoteType::HoldEnd),
@@ -39,19 +224,32 @@ fn into_sm_notetype(sm_char: char) -> Option<NoteType> {
         'M' => Some(NoteType::Mine),
         'L' => Some(NoteType::Lift),
         'F' => Some(NoteType::Fake),
-        _ => None,
-    }
+        'K' => Some(NoteType::AutoKeysound),
+        _ => return None,
+    })
+}
+
+//SM5 follows a note with its keysound's index in brackets, like 1[3]
+fn keysound(input: &str) -> IResult<&str, usize> {
+    delimited(char('['), map_res(digit1, str::parse), char(']'))(input)
 }
 
 fn noterow(input: &str) -> IResult<&str, NoteRow> {
     map(
-        fold_many1(notetype, (vec![], 0), |(mut noterow, mut index), item| {
-            if let Some(item) = item {
-                noterow.push(Note::new(item, index))
-            }
-            index += 1;
-            (noterow, index)
-        }),
+        fold_many1(
+            pair(notetype, opt(keysound)),
+            (vec![], 0),
+            |(mut noterow, mut index), (item, keysound)| {
+                if let Some(item) = item {
+                    noterow.push(Note {
+                        keysound,
+                        ..Note::new(item, index)
+                    })
+                }
+                index += 1;
+                (noterow, index)
+            },
+        ),
         |(noterow, _)| noterow,
     )(input)
 }
@@ -59,7 +257,7 @@ fn noterow(input: &str) -> IResult<&str, NoteRow> {
 fn measure(input: &str) -> IResult<&str, Measure> {
     map(
         fold_many0(
-            terminated(noterow, multispace1),
+            terminated(noterow, many1(alt((multispace1, comment)))),
             (vec![], 0),
             |(mut noterows, mut index), item| {
                 if !item.is_empty() {
@@ -78,12 +276,9 @@ fn measure(input: &str) -> IResult<&str, Measure> {
     )(input)
 }
 
-fn chart(input: &str) -> IResult<&str, Chart> {
+pub fn measures(input: &str) -> IResult<&str, Chart> {
     preceded(
-        terminated(
-            count(terminated(take_until(":"), char(':')), 5),
-            many0(alt((comment, multispace1))),
-        ),
+        many0(alt((comment, multispace1))),
         separated_nonempty_list(
             preceded(
                 many0(alt((comment, multispace1))),
@@ -94,62 +289,238 @@ fn chart(input: &str) -> IResult<&str, Chart> {
     )(input)
 }
 
+//The characters of a line outside of its comment and any keysound brackets
+fn note_chars(line: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let code = line.find("//").map_or(line, |comment| &line[..comment]);
+    let mut bracketed = false;
+    code.char_indices().filter(move |&(_, c)| match c {
+        '[' => {
+            bracketed = true;
+            false
+        }
+        ']' if bracketed => {
+            bracketed = false;
+            false
+        }
+        _ => !bracketed,
+    })
+}
+
+//Every character outside of comments that isn't a note or a separator
+fn unknown_notes(notes: &str) -> Vec<usize> {
+    let mut unknown = vec![];
+    let mut line_start = 0;
+    for line in notes.split('\n') {
+        unknown.extend(
+            note_chars(line)
+                .filter(|&(_, c)| !c.is_whitespace() && c != ',' && into_sm_notetype(c).is_none())
+                .map(|(index, _)| line_start + index),
+        );
+        line_start += line.len() + 1;
+    }
+    unknown
+}
+
+//Lenient parsing reads unknown characters as empty columns
+pub fn notes<'a>(notes: &'a str, warnings: &mut Warnings<'a>) -> Result<Chart, ValueError<'a>> {
+    let unknown = unknown_notes(notes);
+    if unknown.is_empty() {
+        return Ok(measures(notes).map_err(ValueError::unknown_note)?.1);
+    }
+    for &index in &unknown {
+        warnings.recover(ValueError::at(Problem::UnknownNote, &notes[index..]))?;
+    }
+    let known: String = notes
+        .char_indices()
+        .map(|(index, c)| {
+            if unknown.binary_search(&index).is_ok() {
+                '0'
+            } else {
+                c
+            }
+        })
+        .collect();
+    measures(&known)
+        .map(|(_, chart)| chart)
+        .map_err(|_| ValueError::at(Problem::UnknownNote, notes))
+}
+
+fn chart<'a>(
+    input: &'a str,
+    warnings: &mut Warnings<'a>,
+) -> Result<(ChartInfo, Chart), ValueError<'a>> {
+    let (input, info) = chart_info(input)?;
+    Ok((info, notes(input, warnings)?))
+}
+
 fn comment(input: &str) -> IResult<&str, &str> {
     preceded(tag("//"), not_line_ending)(input)
 }
 
-fn notedata(input: &str) -> IResult<&str, NoteData> {
-    let mut input = input;
-    let mut nd = NoteData::new();
+pub fn metadata_tag<'a>(
+    meta: &mut ChartMetadata,
+    tag: &str,
+    value: &'a str,
+    warnings: &mut Warnings<'a>,
+) -> Result<(), ValueError<'a>> {
+    match tag {
+        "TITLE" => meta.title = Some(value.to_owned()),
+        "SUBTITLE" => meta.subtitle = Some(value.to_owned()),
+        "ARTIST" => meta.artist = Some(value.to_owned()),
+        "TITLETRANSLIT" => meta.title_translit = Some(value.to_owned()),
+        "SUBTITLETRANSLIT" => meta.subtitle_translit = Some(value.to_owned()),
+        "ARTISTTRANSLIT" => meta.artist_translit = Some(value.to_owned()),
+        "GENRE" => meta.genre = Some(value.to_owned()),
+        "CREDIT" => meta.credit = Some(value.to_owned()),
+        "BANNER" => meta.banner_path = Some(value.to_owned()),
+        "BACKGROUND" => meta.background_path = Some(value.to_owned()),
+        "LYRICSPATH" => meta.lyrics_path = Some(value.to_owned()),
+        "CDTITLE" => meta.cd_title = Some(value.to_owned()),
+        "MUSIC" => meta.music_path = Some(value.to_owned()),
+        "SAMPLESTART" => meta.sample_start = Some(number(value)?),
+        "SAMPLELENGTH" => meta.sample_length = Some(number(value)?),
+        "DISPLAYBPM" => {
+            meta.display_bpm = Some(
+                ws_trimmed(display_bpm)(value)
+                    .map_err(ValueError::bad_number)?
+                    .1,
+            )
+        }
+        "BGCHANGES" => {
+            meta.background_changes = Some(warnings.pairs(background_change, 4.0, value)?)
+        }
+        "FGCHANGES" => {
+            meta.foreground_changes = Some(warnings.pairs(background_change, 4.0, value)?)
+        }
+        "ATTACKS" => meta.attacks = Some(attacks(value, warnings)?),
+        "KEYSOUNDS" => {
+            meta.keysounds = value
+                .split(',')
+                .map(str::trim)
+                .filter(|keysound| !keysound.is_empty())
+                .map(str::to_owned)
+                .collect()
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+//Returns whether the tag was a timing tag, since .ssc charts need to know when to keep their own
+pub fn structure_tag<'a>(
+    structure: &mut StructureData,
+    tag: &str,
+    value: &'a str,
+    warnings: &mut Warnings<'a>,
+) -> Result<bool, ValueError<'a>> {
+    match tag {
+        "OFFSET" => structure.offset = Some(-number(value)?),
+        "BPMS" => structure.bpms = warnings.pairs(double, 4.0, value)?,
+        "STOPS" | "FREEZES" => structure.stops = Some(warnings.pairs(double, 4.0, value)?),
+        "DELAYS" => structure.delays = Some(warnings.pairs(double, 4.0, value)?),
+        "WARPS" => structure.warps = Some(warnings.pairs(double, 4.0, value)?),
+        "TIMESIGNATURES" => {
+            structure.time_signatures = Some(warnings.pairs(time_signature, 4.0, value)?)
+        }
+        "TICKCOUNTS" => structure.tick_counts = Some(warnings.pairs(integer, 4.0, value)?),
+        "COMBOS" => structure.combos = Some(warnings.pairs(combo, 4.0, value)?),
+        "SPEEDS" => structure.speeds = Some(warnings.pairs(speed, 4.0, value)?),
+        "SCROLLS" => structure.scrolls = Some(warnings.pairs(double, 4.0, value)?),
+        "FAKES" => structure.fakes = Some(warnings.pairs(double, 4.0, value)?),
+        "LABELS" => structure.labels = Some(warnings.pairs(label, 4.0, value)?),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+//Counts the notes a player hits without checking the rest of the chart is valid
+pub fn count_notes(notes: &str) -> usize {
+    notes
+        .lines()
+        .flat_map(note_chars)
+        .filter(|&(_, c)| {
+            into_sm_notetype(c)
+                .flatten()
+                .filter(|note| note.is_hit())
+                .is_some()
+        })
+        .count()
+}
 
-    while let Ok((output, (tag, value))) = preceded(take_until("#"), stepmania_tag)(input) {
-        input = output;
+//The song level tags and the charts read from a file, with any warnings
+pub type Tags<C> = (ChartMetadata, StructureData, Vec<C>, Vec<ParseError>);
 
+//The full parse and the header parse only differ in what they make of each #NOTES tag
+fn parse_tags<'a, C>(
+    input: &'a str,
+    options: ParseOptions,
+    mut chart: impl FnMut(&'a str, &mut Warnings<'a>) -> Result<C, ValueError<'a>>,
+) -> Result<Tags<C>, ParseError> {
+    let mut meta = ChartMetadata::new();
+    let mut structure = StructureData::default();
+    let mut charts = vec![];
+    let mut seen = HashSet::new();
+    let warnings = for_each_tag(input, options, |tag, value, warnings| {
         if !value.trim().is_empty() {
-            match tag {
-                "TITLE" => nd.meta.title = Some(value.to_owned()),
-                "SUBTITLE" => nd.meta.subtitle = Some(value.to_owned()),
-                "ARTIST" => nd.meta.artist = Some(value.to_owned()),
-                "TITLETRANSLIT" => nd.meta.title_translit = Some(value.to_owned()),
-                "SUBTITLETRANSLIT" => nd.meta.subtitle_translit = Some(value.to_owned()),
-                "ARTISTTRANSLIT" => nd.meta.artist_translit = Some(value.to_owned()),
-                "GENRE" => nd.meta.genre = Some(value.to_owned()),
-                "CREDIT" => nd.meta.credit = Some(value.to_owned()),
-                "BANNER" => nd.meta.banner_path = Some(value.to_owned()),
-                "BACKGROUND" => nd.meta.background_path = Some(value.to_owned()),
-                "LYRICSPATH" => nd.meta.lyrics_path = Some(value.to_owned()),
-                "CDTITLE" => nd.meta.cd_title = Some(value.to_owned()),
-                "MUSIC" => nd.meta.music_path = Some(value.to_owned()),
-                "SAMPLESTART" => nd.meta.sample_start = Some(ws_trimmed(double)(value)?.1),
-                "SAMPLELENGTH" => nd.meta.sample_length = Some(ws_trimmed(double)(value)?.1),
-                "OFFSET" => nd.structure.offset = Some(-ws_trimmed(double)(value)?.1),
-                "DISPLAYBPM" => nd.meta.display_bpm = Some(ws_trimmed(display_bpm)(value)?.1),
-                "BPMS" => {
-                    nd.structure.bpms =
-                        ws_trimmed(comma_separated(beat_pair(double, 4.0)))(value)?.1
-                }
-                "STOPS" => {
-                    nd.structure.stops =
-                        Some(ws_trimmed(comma_separated(beat_pair(double, 4.0)))(value)?.1)
-                }
-                "NOTES" => nd.charts.push(chart(value)?.1),
-                _ => {}
+            if tag == "NOTES" {
+                charts.push(chart(value, warnings)?);
+            } else {
+                warnings.duplicate(&mut seen, tag, value);
+                metadata_tag(&mut meta, tag, value, warnings)?;
+                structure_tag(&mut structure, tag, value, warnings)?;
             }
         }
-    }
-    Ok((input, nd))
+        Ok(())
+    })?;
+    Ok((meta, structure, charts, warnings))
 }
 
-pub fn parse(input: &str) -> Result<NoteData, Err<(&str, ErrorKind)>> {
-    notedata(input).map(|notedata| notedata.1)
+pub fn parse(
+    input: &str,
+    options: ParseOptions,
+) -> Result<(NoteData, Vec<ParseError>), ParseError> {
+    let (meta, structure, charts, warnings) = parse_tags(input, options, chart)?;
+    Ok((
+        NoteData {
+            charts,
+            meta,
+            structure,
+        },
+        warnings,
+    ))
+}
+
+pub fn parse_header(
+    input: &str,
+    options: ParseOptions,
+) -> Result<(SongHeader, Vec<ParseError>), ParseError> {
+    let (meta, structure, charts, warnings) = parse_tags(input, options, |value, _| {
+        let (notes, info) = chart_info(value)?;
+        Ok(ChartSummary {
+            info,
+            note_count: count_notes(notes),
+        })
+    })?;
+    Ok((
+        SongHeader {
+            meta,
+            structure,
+            charts,
+        },
+        warnings,
+    ))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{BeatPair, ChartMetadata, StructureData};
+    use crate::BeatPair;
     use nom::Err::Error;
 
+    fn strict<'a>() -> Warnings<'a> {
+        Warnings::new(ParseOptions::default())
+    }
+
     #[test]
     fn parse_display_bpm() {
         assert_eq!(
@@ -173,6 +544,10 @@ mod tests {
         assert_eq!(notetype("\rfoo"), Err(Error(("\rfoo", ErrorKind::NoneOf))));
         assert_eq!(notetype("\nfoo"), Err(Error(("\nfoo", ErrorKind::NoneOf))));
         assert_eq!(notetype(",foo"), Err(Error((",foo", ErrorKind::NoneOf))));
+        assert_eq!(
+            notetype("Xfoo"),
+            Err(Err::Failure(("Xfoo", ErrorKind::Char)))
+        );
     }
 
     #[test]
@@ -206,6 +581,116 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_measure_with_comments() {
+        assert_eq!(
+            measure("1000 // first\n// not a row\n0001\n"),
+            Ok((
+                "",
+                vec![
+                    (vec![Note::new(NoteType::Tap, 0)], Rational32::new(0, 2)),
+                    (vec![Note::new(NoteType::Tap, 3)], Rational32::new(1, 2)),
+                ]
+            ))
+        );
+    }
+
+    #[test]
+    fn locate_errors() {
+        let location = |error| match error {
+            ParseError::BadNumber(location)
+            | ParseError::UnknownNote(location)
+            | ParseError::MalformedPair(location)
+            | ParseError::UnterminatedTag(location) => {
+                (location.tag, location.line, location.column)
+            }
+            error => panic!("unexpected error {}", error),
+        };
+        let error = |input| parse(input, ParseOptions::default()).unwrap_err();
+        let bad_number = error("#TITLE:foo;\n#OFFSET:abc;");
+        assert_eq!(
+            bad_number.to_string(),
+            "bad number in OFFSET at line 2, column 9\n  | #OFFSET:abc;\n  |         ^"
+        );
+        assert_eq!(location(bad_number), ("OFFSET".to_owned(), 2, 9));
+        let unknown_note = error("#NOTES:dance-single::Hard:1::\n0000\n0X00\n;");
+        assert!(matches!(unknown_note, ParseError::UnknownNote(_)));
+        assert_eq!(location(unknown_note), ("NOTES".to_owned(), 3, 2));
+        let malformed_pair = error("#BPMS:0.000=120,\n4.000;");
+        assert!(matches!(malformed_pair, ParseError::MalformedPair(_)));
+        assert_eq!(location(malformed_pair), ("BPMS".to_owned(), 2, 1));
+        let unterminated = error("#TITLE:foo\n#ARTIST:bar;");
+        assert!(matches!(unterminated, ParseError::UnterminatedTag(_)));
+        assert_eq!(location(unterminated), ("TITLE".to_owned(), 1, 1));
+        assert_eq!(
+            location(error("#TITLE:foo;\n#ARTIST:bar")),
+            ("ARTIST".to_owned(), 2, 1)
+        );
+    }
+
+    #[test]
+    fn parse_leniently() {
+        let input =
+            "#TITLE:foo;\n#TITLE:bar;\n#DISPLAYBPM:fast;\n#BPMS:0=120,x,4=240;\n#OFFSET:1\n\
+                     #NOTES:dance-single::Hard:1::\n0X00\n0001\n;";
+        assert!(parse(input, ParseOptions::default()).is_err());
+        let lenient = ParseOptions {
+            lenient: true,
+            ..ParseOptions::default()
+        };
+        let (nd, warnings) = parse(input, lenient).unwrap();
+        assert_eq!(nd.meta.title, Some("bar".to_owned()));
+        assert_eq!(nd.meta.display_bpm, None);
+        assert_eq!(
+            nd.structure.bpms,
+            vec![
+                BeatPair::at_start(120.0),
+                BeatPair::from_pair(1.0, 240.0).unwrap()
+            ]
+        );
+        assert_eq!(nd.structure.offset, Some(-1.0));
+        assert_eq!(
+            nd.charts[0].1,
+            vec![vec![(
+                vec![Note::new(NoteType::Tap, 3)],
+                Rational32::new(1, 2)
+            )]]
+        );
+        let summaries: Vec<_> = warnings
+            .iter()
+            .map(|warning| warning.to_string().lines().next().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            summaries,
+            vec![
+                "duplicate tag in TITLE at line 2, column 8",
+                "bad number in DISPLAYBPM at line 3, column 13",
+                "malformed beat=value pair in BPMS at line 4, column 13",
+                "unterminated tag in OFFSET at line 5, column 1",
+                "unknown note in NOTES at line 7, column 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_header_only() {
+        let input = "#TITLE:foo;\n#BPMS:0=120;\n\
+                     #NOTES:dance-single::Hard:5::\n1200 // 1000\n0300\nM00L\n,\n0F14\n;\n\
+                     #NOTES:dance-double::Easy:::\n00000000\n;";
+        let (header, _) = parse_header(input, ParseOptions::default()).unwrap();
+        let (nd, _) = parse(input, ParseOptions::default()).unwrap();
+        assert_eq!(header, SongHeader::from(&nd));
+        assert_eq!(header.charts[0].info.meter, Some(5));
+        assert_eq!(header.charts[0].note_count, 5);
+        assert_eq!(header.charts[1].note_count, 0);
+        //Notes aren't checked, so a broken chart still has a header
+        assert!(parse_header(
+            "#NOTES:dance-single::Hard:5::\n1X00\n;",
+            ParseOptions::default()
+        )
+        .is_ok());
+    }
+
     #[test]
     fn parse_comment() {
         assert_eq!(comment("// foo\nbar"), Ok(("\nbar", " foo")));
@@ -227,10 +712,20 @@ mod tests {
                  0000\n \
                  0000\n \
                  0010\n \
-                 0000\n"
+                 0000\n",
+                &mut strict()
             ),
             Ok((
-                "",
+                ChartInfo {
+                    steps_type: "foo".to_owned(),
+                    description: None,
+                    difficulty: Difficulty::Edit,
+                    meter: None,
+                    radar_values: vec![0.0, 0.0],
+                    chart_name: None,
+                    credit: None,
+                    structure: None,
+                },
                 vec![
                     vec![(vec![Note::new(NoteType::Tap, 1)], Rational32::new(1, 4))],
                     vec![(vec![Note::new(NoteType::Tap, 2)], Rational32::new(1, 2))],
@@ -239,10 +734,232 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_chart_info() {
+        assert_eq!(
+            chart_info(
+                "
+                 dance-double:
+                 Blank description:
+                 Challenge:
+                 12:
+                 0.5,0.25,0.125,0.0,1.0:foo"
+            ),
+            Ok((
+                "foo",
+                ChartInfo {
+                    steps_type: "dance-double".to_owned(),
+                    description: Some("Blank description".to_owned()),
+                    difficulty: Difficulty::Challenge,
+                    meter: Some(12),
+                    radar_values: vec![0.5, 0.25, 0.125, 0.0, 1.0],
+                    chart_name: None,
+                    credit: None,
+                    structure: None,
+                }
+            ))
+        );
+        assert_eq!(into_difficulty("SMANIAC"), Difficulty::Challenge);
+        assert_eq!(into_difficulty("Heavy"), Difficulty::Hard);
+        assert!(chart_info("dance-single::Hard:x:::").is_err());
+    }
+
+    #[test]
+    fn parse_segments() {
+        let mut structure = StructureData::default();
+        for (tag, value) in &[
+            ("DELAYS", "4.000=0.500"),
+            ("WARPS", "8.000=2.000"),
+            ("TIMESIGNATURES", "0.000=4=4,16.000=7=8"),
+            ("TICKCOUNTS", "0.000=4"),
+            ("COMBOS", "0.000=1,4.000=2=3"),
+            ("SPEEDS", "0.000=1.000=0.000=0,2.000=0.5=1.5=1,6.000=2=1"),
+            ("SCROLLS", "0.000=1.000,12.000=-1.000"),
+            ("FAKES", "2.000=1.000"),
+            ("LABELS", "0.000=Song Start,32.000=Drop"),
+        ] {
+            assert_eq!(
+                structure_tag(&mut structure, tag, value, &mut strict()),
+                Ok(true)
+            );
+        }
+        assert_eq!(
+            structure_tag(&mut structure, "TITLE", "foo", &mut strict()),
+            Ok(false)
+        );
+        assert_eq!(
+            structure,
+            StructureData {
+                offset: None,
+                bpms: vec![],
+                stops: None,
+                delays: Some(vec![BeatPair::from_pair(1.0, 0.5).unwrap()]),
+                warps: Some(vec![BeatPair::from_pair(2.0, 2.0).unwrap()]),
+                time_signatures: Some(vec![
+                    BeatPair::at_start(TimeSignature {
+                        numerator: 4,
+                        denominator: 4
+                    }),
+                    BeatPair::from_pair(
+                        4.0,
+                        TimeSignature {
+                            numerator: 7,
+                            denominator: 8
+                        }
+                    )
+                    .unwrap()
+                ]),
+                tick_counts: Some(vec![BeatPair::at_start(4)]),
+                combos: Some(vec![
+                    BeatPair::at_start(Combo { hit: 1, miss: 1 }),
+                    BeatPair::from_pair(1.0, Combo { hit: 2, miss: 3 }).unwrap()
+                ]),
+                speeds: Some(vec![
+                    BeatPair::at_start(Speed {
+                        ratio: 1.0,
+                        length: 0.0,
+                        unit: SpeedUnit::Beats
+                    }),
+                    BeatPair::from_pair(
+                        0.5,
+                        Speed {
+                            ratio: 0.5,
+                            length: 1.5,
+                            unit: SpeedUnit::Seconds
+                        }
+                    )
+                    .unwrap(),
+                    BeatPair::from_pair(
+                        1.5,
+                        Speed {
+                            ratio: 2.0,
+                            length: 1.0,
+                            unit: SpeedUnit::Beats
+                        }
+                    )
+                    .unwrap()
+                ]),
+                scrolls: Some(vec![
+                    BeatPair::at_start(1.0),
+                    BeatPair::from_pair(3.0, -1.0).unwrap()
+                ]),
+                fakes: Some(vec![BeatPair::from_pair(0.5, 1.0).unwrap()]),
+                labels: Some(vec![
+                    BeatPair::at_start("Song Start".to_owned()),
+                    BeatPair::from_pair(8.0, "Drop".to_owned()).unwrap()
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_effects() {
+        let mut meta = ChartMetadata::new();
+        for (tag, value) in &[
+            (
+                "BGCHANGES",
+                "0.000=intro.avi=1.000=0=0=1,\n16.000=b.png=0.5=1=0=0=Fade=c.png==1^0^0^1=",
+            ),
+            ("FGCHANGES", "8.000=lua"),
+            (
+                "ATTACKS",
+                "TIME=1.5:LEN=2:MODS=drunk, 2x:\ntime=4:END=5.25:MODS=*1 50% wave",
+            ),
+        ] {
+            assert_eq!(metadata_tag(&mut meta, tag, value, &mut strict()), Ok(()));
+        }
+        let mut intro = BackgroundChange::new("intro.avi".to_owned());
+        intro.stretch_no_loop = true;
+        let faded = BackgroundChange {
+            rate: 0.5,
+            crossfade: true,
+            effect: Some("Fade".to_owned()),
+            file2: Some("c.png".to_owned()),
+            color1: Some("1^0^0^1".to_owned()),
+            ..BackgroundChange::new("b.png".to_owned())
+        };
+        assert_eq!(
+            meta.background_changes,
+            Some(vec![
+                BeatPair::at_start(intro),
+                BeatPair::from_pair(4.0, faded).unwrap()
+            ])
+        );
+        assert_eq!(
+            meta.foreground_changes,
+            Some(vec![BeatPair::from_pair(
+                2.0,
+                BackgroundChange::new("lua".to_owned())
+            )
+            .unwrap()])
+        );
+        assert_eq!(
+            meta.attacks,
+            Some(vec![
+                Attack {
+                    time: 1.5,
+                    length: 2.0,
+                    mods: vec!["drunk".to_owned(), "2x".to_owned()],
+                },
+                Attack {
+                    time: 4.0,
+                    length: 1.25,
+                    mods: vec!["*1 50% wave".to_owned()],
+                },
+            ])
+        );
+        assert!(metadata_tag(&mut meta, "BGCHANGES", "0=a=fast", &mut strict()).is_err());
+        assert!(metadata_tag(&mut meta, "ATTACKS", "LEN=2:TIME=1", &mut strict()).is_err());
+    }
+
+    #[test]
+    fn parse_keysounds() {
+        let rows = "1[1]00K[0]\n0000\n2[12]000\n3000\n";
+        let (data, _) = parse(
+            &format!(
+                "#KEYSOUNDS:kick.wav, snare.ogg;\n#NOTES:dance-single::Hard:1::\n{};",
+                rows
+            ),
+            ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(data.meta.keysounds, vec!["kick.wav", "snare.ogg"]);
+        let keyed = |note_type, column, keysound| Note {
+            keysound: Some(keysound),
+            ..Note::new(note_type, column)
+        };
+        assert_eq!(
+            data.charts[0].1,
+            vec![vec![
+                (
+                    vec![
+                        keyed(NoteType::Tap, 0, 1),
+                        keyed(NoteType::AutoKeysound, 3, 0)
+                    ],
+                    Rational32::new(0, 1)
+                ),
+                (vec![keyed(NoteType::Hold, 0, 12)], Rational32::new(1, 2)),
+                (vec![Note::new(NoteType::HoldEnd, 0)], Rational32::new(3, 4)),
+            ]]
+        );
+        assert_eq!(count_notes(rows), 2);
+        assert!(notes("1[x]00\n", &mut strict()).is_err());
+    }
+
     #[test]
     fn parse_notedata() {
+        let empty_info = ChartInfo {
+            steps_type: String::new(),
+            description: None,
+            difficulty: Difficulty::Edit,
+            meter: None,
+            radar_values: vec![],
+            chart_name: None,
+            credit: None,
+            structure: None,
+        };
         assert_eq!(
-            notedata(
+            parse(
                 "content that is
 
         #TITLE:bar1;
@@ -266,49 +983,66 @@ mod tests {
             0000\n \
             0010\n \
             0000\n \
-            ;"
-            ),
-            Ok((
-                "",
-                NoteData {
-                    meta: ChartMetadata {
-                        title: Some("bar1".to_owned()),
-                        subtitle: Some("bar2".to_owned()),
-                        artist: Some("bar3".to_owned()),
-                        title_translit: Some("bar4".to_owned()),
-                        subtitle_translit: Some("bar5".to_owned()),
-                        artist_translit: Some("bar6".to_owned()),
-                        genre: Some("bar7".to_owned()),
-                        credit: Some("bar8".to_owned()),
-                        banner_path: Some("bar9".to_owned()),
-                        background_path: Some("bar10".to_owned()),
-                        lyrics_path: Some("bar11".to_owned()),
-                        cd_title: Some("bar12".to_owned()),
-                        music_path: Some("bar13".to_owned()),
-                        sample_start: Some(1.2),
-                        sample_length: Some(3.4),
-                        display_bpm: Some(DisplayBpm::Random),
-                        background_changes: None,
-                        foreground_changes: None,
-                        selectable: None,
-                    },
-                    structure: StructureData {
-                        bpms: vec![BeatPair::from_pair(1. / 4.0, 2.0).unwrap()],
-                        stops: Some(vec![BeatPair::from_pair(3. / 4.0, 4.0).unwrap()]),
-                        offset: Some(-1.0),
-                    },
-                    charts: vec![
+            ;",
+                ParseOptions::default()
+            )
+            .unwrap()
+            .0,
+            NoteData {
+                meta: ChartMetadata {
+                    title: Some("bar1".to_owned()),
+                    subtitle: Some("bar2".to_owned()),
+                    artist: Some("bar3".to_owned()),
+                    title_translit: Some("bar4".to_owned()),
+                    subtitle_translit: Some("bar5".to_owned()),
+                    artist_translit: Some("bar6".to_owned()),
+                    genre: Some("bar7".to_owned()),
+                    credit: Some("bar8".to_owned()),
+                    banner_path: Some("bar9".to_owned()),
+                    background_path: Some("bar10".to_owned()),
+                    lyrics_path: Some("bar11".to_owned()),
+                    cd_title: Some("bar12".to_owned()),
+                    music_path: Some("bar13".to_owned()),
+                    sample_start: Some(1.2),
+                    sample_length: Some(3.4),
+                    display_bpm: Some(DisplayBpm::Random),
+                    background_changes: None,
+                    foreground_changes: None,
+                    attacks: None,
+                    keysounds: vec![],
+                    selectable: None,
+                },
+                structure: StructureData {
+                    bpms: vec![BeatPair::from_pair(1. / 4.0, 2.0).unwrap()],
+                    stops: Some(vec![BeatPair::from_pair(3. / 4.0, 4.0).unwrap()]),
+                    offset: Some(-1.0),
+                    delays: None,
+                    warps: None,
+                    time_signatures: None,
+                    tick_counts: None,
+                    combos: None,
+                    speeds: None,
+                    scrolls: None,
+                    fakes: None,
+                    labels: None,
+                },
+                charts: vec![
+                    (
+                        empty_info.clone(),
                         vec![vec![(
                             vec![Note::new(NoteType::Tap, 1)],
                             Rational32::new(1, 4),
-                        )]],
+                        )]]
+                    ),
+                    (
+                        empty_info,
                         vec![vec![(
                             vec![Note::new(NoteType::Tap, 2)],
                             Rational32::new(2, 4),
-                        )]],
-                    ],
-                }
-            ))
+                        )]]
+                    ),
+                ],
+            }
         );
     }
 }
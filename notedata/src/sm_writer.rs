@@ -1,34 +1,59 @@
-use crate::{BeatPair, DisplayBpm, Fraction, Measure, Note, NoteData, NoteRow, NoteType};
+use crate::{
+    Attack, BackgroundChange, BeatPair, ChartInfo, ChartMetadata, Difficulty, DisplayBpm, Fraction,
+    Measure, Note, NoteData, NoteRow, NoteType, SpeedUnit, StructureData, WriteError,
+};
+use std::convert::TryFrom;
 
-pub fn write_sm(data: &NoteData) -> String {
+//StepMania places notes on a grid of 192 rows per measure
+const MAX_ROWS: i64 = 192;
+
+pub fn write_sm(data: &NoteData) -> Result<String, WriteError> {
+    let mut output = metadata_string(&data.meta);
+    output.push_str(&structure_string(&data.structure));
+    for (info, chart) in &data.charts {
+        if info.structure_or(&data.structure) != &data.structure {
+            return Err(WriteError::Unrepresentable(
+                ".sm charts can't have their own timing".to_owned(),
+            ));
+        }
+        if info.chart_name.is_some() {
+            return Err(WriteError::Unrepresentable(
+                ".sm charts can't have a name".to_owned(),
+            ));
+        }
+        output.push_str(&write_tag("NOTES", &chart_string(info, chart)?))
+    }
+    Ok(output)
+}
+
+pub fn metadata_string(meta: &ChartMetadata) -> String {
     let mut output = String::new();
     let mut string_tag = |tag_name: &str, from_location: &Option<String>| {
         if let Some(tag) = from_location {
             output.push_str(&write_tag(tag_name, tag))
         }
     };
-    string_tag("TITLE", &data.meta.title);
-    string_tag("SUBTITLE", &data.meta.subtitle);
-    string_tag("ARTIST", &data.meta.artist);
-    string_tag("TITLETRANSLIT", &data.meta.title_translit);
-    string_tag("SUBTITLETRANSLIT", &data.meta.subtitle_translit);
-    string_tag("ARTISTTRANSLIT", &data.meta.artist_translit);
-    string_tag("GENRE", &data.meta.genre);
-    string_tag("CREDIT", &data.meta.credit);
-    string_tag("BANNER", &data.meta.banner_path);
-    string_tag("BACKGROUND", &data.meta.background_path);
-    string_tag("LYRICSPATH", &data.meta.lyrics_path);
-    string_tag("CDTITLE", &data.meta.cd_title);
-    string_tag("MUSIC", &data.meta.music_path);
+    string_tag("TITLE", &meta.title);
+    string_tag("SUBTITLE", &meta.subtitle);
+    string_tag("ARTIST", &meta.artist);
+    string_tag("TITLETRANSLIT", &meta.title_translit);
+    string_tag("SUBTITLETRANSLIT", &meta.subtitle_translit);
+    string_tag("ARTISTTRANSLIT", &meta.artist_translit);
+    string_tag("GENRE", &meta.genre);
+    string_tag("CREDIT", &meta.credit);
+    string_tag("BANNER", &meta.banner_path);
+    string_tag("BACKGROUND", &meta.background_path);
+    string_tag("LYRICSPATH", &meta.lyrics_path);
+    string_tag("CDTITLE", &meta.cd_title);
+    string_tag("MUSIC", &meta.music_path);
     let mut number_tag = |tag_name: &str, from_location: &Option<f64>| {
         if let Some(tag) = from_location {
             output.push_str(&write_tag(tag_name, &tag.to_string()))
         }
     };
-    number_tag("SAMPLESTART", &data.meta.sample_start);
-    number_tag("SAMPLELENGTH", &data.meta.sample_length);
-    number_tag("OFFSET", &data.structure.offset.map(|x| -x));
-    if let Some(tag) = &data.meta.display_bpm {
+    number_tag("SAMPLESTART", &meta.sample_start);
+    number_tag("SAMPLELENGTH", &meta.sample_length);
+    if let Some(tag) = &meta.display_bpm {
         output.push_str(&write_tag(
             "DISPLAYBPM",
             &match tag {
@@ -38,92 +63,246 @@ pub fn write_sm(data: &NoteData) -> String {
             },
         ))
     }
-    output.push_str(&write_tag("BPMS", &float_pair_tag(&data.structure.bpms)));
-    if let Some(tag) = &data.structure.stops {
-        output.push_str(&write_tag("STOPS", &float_pair_tag(tag)));
+    let mut changes_tag =
+        |tag_name: &str, from_location: &Option<Vec<BeatPair<BackgroundChange>>>| {
+            if let Some(changes) = from_location {
+                output.push_str(&write_tag(
+                    tag_name,
+                    &pair_tag(changes, background_change_string),
+                ))
+            }
+        };
+    changes_tag("BGCHANGES", &meta.background_changes);
+    changes_tag("FGCHANGES", &meta.foreground_changes);
+    if let Some(attacks) = &meta.attacks {
+        output.push_str(&write_tag("ATTACKS", &attacks_string(attacks)))
     }
-    for chart in &data.charts {
-        output.push_str(&write_tag("NOTES", &chart_string(&chart)))
+    if !meta.keysounds.is_empty() {
+        output.push_str(&write_tag("KEYSOUNDS", &meta.keysounds.join(",")))
     }
     output
 }
 
-fn write_tag(tag_name: &str, contents: &str) -> String {
+//Older versions of StepMania only read the first six fields, so the rest are left off if unused
+fn background_change_string(change: &BackgroundChange) -> String {
+    let flag = |set| if set { "1" } else { "0" }.to_owned();
+    let mut fields = vec![
+        change.file.clone(),
+        change.rate.to_string(),
+        flag(change.crossfade),
+        flag(change.stretch_rewind),
+        flag(change.stretch_no_loop),
+    ];
+    let extra = [
+        &change.effect,
+        &change.file2,
+        &change.transition,
+        &change.color1,
+        &change.color2,
+    ];
+    if extra.iter().any(|field| field.is_some()) {
+        fields.extend(
+            extra
+                .iter()
+                .map(|field| field.as_deref().unwrap_or("").to_owned()),
+        );
+    }
+    fields.join("=")
+}
+
+fn attacks_string(attacks: &[Attack]) -> String {
+    attacks
+        .iter()
+        .map(|attack| {
+            format!(
+                "TIME={}:LEN={}:MODS={}",
+                attack.time,
+                attack.length,
+                attack.mods.join(",")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+pub fn structure_string(structure: &StructureData) -> String {
+    let mut output = String::new();
+    if let Some(offset) = structure.offset {
+        output.push_str(&write_tag("OFFSET", &(-offset).to_string()));
+    }
+    output.push_str(&write_tag("BPMS", &float_pair_tag(&structure.bpms)));
+    let mut segment_tag = |tag_name: &str, from_location: Option<String>| {
+        if let Some(tag) = from_location {
+            output.push_str(&write_tag(tag_name, &tag))
+        }
+    };
+    segment_tag("STOPS", structure.stops.as_deref().map(float_pair_tag));
+    segment_tag("DELAYS", structure.delays.as_deref().map(float_pair_tag));
+    segment_tag("WARPS", structure.warps.as_deref().map(float_pair_tag));
+    segment_tag(
+        "TIMESIGNATURES",
+        structure.time_signatures.as_deref().map(|list| {
+            pair_tag(list, |signature| {
+                format!("{}={}", signature.numerator, signature.denominator)
+            })
+        }),
+    );
+    segment_tag(
+        "TICKCOUNTS",
+        structure
+            .tick_counts
+            .as_deref()
+            .map(|list| pair_tag(list, i32::to_string)),
+    );
+    segment_tag(
+        "COMBOS",
+        structure
+            .combos
+            .as_deref()
+            .map(|list| pair_tag(list, |combo| format!("{}={}", combo.hit, combo.miss))),
+    );
+    segment_tag(
+        "SPEEDS",
+        structure.speeds.as_deref().map(|list| {
+            pair_tag(list, |speed| {
+                let unit = match speed.unit {
+                    SpeedUnit::Beats => 0,
+                    SpeedUnit::Seconds => 1,
+                };
+                format!("{}={}={}", speed.ratio, speed.length, unit)
+            })
+        }),
+    );
+    segment_tag("SCROLLS", structure.scrolls.as_deref().map(float_pair_tag));
+    segment_tag("FAKES", structure.fakes.as_deref().map(float_pair_tag));
+    segment_tag(
+        "LABELS",
+        structure
+            .labels
+            .as_deref()
+            .map(|list| pair_tag(list, String::clone)),
+    );
+    output
+}
+
+pub fn write_tag(tag_name: &str, contents: &str) -> String {
     format!("#{}:{};\n", tag_name, contents)
 }
 
-fn beat_to_float<T>(pair: &BeatPair<T>) -> f64 {
+pub fn beat_to_float<T>(pair: &BeatPair<T>) -> f64 {
     (f64::from(pair.beat) + (f64::from(*pair.sub_beat.numer()) / f64::from(*pair.sub_beat.denom())))
         * 4.0
 }
 
+fn pair_tag<T, F>(list: &[BeatPair<T>], value_string: F) -> String
+where
+    F: Fn(&T) -> String,
+{
+    list.iter()
+        .map(|pair| format!("{}={}", beat_to_float(pair), value_string(&pair.value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 fn float_pair_tag(list: &[BeatPair<f64>]) -> String {
-    let mut output = String::new();
-    if let Some(first_bpm) = list.get(0) {
-        output.push_str(&format!(
-            "{}={}",
-            beat_to_float(&first_bpm),
-            first_bpm.value
-        ))
-    }
-    for bpm in list.iter().skip(1) {
-        output.push_str(&format!(",{}={}", beat_to_float(bpm), bpm.value))
-    }
-    output
+    pair_tag(list, f64::to_string)
 }
 
-fn chart_string(chart: &[Measure]) -> String {
-    let mut output = String::new();
-    output.push_str("dance-single:\n:\nBeginner:\n1:\n0.0,0.0,0.0,0.0,0.0:\n");
-    for measure in chart {
-        output.push_str(&measure_string(measure, 4));
+pub fn difficulty_name(difficulty: Difficulty) -> &'static str {
+    match difficulty {
+        Difficulty::Beginner => "Beginner",
+        Difficulty::Easy => "Easy",
+        Difficulty::Medium => "Medium",
+        Difficulty::Hard => "Hard",
+        Difficulty::Challenge => "Challenge",
+        Difficulty::Edit => "Edit",
     }
-    output
 }
 
-fn measure_string(measure: &[(NoteRow, Fraction)], length: usize) -> String {
-    let mut output = String::new();
-    let possible_snaps = [4, 8, 12, 16, 24, 32, 48, 64];
-    let mut snap_index = 0;
-    for (_notes, timestamp) in measure {
-        if let Some(snap) = possible_snaps.get(snap_index) {
-            if snap % timestamp.denom() != 0 {
-                snap_index += 1
-            }
-        } else {
-            break;
-        }
+fn chart_info_string(info: &ChartInfo) -> String {
+    format!(
+        "\n     {}:\n     {}:\n     {}:\n     {}:\n     {}:\n",
+        info.steps_type,
+        info.description.as_deref().unwrap_or(""),
+        difficulty_name(info.difficulty),
+        info.meter.unwrap_or(1),
+        info.radar_values
+            .iter()
+            .map(f64::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+fn chart_string(info: &ChartInfo, chart: &[Measure]) -> Result<String, WriteError> {
+    let mut output = chart_info_string(info);
+    output.push_str(&measures_string(chart, info.columns(chart))?);
+    Ok(output)
+}
+
+pub fn measures_string(chart: &[Measure], columns: usize) -> Result<String, WriteError> {
+    Ok(chart
+        .iter()
+        .enumerate()
+        .map(|(index, measure)| measure_string(index, measure, columns))
+        .collect::<Result<Vec<_>, _>>()?
+        .join(",\n"))
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
     }
-    let snap = *possible_snaps.get(snap_index).unwrap_or(&192);
-    let mut row_index = 0;
+}
+
+//Every note gets its exact row, with at least the four quarter notes of a measure
+fn measure_string(
+    index: usize,
+    measure: &[(NoteRow, Fraction)],
+    length: usize,
+) -> Result<String, WriteError> {
+    let unrepresentable =
+        |reason| WriteError::Unrepresentable(format!("measure {} has {}", index, reason));
+    let rows = measure
+        .iter()
+        .map(|(_, timestamp)| i64::from(*timestamp.denom()))
+        .try_fold(4, |rows, denominator| {
+            Some(rows / gcd(rows, denominator) * denominator).filter(|&rows| rows <= MAX_ROWS)
+        })
+        .ok_or_else(|| unrepresentable(format!("notes between StepMania's {} rows", MAX_ROWS)))?;
     let blank_line = vec!['0'; length].into_iter().collect::<String>();
+    let mut lines = vec![blank_line; rows as usize];
     for (notes, timestamp) in measure {
-        while Fraction::new(row_index, snap) < *timestamp {
-            output.push_str(&format!("{}\n", blank_line));
-            row_index += 1;
-        }
-        output.push_str(&format!("{}\n", row_string(notes, length)));
-        row_index += 1;
-    }
-    while row_index < snap {
-        output.push_str(&format!("{}\n", blank_line));
-        row_index += 1;
+        let row = usize::try_from((*timestamp * rows as i32).to_integer())
+            .ok()
+            .and_then(|row| lines.get_mut(row))
+            .ok_or_else(|| unrepresentable(format!("a row outside of it at {}", timestamp)))?;
+        *row = row_string(notes, length).ok_or_else(|| {
+            unrepresentable(format!(
+                "a note past its {} columns at {}",
+                length, timestamp
+            ))
+        })?;
     }
-    output.push_str(",\n");
-    output
+    Ok(lines.into_iter().map(|line| line + "\n").collect())
 }
 
-fn row_string(row: &[Note], length: usize) -> String {
-    let mut blank_line = vec!['0'; length];
+//None when a note is past the last column
+fn row_string(row: &[Note], length: usize) -> Option<String> {
+    let mut blank_line = vec!["0".to_owned(); length];
     for note in row {
-        if let Some(index) = blank_line.get_mut(note.column) {
-            *index = notetype_to_char(note.note_type)
+        let index = blank_line.get_mut(note.column)?;
+        *index = notetype_to_char(note.note_type).to_string();
+        if let Some(keysound) = note.keysound {
+            index.push_str(&format!("[{}]", keysound));
         }
     }
-    blank_line.into_iter().collect()
+    Some(blank_line.concat())
 }
 
-fn notetype_to_char(note: NoteType) -> char {
+pub fn notetype_to_char(note: NoteType) -> char {
     match note {
         NoteType::Tap => '1',
         NoteType::Hold => '2',
@@ -132,15 +311,242 @@ fn notetype_to_char(note: NoteType) -> char {
         NoteType::Mine => 'M',
         NoteType::Lift => 'L',
         NoteType::Fake => 'F',
+        NoteType::AutoKeysound => 'K',
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::sm_writer::write_tag;
+    use crate::sm_writer::{
+        chart_info_string, measure_string, metadata_string, structure_string, write_sm, write_tag,
+    };
+    use crate::{
+        parser_generic::Warnings, sm_parser, Attack, BackgroundChange, BeatPair, ChartInfo,
+        ChartMetadata, Combo, Difficulty, Fraction, Measure, Note, NoteData, NoteType,
+        ParseOptions, StructureData, WriteError,
+    };
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 
     #[test]
     fn create_tag() {
         assert_eq!(write_tag("TITLE", "foobar baz"), "#TITLE:foobar baz;\n")
     }
+
+    #[test]
+    fn create_chart_info() {
+        assert_eq!(
+            chart_info_string(&ChartInfo {
+                steps_type: "dance-double".to_owned(),
+                description: Some("foo".to_owned()),
+                difficulty: Difficulty::Hard,
+                meter: Some(9),
+                radar_values: vec![0.5, 1.0],
+                chart_name: None,
+                credit: None,
+                structure: None,
+            }),
+            "\n     dance-double:\n     foo:\n     Hard:\n     9:\n     0.5,1:\n"
+        );
+        assert_eq!(
+            chart_info_string(&ChartInfo::new()),
+            "\n     dance-single:\n     :\n     Beginner:\n     1:\n     :\n"
+        );
+    }
+
+    #[test]
+    fn segments_round_trip() {
+        let structure = StructureData {
+            offset: Some(0.5),
+            bpms: vec![BeatPair::from_pair(0.0, 120.0).unwrap()],
+            delays: Some(vec![BeatPair::from_pair(0.25, 0.5).unwrap()]),
+            warps: Some(vec![BeatPair::from_pair(1.0, 2.0).unwrap()]),
+            combos: Some(vec![BeatPair::at_start(Combo { hit: 2, miss: 1 })]),
+            labels: Some(vec![BeatPair::at_start("Start".to_owned())]),
+            ..StructureData::default()
+        };
+        let output = structure_string(&structure);
+        assert_eq!(
+            output,
+            "#OFFSET:-0.5;\n#BPMS:0=120;\n#DELAYS:1=0.5;\n#WARPS:4=2;\n#COMBOS:0=2=1;\n#LABELS:0=Start;\n"
+        );
+        let mut parsed = StructureData::default();
+        for line in output.lines() {
+            let (tag, value) = line[1..line.len() - 1].split_at(line.find(':').unwrap() - 1);
+            let mut warnings = Warnings::new(ParseOptions::default());
+            sm_parser::structure_tag(&mut parsed, tag, &value[1..], &mut warnings).unwrap();
+        }
+        assert_eq!(parsed, structure);
+    }
+
+    #[test]
+    fn effects_round_trip() {
+        let mut meta = ChartMetadata::new();
+        meta.background_changes = Some(vec![
+            BeatPair::at_start(BackgroundChange::new("intro.avi".to_owned())),
+            BeatPair::at_position(
+                Fraction::new(3, 2),
+                BackgroundChange {
+                    rate: 0.5,
+                    stretch_rewind: true,
+                    transition: Some("CrossFade".to_owned()),
+                    ..BackgroundChange::new("b.png".to_owned())
+                },
+            ),
+        ]);
+        meta.attacks = Some(vec![Attack {
+            time: 1.5,
+            length: 2.0,
+            mods: vec!["drunk".to_owned(), "2x".to_owned()],
+        }]);
+        meta.keysounds = vec!["kick.wav".to_owned(), "snare.ogg".to_owned()];
+        let output = metadata_string(&meta);
+        assert_eq!(
+            output,
+            "#BGCHANGES:0=intro.avi=1=0=0=0,6=b.png=0.5=0=1=0===CrossFade==;\n\
+             #ATTACKS:TIME=1.5:LEN=2:MODS=drunk,2x;\n#KEYSOUNDS:kick.wav,snare.ogg;\n"
+        );
+        let mut parsed = ChartMetadata::new();
+        for line in output.lines() {
+            let (tag, value) = line[1..line.len() - 1].split_at(line.find(':').unwrap() - 1);
+            let mut warnings = Warnings::new(ParseOptions::default());
+            sm_parser::metadata_tag(&mut parsed, tag, &value[1..], &mut warnings).unwrap();
+        }
+        assert_eq!(parsed, meta);
+    }
+
+    fn taps(times: &[(i32, i32)]) -> Measure {
+        times
+            .iter()
+            .enumerate()
+            .map(|(column, &(numerator, denominator))| {
+                (
+                    vec![Note::new(NoteType::Tap, column % 4)],
+                    Fraction::new(numerator, denominator),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exact_rows() {
+        let rows = |measure: &Measure| measure_string(0, measure, 4).unwrap().lines().count();
+        assert_eq!(rows(&vec![]), 4);
+        assert_eq!(rows(&taps(&[(0, 1), (1, 2)])), 4);
+        assert_eq!(rows(&taps(&[(1, 3), (3, 16)])), 48);
+        assert_eq!(rows(&taps(&[(2, 5), (4, 7)])), 140);
+        assert_eq!(
+            measure_string(0, &taps(&[(1, 3), (1, 8)]), 4).unwrap(),
+            "0000\n0000\n0000\n0100\n0000\n0000\n0000\n0000\n1000\n0000\n0000\n0000\n\
+             0000\n0000\n0000\n0000\n0000\n0000\n0000\n0000\n0000\n0000\n0000\n0000\n"
+        );
+        assert!(measure_string(0, &taps(&[(1, 5), (1, 64)]), 4).is_err());
+        assert!(measure_string(0, &taps(&[(1, 1)]), 4).is_err());
+        assert!(measure_string(0, &taps(&[(0, 1), (1, 2), (1, 4)]), 2).is_err());
+    }
+
+    fn random_measure(rng: &mut StdRng, columns: usize) -> Measure {
+        //Pairs of snaps whose rows fit in a measure, including the 5ths and 7ths of other formats
+        let snaps = [
+            1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 28, 32, 48, 64, 96, 192,
+        ];
+        let snap = *snaps.choose(rng).unwrap();
+        let other = *snaps
+            .iter()
+            .filter(|&&other| snap * other / super::gcd(snap.into(), other.into()) as i32 <= 192)
+            .collect::<Vec<_>>()
+            .choose(rng)
+            .unwrap();
+        let mut times: Vec<_> = (0..snap)
+            .map(|row| Fraction::new(row, snap))
+            .chain((0..*other).map(|row| Fraction::new(row, *other)))
+            .filter(|_| rng.gen_bool(0.3))
+            .collect();
+        times.sort();
+        times.dedup();
+        let note_types = [
+            NoteType::Tap,
+            NoteType::Hold,
+            NoteType::HoldEnd,
+            NoteType::Roll,
+            NoteType::Mine,
+            NoteType::Lift,
+            NoteType::Fake,
+            NoteType::AutoKeysound,
+        ];
+        times
+            .into_iter()
+            .filter_map(|time| {
+                let row: Vec<_> = (0..columns)
+                    .filter_map(|column| {
+                        if rng.gen_bool(0.4) {
+                            let note_type = *note_types.choose(rng).unwrap();
+                            let keysound = if rng.gen_bool(0.3) {
+                                Some(rng.gen_range(0, 20))
+                            } else {
+                                None
+                            };
+                            Some(Note {
+                                keysound,
+                                ..Note::new(note_type, column)
+                            })
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                Some((row, time)).filter(|(row, _)| !row.is_empty())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chart_timing() {
+        let mut data = NoteData::new();
+        data.structure.bpms = vec![BeatPair::at_start(120.0)];
+        let mut info = ChartInfo::new();
+        info.structure = Some(data.structure.clone());
+        data.charts = vec![(info, vec![])];
+        assert!(write_sm(&data).is_ok());
+        data.charts[0].0.structure = Some(StructureData {
+            bpms: vec![BeatPair::at_start(150.0)],
+            ..StructureData::default()
+        });
+        assert!(matches!(
+            write_sm(&data),
+            Err(WriteError::Unrepresentable(_))
+        ));
+        data.charts[0].0.structure = None;
+        data.charts[0].0.chart_name = Some("foo".to_owned());
+        assert!(matches!(
+            write_sm(&data),
+            Err(WriteError::Unrepresentable(_))
+        ));
+    }
+
+    #[test]
+    fn random_charts_round_trip() {
+        let mut rng = StdRng::seed_from_u64(19);
+        for _ in 0..200 {
+            let (steps_type, columns) =
+                *[("dance-single", 4), ("dance-double", 8), ("pump-single", 5)]
+                    .choose(&mut rng)
+                    .unwrap();
+            let mut data = NoteData::new();
+            data.charts = vec![(
+                ChartInfo {
+                    steps_type: steps_type.to_owned(),
+                    meter: Some(rng.gen_range(1, 20)),
+                    ..ChartInfo::default()
+                },
+                (0..rng.gen_range(1, 6))
+                    .map(|_| random_measure(&mut rng, columns))
+                    .collect(),
+            )];
+            let written = write_sm(&data).unwrap();
+            let parsed = sm_parser::parse(&written, ParseOptions::default())
+                .unwrap()
+                .0;
+            assert_eq!(parsed.charts, data.charts, "{}", written);
+        }
+    }
 }
@@ -0,0 +1,233 @@
+use crate::{
+    error::{ParseError, ValueError},
+    parser_generic::{comma_separated, for_each_tag, ws_trimmed, Warnings},
+    sm_parser::{self, count_notes, into_difficulty, metadata_tag, structure_tag, Tags},
+    ChartInfo, ChartMetadata, ChartSummary, NoteData, ParseOptions, SongHeader, StructureData,
+};
+use nom::{character::complete::digit1, combinator::map_res, number::complete::double};
+use std::collections::HashSet;
+
+fn chart_tag<'a>(
+    info: &mut ChartInfo,
+    tag: &str,
+    value: &'a str,
+    warnings: &mut Warnings<'a>,
+) -> Result<(), ValueError<'a>> {
+    match tag {
+        "CHARTNAME" => info.chart_name = Some(value.to_owned()),
+        "STEPSTYPE" => info.steps_type = value.trim().to_owned(),
+        "DESCRIPTION" => info.description = Some(value.to_owned()),
+        "DIFFICULTY" => info.difficulty = into_difficulty(value.trim()),
+        "METER" => {
+            info.meter = Some(
+                ws_trimmed(map_res(digit1, str::parse))(value)
+                    .map_err(ValueError::bad_number)?
+                    .1,
+            )
+        }
+        "RADARVALUES" => {
+            info.radar_values = ws_trimmed(comma_separated(double))(value)
+                .map_err(ValueError::bad_number)?
+                .1
+        }
+        "CREDIT" => info.credit = Some(value.to_owned()),
+        _ => {
+            let mut structure = info.structure.clone().unwrap_or_default();
+            if structure_tag(&mut structure, tag, value, warnings)? {
+                info.structure = Some(structure);
+            }
+        }
+    }
+    Ok(())
+}
+
+//Like the .sm parser, the full and header parses only differ in how they read the notes
+fn parse_tags<'a, N>(
+    input: &'a str,
+    options: ParseOptions,
+    mut notes: impl FnMut(&'a str, &mut Warnings<'a>) -> Result<N, ValueError<'a>>,
+) -> Result<Tags<(ChartInfo, N)>, ParseError> {
+    let mut meta = ChartMetadata::new();
+    let mut structure = StructureData::default();
+    let mut charts = vec![];
+    //Tags before the first #NOTEDATA belong to the song, everything after belongs to a chart
+    let mut current_chart: Option<ChartInfo> = None;
+    let mut seen = HashSet::new();
+
+    let warnings = for_each_tag(input, options, |tag, value, warnings| {
+        if tag == "NOTEDATA" {
+            current_chart = Some(ChartInfo::new());
+            seen.clear();
+        } else if !value.trim().is_empty() {
+            warnings.duplicate(&mut seen, tag, value);
+            if let Some(info) = &mut current_chart {
+                match tag {
+                    "NOTES" | "NOTES2" => {
+                        charts.push((info.clone(), notes(value, warnings)?));
+                        current_chart = None;
+                    }
+                    _ => chart_tag(info, tag, value, warnings)?,
+                }
+            } else {
+                metadata_tag(&mut meta, tag, value, warnings)?;
+                structure_tag(&mut structure, tag, value, warnings)?;
+            }
+        }
+        Ok(())
+    })?;
+    //Like StepMania, a chart's own timing starts from the song's offset unless it has its own
+    for (info, _) in &mut charts {
+        if let Some(chart_structure) = &mut info.structure {
+            chart_structure.offset = chart_structure.offset.or(structure.offset);
+        }
+    }
+    Ok((meta, structure, charts, warnings))
+}
+
+pub fn parse(
+    input: &str,
+    options: ParseOptions,
+) -> Result<(NoteData, Vec<ParseError>), ParseError> {
+    let (meta, structure, charts, warnings) = parse_tags(input, options, sm_parser::notes)?;
+    Ok((
+        NoteData {
+            charts,
+            meta,
+            structure,
+        },
+        warnings,
+    ))
+}
+
+pub fn parse_header(
+    input: &str,
+    options: ParseOptions,
+) -> Result<(SongHeader, Vec<ParseError>), ParseError> {
+    let (meta, structure, charts, warnings) =
+        parse_tags(input, options, |notes, _| Ok(count_notes(notes)))?;
+    Ok((
+        SongHeader {
+            meta,
+            structure,
+            charts: charts
+                .into_iter()
+                .map(|(info, note_count)| ChartSummary { info, note_count })
+                .collect(),
+        },
+        warnings,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BeatPair, Difficulty, Fraction, Note, NoteType};
+
+    #[test]
+    fn parse_notedata() {
+        let (nd, _) = parse(
+            "#VERSION:0.83;
+        #TITLE:foo;
+        #OFFSET:-0.5;
+        #BPMS:0.000=120.000;
+        #NOTEDATA:;
+        #CHARTNAME:bar;
+        #STEPSTYPE:dance-single;
+        #DESCRIPTION:baz;
+        #DIFFICULTY:Hard;
+        #METER:11;
+        #RADARVALUES:0.5,0.25;
+        #CREDIT:qux;
+        #NOTES:
+        0000
+        0100
+        0000
+        0000
+        ;
+        #NOTEDATA:;
+        #STEPSTYPE:dance-single;
+        #DIFFICULTY:Challenge;
+        #BPMS:0.000=150.000,4.000=300.000;
+        #STOPS:2.000=0.250;
+        #NOTES:
+        1000
+        ,
+        0001
+        ;",
+            ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(nd.meta.title, Some("foo".to_owned()));
+        assert_eq!(nd.structure.offset, Some(0.5));
+        assert_eq!(
+            nd.structure.bpms,
+            vec![BeatPair::from_pair(0.0, 120.0).unwrap()]
+        );
+        assert_eq!(
+            nd.charts,
+            vec![
+                (
+                    ChartInfo {
+                        steps_type: "dance-single".to_owned(),
+                        description: Some("baz".to_owned()),
+                        difficulty: Difficulty::Hard,
+                        meter: Some(11),
+                        radar_values: vec![0.5, 0.25],
+                        chart_name: Some("bar".to_owned()),
+                        credit: Some("qux".to_owned()),
+                        structure: None,
+                    },
+                    vec![vec![(
+                        vec![Note::new(NoteType::Tap, 1)],
+                        Fraction::new(1, 4)
+                    )]]
+                ),
+                (
+                    ChartInfo {
+                        steps_type: "dance-single".to_owned(),
+                        description: None,
+                        difficulty: Difficulty::Challenge,
+                        meter: None,
+                        radar_values: vec![],
+                        chart_name: None,
+                        credit: None,
+                        //The chart has no #OFFSET of its own, so it keeps the song's
+                        structure: Some(StructureData {
+                            offset: Some(0.5),
+                            bpms: vec![
+                                BeatPair::from_pair(0.0, 150.0).unwrap(),
+                                BeatPair::from_pair(1.0, 300.0).unwrap()
+                            ],
+                            stops: Some(vec![BeatPair::from_pair(0.5, 0.25).unwrap()]),
+                            delays: None,
+                            warps: None,
+                            time_signatures: None,
+                            tick_counts: None,
+                            combos: None,
+                            speeds: None,
+                            scrolls: None,
+                            fakes: None,
+                            labels: None,
+                        }),
+                    },
+                    vec![
+                        vec![(vec![Note::new(NoteType::Tap, 0)], Fraction::new(0, 1))],
+                        vec![(vec![Note::new(NoteType::Tap, 3)], Fraction::new(0, 1))]
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_header_only() {
+        let input = "#TITLE:foo;\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#METER:7;\n\
+                     #NOTES:\n1000\n0200\n0300\nMM00\n;\n#NOTEDATA:;\n#NOTES2:\n0011\n;";
+        let (header, _) = parse_header(input, ParseOptions::default()).unwrap();
+        let (nd, _) = parse(input, ParseOptions::default()).unwrap();
+        assert_eq!(header, SongHeader::from(&nd));
+        assert_eq!(header.charts[0].info.meter, Some(7));
+        assert_eq!(header.charts[0].note_count, 2);
+        assert_eq!(header.charts[1].note_count, 2);
+    }
+}
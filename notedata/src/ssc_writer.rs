@@ -0,0 +1,127 @@
+use crate::{
+    sm_writer::{difficulty_name, measures_string, metadata_string, structure_string, write_tag},
+    ChartInfo, Measure, NoteData, WriteError,
+};
+
+pub fn write_ssc(data: &NoteData) -> Result<String, WriteError> {
+    let mut output = write_tag("VERSION", "0.83");
+    output.push_str(&metadata_string(&data.meta));
+    output.push_str(&structure_string(&data.structure));
+    for (info, chart) in &data.charts {
+        output.push_str(&chart_string(info, chart)?);
+    }
+    Ok(output)
+}
+
+fn chart_string(info: &ChartInfo, chart: &[Measure]) -> Result<String, WriteError> {
+    let mut output = format!(
+        "//---------------{} - {}----------------\n",
+        info.steps_type,
+        info.description.as_deref().unwrap_or("")
+    );
+    output.push_str(&write_tag("NOTEDATA", ""));
+    let mut string_tag = |tag_name: &str, from_location: &Option<String>| {
+        if let Some(tag) = from_location {
+            output.push_str(&write_tag(tag_name, tag))
+        }
+    };
+    string_tag("CHARTNAME", &info.chart_name);
+    string_tag("STEPSTYPE", &Some(info.steps_type.clone()));
+    string_tag("DESCRIPTION", &info.description);
+    string_tag(
+        "DIFFICULTY",
+        &Some(difficulty_name(info.difficulty).to_owned()),
+    );
+    string_tag("METER", &info.meter.map(|meter| meter.to_string()));
+    string_tag(
+        "RADARVALUES",
+        &Some(
+            info.radar_values
+                .iter()
+                .map(f64::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+    );
+    string_tag("CREDIT", &info.credit);
+    if let Some(structure) = &info.structure {
+        output.push_str(&structure_string(structure));
+    }
+    output.push_str(&write_tag(
+        "NOTES",
+        &format!("\n{}", measures_string(chart, info.columns(chart))?),
+    ));
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ssc_parser, BeatPair, Difficulty, Fraction, Note, NoteType, ParseOptions, StructureData,
+    };
+
+    #[test]
+    fn round_trip() {
+        let mut data = NoteData::new();
+        data.meta.title = Some("foo".to_owned());
+        data.structure.offset = Some(0.25);
+        data.structure.bpms = vec![BeatPair::from_pair(0.0, 120.0).unwrap()];
+        data.charts = vec![
+            (
+                ChartInfo {
+                    steps_type: "dance-single".to_owned(),
+                    description: Some("bar".to_owned()),
+                    difficulty: Difficulty::Medium,
+                    meter: Some(6),
+                    radar_values: vec![0.5, 0.25],
+                    chart_name: Some("baz".to_owned()),
+                    credit: None,
+                    structure: None,
+                },
+                vec![vec![(
+                    vec![Note::new(NoteType::Tap, 1)],
+                    Fraction::new(1, 4),
+                )]],
+            ),
+            (
+                ChartInfo {
+                    steps_type: "dance-single".to_owned(),
+                    description: None,
+                    difficulty: Difficulty::Hard,
+                    meter: Some(9),
+                    radar_values: vec![],
+                    chart_name: None,
+                    credit: Some("qux".to_owned()),
+                    structure: Some(StructureData {
+                        offset: Some(0.5),
+                        bpms: vec![
+                            BeatPair::from_pair(0.0, 150.0).unwrap(),
+                            BeatPair::from_pair(1.0, 300.0).unwrap(),
+                        ],
+                        stops: Some(vec![BeatPair::from_pair(0.5, 0.25).unwrap()]),
+                        delays: None,
+                        warps: None,
+                        time_signatures: None,
+                        tick_counts: None,
+                        combos: None,
+                        speeds: None,
+                        scrolls: None,
+                        fakes: None,
+                        labels: None,
+                    }),
+                },
+                vec![
+                    vec![(vec![Note::new(NoteType::Tap, 0)], Fraction::new(0, 1))],
+                    vec![(vec![Note::new(NoteType::Tap, 3)], Fraction::new(1, 2))],
+                ],
+            ),
+        ];
+        assert_eq!(
+            ssc_parser::parse(&write_ssc(&data).unwrap(), ParseOptions::default())
+                .unwrap()
+                .0,
+            data
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{
+    parser_generic::value, timingdata::TimingMap, ChartNotes, Measure, NoteType, StructureData,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+//The groove radar scales, as StepMania uses them. Each value is capped at 1.
+const STREAM_NPS: f64 = 7.0;
+const VOLTAGE_WINDOW_BEATS: f64 = 8.0;
+const VOLTAGE_BPS: f64 = 10.0;
+const CHAOS_SCALE: f64 = 0.5;
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RadarValues {
+    pub stream: f64,
+    pub voltage: f64,
+    pub air: f64,
+    pub freeze: f64,
+    pub chaos: f64,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChartStats {
+    //Notes that are hit, a hold or roll counts once for its head
+    pub notes: usize,
+    //How many rows have each number of notes hit together, so chords[2] is the number of jumps
+    pub chords: Vec<usize>,
+    pub holds: usize,
+    pub rolls: usize,
+    pub mines: usize,
+    //When the last note or hold end comes, in seconds from the start of the music
+    pub length: f64,
+    //The notes hit during each second of the music, up to the last one
+    pub nps: Vec<usize>,
+    pub peak_nps: usize,
+    pub radar: RadarValues,
+}
+
+fn add(counts: &mut Vec<usize>, index: usize, amount: usize) {
+    if counts.len() <= index {
+        counts.resize(index + 1, 0);
+    }
+    counts[index] += amount;
+}
+
+impl ChartStats {
+    //Notes in warps are never reached, so they aren't counted. There are no stats without timing.
+    #[must_use]
+    pub fn new(chart: &[Measure], structure: &StructureData) -> Option<Self> {
+        let timing = TimingMap::new(structure)?;
+        let mut stats = Self::default();
+        let mut last_beat = 0.0;
+        let mut voltage_windows = vec![];
+        let mut chaos_rows = 0;
+        for (position, row) in chart.rows() {
+            let beat = value(position);
+            if timing.is_warped(beat) {
+                continue;
+            }
+            let time = timing.beat_to_ms(beat) / 1000.0;
+            if row
+                .iter()
+                .any(|note| note.note_type != NoteType::AutoKeysound)
+            {
+                last_beat = beat;
+                stats.length = stats.length.max(time);
+            }
+            for note in row {
+                match note.note_type {
+                    NoteType::Hold => stats.holds += 1,
+                    NoteType::Roll => stats.rolls += 1,
+                    NoteType::Mine => stats.mines += 1,
+                    _ => {}
+                }
+            }
+            let hits = row.iter().filter(|note| note.note_type.is_hit()).count();
+            if hits == 0 {
+                continue;
+            }
+            stats.notes += hits;
+            add(&mut stats.chords, hits, 1);
+            add(&mut stats.nps, time.max(0.0) as usize, hits);
+            add(
+                &mut voltage_windows,
+                (beat / VOLTAGE_WINDOW_BEATS) as usize,
+                hits,
+            );
+            //Anything off the 8th note grid is chaotic
+            if !(position * 2).is_integer() {
+                chaos_rows += 1;
+            }
+        }
+        stats.peak_nps = stats.nps.iter().copied().max().unwrap_or_default();
+        let seconds = stats.length;
+        if seconds > 0.0 {
+            let per_second = |count: usize| count as f64 / seconds;
+            let peak_density =
+                voltage_windows.into_iter().max().unwrap_or_default() as f64 / VOLTAGE_WINDOW_BEATS;
+            stats.radar = RadarValues {
+                stream: (per_second(stats.notes) / STREAM_NPS).min(1.0),
+                voltage: (peak_density * last_beat / seconds / VOLTAGE_BPS).min(1.0),
+                air: per_second(stats.chords.iter().skip(2).sum()).min(1.0),
+                freeze: per_second(stats.holds + stats.rolls).min(1.0),
+                chaos: (per_second(chaos_rows) * CHAOS_SCALE).min(1.0),
+            };
+        }
+        Some(stats)
+    }
+
+    //Rows with exactly this many notes hit together
+    #[must_use]
+    pub fn rows_with(&self, notes: usize) -> usize {
+        self.chords.get(notes).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser_generic::row, BeatPair, Fraction};
+
+    #[test]
+    fn chart_stats() {
+        let chart = vec![
+            vec![
+                row(
+                    &[(NoteType::Tap, 0), (NoteType::Tap, 1)],
+                    Fraction::new(0, 1),
+                ),
+                row(&[(NoteType::Tap, 3)], Fraction::new(1, 12)),
+                row(&[(NoteType::Hold, 2)], Fraction::new(1, 4)),
+                row(&[(NoteType::Mine, 3)], Fraction::new(1, 2)),
+                row(&[(NoteType::HoldEnd, 2)], Fraction::new(3, 4)),
+            ],
+            vec![
+                row(&[(NoteType::Tap, 0)], Fraction::new(0, 1)),
+                row(&[(NoteType::AutoKeysound, 1)], Fraction::new(1, 2)),
+            ],
+        ];
+        //120 bpm is 2 seconds per measure
+        let structure = StructureData {
+            offset: Some(0.0),
+            bpms: vec![BeatPair::from_pair(0.0, 120.0).unwrap()],
+            ..StructureData::default()
+        };
+        let stats = ChartStats::new(&chart, &structure).unwrap();
+        assert_eq!(stats.notes, 5);
+        assert_eq!(stats.chords, vec![0, 3, 1]);
+        assert_eq!((stats.rows_with(2), stats.rows_with(4)), (1, 0));
+        assert_eq!((stats.holds, stats.rolls, stats.mines), (1, 0, 1));
+        assert_eq!(stats.length, 2.0);
+        assert_eq!(stats.nps, vec![4, 0, 1]);
+        assert_eq!(stats.peak_nps, 4);
+        assert_eq!(
+            stats.radar,
+            RadarValues {
+                stream: 2.5 / 7.0,
+                voltage: 0.125,
+                air: 0.5,
+                freeze: 0.5,
+                chaos: 0.25,
+            }
+        );
+        assert_eq!(ChartStats::new(&chart, &StructureData::default()), None);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{ParseError, ParseOptions};
+use encoding_rs::{Encoding, SHIFT_JIS_INIT, UTF_8, WINDOWS_1252_INIT};
+use std::{borrow::Cow, io};
+
+//Older packs were mostly written on Japanese or western Windows machines
+pub static LEGACY_ENCODINGS: [&Encoding; 2] = [&SHIFT_JIS_INIT, &WINDOWS_1252_INIT];
+
+//A byte order mark always wins, then the chosen encoding, then the first encoding that fits
+pub fn decode(bytes: &[u8], options: ParseOptions) -> Result<String, ParseError> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        return Ok(encoding
+            .decode_without_bom_handling(&bytes[bom_length..])
+            .0
+            .into_owned());
+    }
+    if let Some(encoding) = options.encoding {
+        return Ok(encoding.decode_without_bom_handling(bytes).0.into_owned());
+    }
+    std::iter::once(UTF_8)
+        .chain(options.fallback_encodings.iter().copied())
+        .find_map(|encoding| encoding.decode_without_bom_handling_and_without_replacement(bytes))
+        .map(Cow::into_owned)
+        .ok_or(ParseError::Encoding)
+}
+
+pub fn read_text(mut reader: impl io::Read, options: ParseOptions) -> Result<String, ParseError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    decode(&bytes, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::SHIFT_JIS;
+
+    fn detect(bytes: &[u8]) -> String {
+        decode(bytes, ParseOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn detect_encodings() {
+        assert_eq!(detect("#TITLE:\u{66f2};".as_bytes()), "#TITLE:\u{66f2};");
+        assert_eq!(detect(b"#TITLE:\x8b\xc8;"), "#TITLE:\u{66f2};");
+        assert_eq!(detect(b"#ARTIST:Caf\xe9 ;"), "#ARTIST:Caf\u{e9} ;");
+        assert_eq!(detect(b"\xef\xbb\xbf#TITLE:a;"), "#TITLE:a;");
+        assert_eq!(detect(b"\xff\xfe#\0A\0"), "#A");
+    }
+
+    #[test]
+    fn choose_encodings() {
+        let shift_jis = ParseOptions {
+            encoding: Some(SHIFT_JIS),
+            ..ParseOptions::default()
+        };
+        //Valid UTF-8 is still read as the chosen encoding
+        assert_eq!(
+            decode("\u{e9}".as_bytes(), shift_jis).unwrap(),
+            "\u{ff83}\u{ff69}"
+        );
+        let utf8_only = ParseOptions {
+            fallback_encodings: &[],
+            ..ParseOptions::default()
+        };
+        assert!(matches!(
+            decode(b"\x8b\xc8", utf8_only),
+            Err(ParseError::Encoding)
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{
+    parser_generic::{rows_to_chart, value},
+    BeatPair, Fraction, Measure, Note, NoteType, StructureData,
+};
+use std::{cmp::Ordering, collections::BTreeMap, convert::TryFrom};
+
+//Snaps tried in order when quantizing, as rows per measure
+const SNAPS: [i32; 10] = [4, 8, 12, 16, 24, 32, 48, 64, 96, 192];
+//Millisecond based formats round their times, so a row this close to a snap is considered on it
+const SNAP_TOLERANCE: f64 = 2.0;
+
+pub struct TimingPoint {
+    pub time: f64,
+    pub bpm: f64,
+}
+
+pub struct TimedNote {
+    pub column: usize,
+    pub time: f64,
+    pub end_time: Option<f64>,
+}
+
+//A timing segment in the converted chart, starting at a position in measures and a time in ms
+#[derive(Clone, Copy)]
+struct Segment {
+    position: Fraction,
+    time: f64,
+    bpm: f64,
+}
+
+//Converts charts that place everything in milliseconds, like osu! and Quaver, onto measures
+pub struct TimedChart {
+    segments: Vec<Segment>,
+    lead_in: i32,
+}
+
+fn ms_per_measure(bpm: f64) -> f64 {
+    240_000.0 / bpm
+}
+
+//Finds the coarsest snap within the tolerance of a position, falling back to the finest snap
+fn quantize(position: f64, ms_per_measure: f64) -> Fraction {
+    SNAPS
+        .iter()
+        .map(|&snap| Fraction::new((position * f64::from(snap)).round() as i32, snap))
+        .find(|snapped| (value(*snapped) - position).abs() * ms_per_measure <= SNAP_TOLERANCE)
+        .unwrap_or_else(|| Fraction::new((position * 192.0).round() as i32, 192))
+}
+
+fn segments(timing_points: &[TimingPoint], lead_in: i32) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = vec![];
+    for point in timing_points {
+        let segment = match segments.last_mut() {
+            None => Segment {
+                position: Fraction::from(lead_in),
+                time: point.time,
+                bpm: point.bpm,
+            },
+            Some(last) => {
+                //Snapping a bpm change moves it, so the previous bpm is stretched to keep it in sync
+                let exact =
+                    value(last.position) + (point.time - last.time) / ms_per_measure(last.bpm);
+                let position = quantize(exact, ms_per_measure(last.bpm));
+                if position <= last.position {
+                    *last = Segment {
+                        bpm: point.bpm,
+                        ..*last
+                    };
+                    continue;
+                }
+                if (value(position) - exact).abs() * ms_per_measure(last.bpm) > 0.5 {
+                    last.bpm =
+                        value(position - last.position) * 240_000.0 / (point.time - last.time);
+                }
+                Segment {
+                    position,
+                    time: point.time,
+                    bpm: point.bpm,
+                }
+            }
+        };
+        segments.push(segment);
+    }
+    segments
+}
+
+impl TimedChart {
+    //Notes before the first timing point get whole measures of the first bpm in front of it
+    pub fn new(mut timing_points: Vec<TimingPoint>, notes: &[TimedNote]) -> Option<Self> {
+        timing_points.retain(|point| point.bpm > 0.0);
+        timing_points.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+        let first = timing_points.first()?;
+        let earliest = notes
+            .iter()
+            .map(|note| note.time)
+            .fold(first.time, f64::min);
+        let lead_in = ((first.time - earliest) / ms_per_measure(first.bpm)).ceil() as i32;
+        Some(Self {
+            segments: segments(&timing_points, lead_in),
+            lead_in,
+        })
+    }
+
+    pub fn position(&self, time: f64) -> Fraction {
+        let segment = self
+            .segments
+            .iter()
+            .rev()
+            .find(|segment| segment.time <= time)
+            .unwrap_or(&self.segments[0]);
+        segment.position
+            + quantize(
+                (time - segment.time) / ms_per_measure(segment.bpm),
+                ms_per_measure(segment.bpm),
+            )
+    }
+
+    pub fn write_structure(&self, structure: &mut StructureData) {
+        let first = self.segments[0];
+        structure.offset =
+            Some((first.time - f64::from(self.lead_in) * ms_per_measure(first.bpm)) / 1000.0);
+        structure.bpms = vec![BeatPair::at_start(first.bpm)];
+        structure.bpms.extend(
+            self.segments
+                .iter()
+                .skip(1)
+                .map(|segment| BeatPair::at_position(segment.position, segment.bpm)),
+        );
+    }
+
+    pub fn chart(&self, notes: &[TimedNote]) -> Vec<Measure> {
+        let row = |time| {
+            let position = self.position(time);
+            (
+                usize::try_from(position.to_integer()).unwrap_or(0),
+                position.fract(),
+            )
+        };
+        let mut rows: BTreeMap<(usize, Fraction), Vec<Note>> = BTreeMap::new();
+        for note in notes {
+            let start = row(note.time);
+            let (note_type, end) = match note.end_time.map(row) {
+                Some(end) if end > start => (NoteType::Hold, Some(end)),
+                _ => (NoteType::Tap, None),
+            };
+            rows.entry(start)
+                .or_default()
+                .push(Note::new(note_type, note.column));
+            if let Some(end) = end {
+                rows.entry(end)
+                    .or_default()
+                    .push(Note::new(NoteType::HoldEnd, note.column));
+            }
+        }
+        rows_to_chart(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantize_rows() {
+        assert_eq!(quantize(0.25, 2000.0), Fraction::new(1, 4));
+        assert_eq!(quantize(0.3335, 2000.0), Fraction::new(1, 3));
+        assert_eq!(quantize(0.1, 2000.0), Fraction::new(19, 192));
+    }
+
+    #[test]
+    fn snapped_bpm_changes_stay_in_sync() {
+        //The second point is 1ms late for a sixteenth, so the first bpm stretches to land on it
+        let chart = TimedChart::new(
+            vec![
+                TimingPoint {
+                    time: 0.0,
+                    bpm: 120.0,
+                },
+                TimingPoint {
+                    time: 126.0,
+                    bpm: 60.0,
+                },
+            ],
+            &[],
+        )
+        .unwrap();
+        let mut structure = StructureData::default();
+        chart.write_structure(&mut structure);
+        assert_eq!(structure.offset, Some(0.0));
+        assert_eq!(structure.bpms[1].sub_beat, Fraction::new(1, 16));
+        assert!((structure.bpms[0].value - 119.047_619).abs() < 0.001);
+        assert_eq!(chart.position(1126.0), Fraction::new(5, 16));
+    }
+}
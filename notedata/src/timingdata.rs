@@ -1,8 +1,211 @@
-use crate::NOTEFIELD_SIZE;
-use crate::{Fraction, Measure, NoteData, NoteType, StructureData};
+use crate::{
+    parser_generic::value, BeatPair, Fraction, Measure, NoteData, NoteType, StructureData,
+};
+use std::{cmp::Ordering, path::PathBuf};
 
-fn value(fraction: Fraction) -> f64 {
-    f64::from(*fraction.numer()) / f64::from(*fraction.denom())
+//Positions in StructureData are in measures, the timing map works in beats
+fn beats<T>(pair: &BeatPair<T>) -> f64 {
+    (f64::from(pair.beat) + value(pair.sub_beat)) * 4.0
+}
+
+//Converts between beats and milliseconds for a song's timing. Stops and delays pause the song,
+//while warps, negative bpms and negative stops skip over the beats they cover.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimingMap {
+    //Each bpm segment as its start in beats, its start in ms before pauses and warps, and its bpm
+    bpms: Vec<(f64, f64, f64)>,
+    //Pauses as their beat and length in ms, a stop comes after its beat's notes and a delay before
+    stops: Vec<(f64, f64)>,
+    delays: Vec<(f64, f64)>,
+    //Skipped ranges of beats, sorted and not overlapping
+    warps: Vec<(f64, f64)>,
+    //The linear pieces of beat_to_ms as their start beat, start in ms and ms per beat
+    pieces: Vec<(f64, f64, f64)>,
+}
+
+impl TimingMap {
+    //There's no timing without a positive bpm
+    #[must_use]
+    pub fn new(structure: &StructureData) -> Option<Self> {
+        let mut changes: Vec<_> = structure
+            .bpms
+            .iter()
+            .map(|bpm| (beats(bpm), bpm.value))
+            .collect();
+        changes.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        let mut warps: Vec<_> = structure
+            .warps
+            .iter()
+            .flatten()
+            .map(|warp| (beats(warp), beats(warp) + warp.value))
+            .collect();
+        //Time runs backwards under a negative bpm, so everything until it catches up is skipped
+        let mut bpms: Vec<(f64, f64, f64)> = vec![];
+        let mut negative = None;
+        for (beat, bpm) in changes {
+            if bpm < 0.0 {
+                negative = negative.or(Some((beat, -bpm)));
+            } else if bpm > 0.0 {
+                if let Some((start, negative_bpm)) = negative.take() {
+                    warps.push((start, beat + (beat - start) * bpm / negative_bpm));
+                }
+                bpms.push((beat, 0.0, bpm));
+            }
+        }
+        if let Some((start, _)) = negative {
+            warps.push((start, f64::INFINITY));
+        }
+        let (first_beat, _, first_bpm) = *bpms.first()?;
+        let mut time =
+            structure.offset.unwrap_or_default() * 1000.0 + first_beat * 60_000.0 / first_bpm;
+        let mut previous = (first_beat, first_bpm);
+        for bpm in &mut bpms {
+            time += (bpm.0 - previous.0) * 60_000.0 / previous.1;
+            bpm.1 = time;
+            previous = (bpm.0, bpm.2);
+        }
+        let mut map = Self {
+            bpms,
+            stops: vec![],
+            delays: vec![],
+            warps: vec![],
+            pieces: vec![],
+        };
+        //A negative pause skips the beats that would have taken that long
+        let mut pauses = |segments: &Option<Vec<BeatPair<f64>>>| -> Vec<(f64, f64)> {
+            let mut pauses = vec![];
+            for segment in segments.iter().flatten() {
+                let beat = beats(segment);
+                if segment.value < 0.0 {
+                    warps.push((beat, beat - segment.value * map.bpm_at_beat(beat) / 60.0));
+                } else {
+                    pauses.push((beat, segment.value * 1000.0));
+                }
+            }
+            pauses
+        };
+        let stops = pauses(&structure.stops);
+        let delays = pauses(&structure.delays);
+        warps.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        for warp in warps.into_iter().filter(|warp| warp.0 < warp.1) {
+            match map.warps.last_mut() {
+                Some(last) if warp.0 <= last.1 => last.1 = last.1.max(warp.1),
+                _ => map.warps.push(warp),
+            }
+        }
+        //Pauses inside a warp are skipped along with it
+        map.stops = stops
+            .into_iter()
+            .filter(|stop| !map.is_warped(stop.0))
+            .collect();
+        map.delays = delays
+            .into_iter()
+            .filter(|delay| !map.is_warped(delay.0))
+            .collect();
+        map.pieces = map.pieces();
+        Some(map)
+    }
+
+    fn pieces(&self) -> Vec<(f64, f64, f64)> {
+        let mut starts: Vec<_> = self
+            .bpms
+            .iter()
+            .map(|bpm| bpm.0)
+            .chain(self.stops.iter().chain(&self.delays).map(|pause| pause.0))
+            .chain(self.warps.iter().flat_map(|warp| vec![warp.0, warp.1]))
+            .filter(|beat| beat.is_finite())
+            .collect();
+        starts.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        starts.dedup();
+        starts
+            .into_iter()
+            .map(|beat| {
+                let stopped: f64 = self
+                    .stops
+                    .iter()
+                    .filter(|stop| stop.0 == beat)
+                    .map(|stop| stop.1)
+                    .sum();
+                let ms_per_beat = if self.is_warped(beat) {
+                    0.0
+                } else {
+                    60_000.0 / self.bpm_at_beat(beat)
+                };
+                (beat, self.beat_to_ms(beat) + stopped, ms_per_beat)
+            })
+            .collect()
+    }
+
+    fn unpaused_ms(&self, beat: f64) -> f64 {
+        let (start, time, bpm) = self
+            .bpms
+            .iter()
+            .rev()
+            .find(|bpm| bpm.0 <= beat)
+            .unwrap_or(&self.bpms[0]);
+        time + (beat - start) * 60_000.0 / bpm
+    }
+
+    //When a note on the beat is hit, which for a beat in a warp is when the warp starts
+    #[must_use]
+    pub fn beat_to_ms(&self, beat: f64) -> f64 {
+        let paused: f64 = self
+            .stops
+            .iter()
+            .filter(|stop| stop.0 < beat)
+            .chain(self.delays.iter().filter(|delay| delay.0 <= beat))
+            .map(|pause| pause.1)
+            .sum();
+        let warped: f64 = self
+            .warps
+            .iter()
+            .filter(|warp| warp.0 < beat)
+            .map(|warp| self.unpaused_ms(beat.min(warp.1)) - self.unpaused_ms(warp.0))
+            .sum();
+        self.unpaused_ms(beat) + paused - warped
+    }
+
+    //The beat the song is on at a time, which stays put during pauses and jumps over warps
+    #[must_use]
+    pub fn ms_to_beat(&self, ms: f64) -> f64 {
+        let index = self
+            .pieces
+            .iter()
+            .rposition(|piece| piece.1 <= ms)
+            .unwrap_or_default();
+        let (start, time, ms_per_beat) = self.pieces[index];
+        let beat = if ms_per_beat > 0.0 {
+            start + (ms - time) / ms_per_beat
+        } else {
+            start
+        };
+        match self.pieces.get(index + 1) {
+            Some(next) => beat.min(next.0),
+            None => beat,
+        }
+    }
+
+    #[must_use]
+    pub fn bpm_at_beat(&self, beat: f64) -> f64 {
+        self.bpms
+            .iter()
+            .rev()
+            .find(|bpm| bpm.0 <= beat)
+            .unwrap_or(&self.bpms[0])
+            .2
+    }
+
+    #[must_use]
+    pub fn bpm_at(&self, ms: f64) -> f64 {
+        self.bpm_at_beat(self.ms_to_beat(ms))
+    }
+
+    #[must_use]
+    pub fn is_warped(&self, beat: f64) -> bool {
+        self.warps
+            .iter()
+            .any(|warp| warp.0 <= beat && beat < warp.1)
+    }
 }
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
@@ -18,7 +221,9 @@ pub struct TimingData<T>
 where
     T: TimingInfo,
 {
-    pub notes: [TimingColumn<T>; NOTEFIELD_SIZE],
+    pub notes: Vec<TimingColumn<T>>,
+    //The samples keysound indices point to, relative to the song's folder
+    pub keysounds: Vec<PathBuf>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -41,17 +246,17 @@ where
 pub trait TimingInfo: Copy {}
 
 pub trait LayoutInfo {
-    fn from_layout(time: i64, sprite: Rectangle, note: NoteType) -> Self;
+    fn from_layout(time: i64, sprite: Rectangle, note: NoteType, keysound: Option<usize>) -> Self;
 }
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
-pub struct GameplayInfo(pub i64, pub Rectangle, pub NoteType);
+pub struct GameplayInfo(pub i64, pub Rectangle, pub NoteType, pub Option<usize>);
 
 impl TimingInfo for GameplayInfo {}
 
 impl LayoutInfo for GameplayInfo {
-    fn from_layout(time: i64, sprite: Rectangle, note: NoteType) -> Self {
-        Self(time, sprite, note)
+    fn from_layout(time: i64, sprite: Rectangle, note: NoteType, keysound: Option<usize>) -> Self {
+        Self(time, sprite, note, keysound)
     }
 }
 
@@ -67,7 +272,12 @@ impl Rectangle {
 impl TimingInfo for CalcInfo {}
 
 impl LayoutInfo for CalcInfo {
-    fn from_layout(time: i64, _sprite: Rectangle, note: NoteType) -> Self {
+    fn from_layout(
+        time: i64,
+        _sprite: Rectangle,
+        note: NoteType,
+        _keysound: Option<usize>,
+    ) -> Self {
         Self(time, note)
     }
 }
@@ -139,71 +349,73 @@ where
     {
         data.charts
             .iter()
-            .map(|chart| Self::from_chartdata::<U>(chart, &data.structure, &sprite_finder, rate))
+            .map(|(info, chart)| Self {
+                keysounds: data.meta.keysounds.iter().map(PathBuf::from).collect(),
+                ..Self::from_chartdata::<U>(
+                    chart,
+                    info.structure_or(&data.structure),
+                    info.columns(chart),
+                    &sprite_finder,
+                    rate,
+                )
+            })
             .collect()
     }
     pub fn from_chartdata<U>(
         data: &[Measure],
         structure: &StructureData,
+        columns: usize,
         sprite_finder: &U,
         rate: f64,
     ) -> Self
     where
         U: Fn(usize, f64, Fraction, NoteType, usize) -> Rectangle,
     {
-        let offset = structure.offset.unwrap_or_default() * 1000.0;
-        let mut bpms: Vec<_> = structure
-            .bpms
+        let map = match TimingMap::new(structure) {
+            Some(map) => map,
+            None => return Self::new(columns),
+        };
+        let fakes: Vec<_> = structure
+            .fakes
             .iter()
-            .map(|beat_pair| (beat_pair, 0.0))
+            .flatten()
+            .map(|fake| (beats(fake), beats(fake) + fake.value))
             .collect();
-        match bpms.get_mut(0) {
-            Some(bpm) => bpm.1 = offset,
-            None => return Self::new(),
-        };
-        for i in 1..bpms.len() {
-            bpms[i].1 = bpms[i - 1].1
-                + ((f64::from(bpms[i].0.beat - bpms[i - 1].0.beat)
-                    + value(bpms[i].0.sub_beat - bpms[i - 1].0.sub_beat))
-                    * 240_000.0
-                    / bpms[i - 1].0.value);
-        }
-        let mut bpms = bpms.into_iter();
-        let mut current_bpm = bpms.next().unwrap();
-        let mut next_bpm = bpms.next();
-        let mut output: [TimingColumn<T>; NOTEFIELD_SIZE] =
-            array_init::array_init(|_| TimingColumn::new());
+        let mut output = vec![TimingColumn::new(); columns];
         for (measure_index, measure) in data.iter().enumerate() {
             for (row, inner_time) in measure.iter() {
-                while let Some(bpm) = next_bpm {
-                    if measure_index as i32 > bpm.0.beat
-                        || (measure_index as i32 == bpm.0.beat
-                            && bpm.0.sub_beat <= inner_time.fract())
+                let beat = (measure_index as f64 + value(*inner_time)) * 4.0;
+                let is_fake =
+                    map.is_warped(beat) || fakes.iter().any(|fake| fake.0 <= beat && beat < fake.1);
+                let row_time = map.beat_to_ms(beat) / rate;
+                for note in row.iter() {
+                    //A hold is faked or kept whole by its head, wherever it ends
+                    let note_type = if is_fake
+                        && !matches!(note.note_type, NoteType::AutoKeysound | NoteType::HoldEnd)
                     {
-                        current_bpm = bpm;
-                        next_bpm = bpms.next();
+                        NoteType::Fake
                     } else {
-                        break;
-                    }
-                }
-                let row_time = (current_bpm.1
-                    + 240_000.0
-                        * ((measure_index - current_bpm.0.beat as usize) as f64
-                            + value(inner_time - current_bpm.0.sub_beat))
-                        / current_bpm.0.value)
-                    / rate;
-                for note in row.iter() {
+                        note.note_type
+                    };
                     let sprite =
                         sprite_finder(measure_index, 0.0, *inner_time, note.note_type, note.column);
                     //This if let can hide errors in the parser or .sm file
                     // An else clause should be added where errors are handled
                     if let Some(column) = output.get_mut(note.column) {
-                        column.add(T::from_layout(row_time as i64, sprite, note.note_type));
+                        column.add(T::from_layout(
+                            row_time as i64,
+                            sprite,
+                            note_type,
+                            note.keysound,
+                        ));
                     }
                 }
             }
         }
-        Self { notes: output }
+        Self {
+            notes: output,
+            keysounds: vec![],
+        }
     }
 }
 
@@ -211,9 +423,10 @@ impl<T> TimingData<T>
 where
     T: TimingInfo,
 {
-    pub fn new() -> Self {
+    pub fn new(columns: usize) -> Self {
         Self {
-            notes: array_init::array_init(|_| TimingColumn::new()),
+            notes: vec![TimingColumn::new(); columns],
+            keysounds: vec![],
         }
     }
 }
@@ -246,6 +459,297 @@ impl TimingColumn<Judgement> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{ChartInfo, Note};
+
+    fn note_times(chart: &[Measure], structure: &StructureData, rate: f64) -> Vec<Vec<i64>> {
+        TimingData::<CalcInfo>::from_chartdata(
+            chart,
+            structure,
+            4,
+            &|_, _, _, _, _| Rectangle::default(),
+            rate,
+        )
+        .notes
+        .iter()
+        .map(|column| column.notes.iter().map(|note| note.0).collect())
+        .collect()
+    }
+
+    fn tap(column: usize, time: Fraction) -> (Vec<Note>, Fraction) {
+        (vec![Note::new(NoteType::Tap, column)], time)
+    }
+
+    #[test]
+    fn bpm_changes_and_stops() {
+        let structure = StructureData {
+            offset: Some(0.1),
+            bpms: vec![
+                BeatPair::from_pair(0.0, 120.0).unwrap(),
+                BeatPair::from_pair(1.0, 240.0).unwrap(),
+            ],
+            stops: Some(vec![
+                BeatPair::from_pair(0.5, 0.5).unwrap(),
+                BeatPair::from_pair(1.0, 0.125).unwrap(),
+                BeatPair::from_pair(1.5, 0.25).unwrap(),
+            ]),
+            ..StructureData::default()
+        };
+        let chart = vec![
+            vec![
+                tap(0, Fraction::new(0, 1)),
+                tap(1, Fraction::new(1, 2)),
+                tap(2, Fraction::new(3, 4)),
+            ],
+            vec![
+                tap(0, Fraction::new(0, 1)),
+                tap(1, Fraction::new(1, 2)),
+                tap(2, Fraction::new(3, 4)),
+            ],
+            vec![tap(3, Fraction::new(0, 1))],
+        ];
+        //120 bpm is 2000ms per measure, 240 bpm is 1000ms per measure
+        assert_eq!(
+            note_times(&chart, &structure, 1.0),
+            vec![
+                vec![100, 100 + 2000 + 500],
+                vec![100 + 1000, 100 + 2000 + 500 + 125 + 500],
+                vec![100 + 1500 + 500, 100 + 2000 + 500 + 125 + 750 + 250],
+                vec![100 + 2000 + 500 + 125 + 1000 + 250],
+            ]
+        );
+        assert_eq!(
+            note_times(&chart, &structure, 2.0)[3],
+            vec![(100 + 2000 + 500 + 125 + 1000 + 250) / 2]
+        );
+    }
+
+    #[test]
+    fn stops_before_bpm_change() {
+        let structure = StructureData {
+            offset: None,
+            bpms: vec![
+                BeatPair::from_pair(0.0, 60.0).unwrap(),
+                BeatPair::from_pair(0.25, 180.0).unwrap(),
+            ],
+            stops: Some(vec![
+                BeatPair::from_pair(0.125, 1.0).unwrap(),
+                BeatPair::from_pair(0.0, 2.0).unwrap(),
+            ]),
+            ..StructureData::default()
+        };
+        let chart = vec![vec![
+            tap(0, Fraction::new(0, 1)),
+            tap(0, Fraction::new(1, 8)),
+            tap(0, Fraction::new(1, 4)),
+            tap(0, Fraction::new(1, 2)),
+        ]];
+        //60 bpm is 1000ms per beat, 180 bpm is 333.33ms per beat
+        assert_eq!(
+            note_times(&chart, &structure, 1.0)[0],
+            vec![0, 500 + 2000, 1000 + 3000, 1000 + 3000 + 333]
+        );
+    }
+
+    #[test]
+    fn delays_warps_and_fakes() {
+        let structure = StructureData {
+            offset: None,
+            bpms: vec![BeatPair::from_pair(0.0, 120.0).unwrap()],
+            stops: Some(vec![BeatPair::from_pair(0.25, 0.5).unwrap()]),
+            delays: Some(vec![BeatPair::from_pair(0.5, 0.25).unwrap()]),
+            warps: Some(vec![BeatPair::from_pair(1.0, 2.0).unwrap()]),
+            fakes: Some(vec![BeatPair::from_pair(2.0, 1.0).unwrap()]),
+            ..StructureData::default()
+        };
+        let chart = vec![
+            vec![
+                tap(0, Fraction::new(1, 4)),
+                tap(1, Fraction::new(1, 2)),
+                tap(2, Fraction::new(3, 4)),
+            ],
+            vec![
+                tap(0, Fraction::new(0, 1)),
+                tap(1, Fraction::new(1, 4)),
+                tap(2, Fraction::new(1, 2)),
+                tap(3, Fraction::new(3, 4)),
+            ],
+            vec![tap(0, Fraction::new(0, 1)), tap(1, Fraction::new(1, 4))],
+        ];
+        let notes = TimingData::<CalcInfo>::from_chartdata(
+            &chart,
+            &structure,
+            4,
+            &|_, _, _, _, _| Rectangle::default(),
+            1.0,
+        )
+        .notes;
+        let times: Vec<Vec<i64>> = notes
+            .iter()
+            .map(|column| column.notes.iter().map(|note| note.0).collect())
+            .collect();
+        let types: Vec<Vec<NoteType>> = notes
+            .iter()
+            .map(|column| column.notes.iter().map(|note| note.1).collect())
+            .collect();
+        //The warp skips the first half of measure 1, which lasts 1000ms at 120 bpm
+        assert_eq!(
+            times,
+            vec![
+                vec![500, 2750, 2750 + 1000],
+                vec![1000 + 500 + 250, 2750, 2750 + 1500],
+                vec![1500 + 750, 2750],
+                vec![2750 + 500],
+            ]
+        );
+        assert_eq!(
+            types,
+            vec![
+                vec![NoteType::Tap, NoteType::Fake, NoteType::Fake],
+                vec![NoteType::Tap, NoteType::Fake, NoteType::Tap],
+                vec![NoteType::Tap, NoteType::Tap],
+                vec![NoteType::Tap],
+            ]
+        );
+    }
+
+    #[test]
+    fn holds_across_fakes() {
+        let structure = StructureData {
+            offset: None,
+            bpms: vec![BeatPair::from_pair(0.0, 120.0).unwrap()],
+            fakes: Some(vec![BeatPair::from_pair(0.5, 2.0).unwrap()]),
+            ..StructureData::default()
+        };
+        let row = |note_type, column, time| (vec![Note::new(note_type, column)], time);
+        //The hold in column 0 ends inside the fake segment, the one in column 1 starts there
+        let chart = vec![vec![
+            row(NoteType::Hold, 0, Fraction::new(0, 1)),
+            row(NoteType::HoldEnd, 0, Fraction::new(1, 2)),
+            row(NoteType::Hold, 1, Fraction::new(5, 8)),
+            row(NoteType::HoldEnd, 1, Fraction::new(7, 8)),
+        ]];
+        let types: Vec<Vec<NoteType>> = TimingData::<CalcInfo>::from_chartdata(
+            &chart,
+            &structure,
+            2,
+            &|_, _, _, _, _| Rectangle::default(),
+            1.0,
+        )
+        .notes
+        .iter()
+        .map(|column| column.notes.iter().map(|note| note.1).collect())
+        .collect();
+        assert_eq!(
+            types,
+            vec![
+                vec![NoteType::Hold, NoteType::HoldEnd],
+                vec![NoteType::Fake, NoteType::HoldEnd],
+            ]
+        );
+    }
+
+    fn timing_map(bpms: &[(f64, f64)], stops: &[(f64, f64)]) -> TimingMap {
+        let pairs = |pairs: &[(f64, f64)]| -> Vec<BeatPair<f64>> {
+            pairs
+                .iter()
+                .map(|&(beat, value)| BeatPair::from_pair(beat / 4.0, value).unwrap())
+                .collect()
+        };
+        TimingMap::new(&StructureData {
+            offset: Some(-0.5),
+            bpms: pairs(bpms),
+            stops: Some(pairs(stops)),
+            ..StructureData::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn beats_and_times() {
+        let map = timing_map(&[(0.0, 120.0), (4.0, 60.0)], &[(2.0, 0.25)]);
+        //500ms per beat until beat 4, then 1000ms per beat
+        for &(beat, ms) in &[
+            (-1.0, -1000.0),
+            (0.0, -500.0),
+            (2.0, 500.0),
+            (3.0, 1250.0),
+            (4.0, 1750.0),
+            (6.5, 4250.0),
+        ] {
+            assert_eq!(map.beat_to_ms(beat), ms);
+            assert_eq!(map.ms_to_beat(ms), beat);
+        }
+        //The song stays on the stop's beat until it ends
+        assert_eq!(map.ms_to_beat(600.0), 2.0);
+        assert_eq!(map.ms_to_beat(750.0), 2.0);
+        assert_eq!(map.bpm_at(1000.0), 120.0);
+        assert_eq!(map.bpm_at(2000.0), 60.0);
+        assert_eq!(map.bpm_at_beat(4.0), 60.0);
+        assert!(TimingMap::new(&StructureData::default()).is_none());
+    }
+
+    #[test]
+    fn negative_timing_warps() {
+        //Time at -120 bpm from beat 4 to 6 goes back 1000ms, which 120 bpm takes until beat 8
+        let map = timing_map(&[(0.0, 120.0), (4.0, -120.0), (6.0, 120.0)], &[]);
+        assert!(!map.is_warped(3.9));
+        assert!(map.is_warped(4.0));
+        assert!(map.is_warped(7.9));
+        assert!(!map.is_warped(8.0));
+        assert_eq!(map.beat_to_ms(5.0), 1500.0);
+        assert_eq!(map.beat_to_ms(8.0), 1500.0);
+        assert_eq!(map.beat_to_ms(9.0), 2000.0);
+        assert_eq!(map.ms_to_beat(1500.0), 8.0);
+        assert_eq!(map.ms_to_beat(2000.0), 9.0);
+        //A negative stop skips the beats it would have taken
+        let stop_map = timing_map(&[(0.0, 120.0)], &[(4.0, -1.0)]);
+        assert!(stop_map.is_warped(5.9));
+        assert_eq!(stop_map.beat_to_ms(6.0), 1500.0);
+        assert_eq!(stop_map.ms_to_beat(2000.0), 7.0);
+    }
+
+    #[test]
+    fn columns_from_steps_type() {
+        let mut data = NoteData::new();
+        data.structure.bpms = vec![BeatPair::from_pair(0.0, 120.0).unwrap()];
+        let chart = vec![vec![
+            tap(0, Fraction::new(0, 1)),
+            tap(5, Fraction::new(1, 2)),
+        ]];
+        let info = |steps_type: &str| ChartInfo {
+            steps_type: steps_type.to_owned(),
+            ..ChartInfo::default()
+        };
+        data.charts = vec![
+            (info("dance-double"), chart.clone()),
+            (info("pump-single"), chart.clone()),
+            (info("kb7-single"), chart.clone()),
+            (info("unknown"), chart),
+        ];
+        let timing =
+            TimingData::<CalcInfo>::from_notedata(&data, |_, _, _, _, _| Rectangle::default(), 1.0);
+        assert_eq!(
+            timing
+                .iter()
+                .map(|chart_timing| chart_timing.notes.len())
+                .collect::<Vec<_>>(),
+            vec![8, 5, 7, 6]
+        );
+        assert_eq!(
+            timing[0].notes[5].notes,
+            vec![CalcInfo(1000, NoteType::Tap)]
+        );
+        //Notes outside of the steps type's columns are dropped
+        assert_eq!(
+            timing[1]
+                .notes
+                .iter()
+                .map(|column| column.notes.len())
+                .sum::<usize>(),
+            1
+        );
+    }
+
     #[test]
     fn wife_symmetry() {
         for offset in 0..180 {
@@ -0,0 +1,320 @@
+use crate::{Chart, Fraction, NoteType};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use std::{fmt, str::FromStr};
+
+//Shuffles take a seed so a replay or another player gets the same chart
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transform {
+    Mirror,
+    Left,
+    Right,
+    Shuffle(u64),
+    SuperShuffle(u64),
+    NoMines,
+    NoHolds,
+    NoJumps,
+    Little,
+}
+
+impl Transform {
+    pub fn apply(self, chart: &mut Chart, columns: usize) {
+        match self {
+            Self::Mirror => permute(chart, &(0..columns).rev().collect::<Vec<_>>()),
+            Self::Left => permute(chart, &turn(columns)),
+            Self::Right => permute(chart, &invert(&turn(columns))),
+            Self::Shuffle(seed) => {
+                let mut mapping: Vec<_> = (0..columns).collect();
+                mapping.shuffle(&mut StdRng::seed_from_u64(seed));
+                permute(chart, &mapping)
+            }
+            Self::SuperShuffle(seed) => super_shuffle(chart, columns, seed),
+            Self::NoMines => retain(chart, |_, note_type, _| note_type != NoteType::Mine),
+            Self::NoHolds => {
+                for (row, _) in chart.iter_mut().flatten() {
+                    row.retain(|note| note.note_type != NoteType::HoldEnd);
+                    for note in row {
+                        if let NoteType::Hold | NoteType::Roll = note.note_type {
+                            note.note_type = NoteType::Tap
+                        }
+                    }
+                }
+                remove_empty_rows(chart);
+            }
+            //Only the leftmost note of a jump is kept
+            Self::NoJumps => retain(chart, |_, note_type, earlier_hits| {
+                !note_type.is_hit() || earlier_hits == 0
+            }),
+            //Only notes on a beat are kept, along with the background keysounds
+            Self::Little => retain(chart, |time, note_type, _| {
+                note_type == NoteType::AutoKeysound || (time * 4).is_integer()
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Mirror => write!(f, "mirror"),
+            Self::Left => write!(f, "left"),
+            Self::Right => write!(f, "right"),
+            Self::Shuffle(seed) => write!(f, "shuffle={}", seed),
+            Self::SuperShuffle(seed) => write!(f, "supershuffle={}", seed),
+            Self::NoMines => write!(f, "nomines"),
+            Self::NoHolds => write!(f, "noholds"),
+            Self::NoJumps => write!(f, "nojumps"),
+            Self::Little => write!(f, "little"),
+        }
+    }
+}
+
+//Shuffles without a seed, like "shuffle" instead of "shuffle=12", get a random one
+impl FromStr for Transform {
+    type Err = String;
+
+    fn from_str(transform: &str) -> Result<Self, Self::Err> {
+        let mut parts = transform.trim().splitn(2, '=');
+        let name = parts.next().unwrap_or_default().to_lowercase();
+        let seed = match parts.next() {
+            Some(seed) => seed
+                .trim()
+                .parse()
+                .map_err(|_| format!("bad seed in transform {}", name))?,
+            None => rand::random(),
+        };
+        Ok(match name.as_str() {
+            "mirror" => Self::Mirror,
+            "left" => Self::Left,
+            "right" => Self::Right,
+            "shuffle" => Self::Shuffle(seed),
+            "supershuffle" => Self::SuperShuffle(seed),
+            "nomines" => Self::NoMines,
+            "noholds" => Self::NoHolds,
+            "nojumps" => Self::NoJumps,
+            "little" => Self::Little,
+            _ => return Err(format!("unknown transform {}", name)),
+        })
+    }
+}
+
+//Each column's new column, columns past the end of the mapping are left alone
+fn permute(chart: &mut Chart, mapping: &[usize]) {
+    for (row, _) in chart.iter_mut().flatten() {
+        for note in row.iter_mut() {
+            if let Some(&column) = mapping.get(note.column) {
+                note.column = column
+            }
+        }
+        row.sort_by_key(|note| note.column);
+    }
+}
+
+fn invert(mapping: &[usize]) -> Vec<usize> {
+    let mut inverse = vec![0; mapping.len()];
+    for (column, &target) in mapping.iter().enumerate() {
+        inverse[target] = column;
+    }
+    inverse
+}
+
+//Turning left on a dance pad sends left to down, down to right, up to left and right to up. Each
+//pad of a double chart turns on its own, other layouts move every note one column left.
+fn turn(columns: usize) -> Vec<usize> {
+    match columns % 4 {
+        0 => (0..columns)
+            .map(|column| column - column % 4 + [1, 3, 0, 2][column % 4])
+            .collect(),
+        _ => (0..columns)
+            .map(|column| (column + columns - 1) % columns)
+            .collect(),
+    }
+}
+
+//Every note gets a random column, avoiding columns that are in the middle of a hold
+fn super_shuffle(chart: &mut Chart, columns: usize, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    //The new column of each hold in progress, by its original column
+    let mut holds = vec![None; columns];
+    for (row, _) in chart.iter_mut().flatten() {
+        let mut free: Vec<_> = (0..columns)
+            .filter(|column| !holds.contains(&Some(*column)))
+            .collect();
+        free.shuffle(&mut rng);
+        for note in row.iter_mut() {
+            let hold = holds.get_mut(note.column);
+            note.column = match (note.note_type, hold) {
+                (NoteType::HoldEnd, Some(hold)) => hold.take().unwrap_or(note.column),
+                (NoteType::Hold, Some(hold)) | (NoteType::Roll, Some(hold)) => {
+                    *hold = free.pop();
+                    hold.unwrap_or(note.column)
+                }
+                _ => free.pop().unwrap_or(note.column),
+            };
+        }
+        row.sort_by_key(|note| note.column);
+    }
+}
+
+//Keeps the notes the filter accepts along with the ends of any holds it keeps. The filter gets
+//each note's position in its measure, its type and how many notes were hit earlier in its row.
+fn retain(chart: &mut Chart, mut keep: impl FnMut(Fraction, NoteType, usize) -> bool) {
+    let mut dropped_holds = vec![];
+    for (row, time) in chart.iter_mut().flatten() {
+        let mut earlier_hits = 0;
+        row.retain(|note| {
+            if note.note_type == NoteType::HoldEnd {
+                return match dropped_holds
+                    .iter()
+                    .position(|&column| column == note.column)
+                {
+                    Some(index) => {
+                        dropped_holds.swap_remove(index);
+                        false
+                    }
+                    None => true,
+                };
+            }
+            let kept = keep(*time, note.note_type, earlier_hits);
+            if note.note_type.is_hit() {
+                earlier_hits += 1;
+            }
+            if !kept {
+                if let NoteType::Hold | NoteType::Roll = note.note_type {
+                    dropped_holds.push(note.column)
+                }
+            }
+            kept
+        });
+    }
+    remove_empty_rows(chart);
+}
+
+fn remove_empty_rows(chart: &mut Chart) {
+    for measure in chart.iter_mut() {
+        measure.retain(|(row, _)| !row.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Note;
+
+    fn chart(rows: &[&str]) -> Chart {
+        vec![rows
+            .iter()
+            .enumerate()
+            .map(|(index, row)| {
+                (
+                    row.chars()
+                        .enumerate()
+                        .filter_map(|(column, note)| {
+                            let note_type = match note {
+                                '1' => NoteType::Tap,
+                                '2' => NoteType::Hold,
+                                '3' => NoteType::HoldEnd,
+                                '4' => NoteType::Roll,
+                                'M' => NoteType::Mine,
+                                _ => return None,
+                            };
+                            Some(Note::new(note_type, column))
+                        })
+                        .collect::<Vec<_>>(),
+                    Fraction::new(index as i32, rows.len() as i32),
+                )
+            })
+            .filter(|(row, _)| !row.is_empty())
+            .collect()]
+    }
+
+    fn transformed(transform: Transform, rows: &[&str]) -> Chart {
+        let mut transformed = chart(rows);
+        transform.apply(&mut transformed, rows[0].len());
+        transformed
+    }
+
+    #[test]
+    fn permutations() {
+        let rows = ["1000", "0100", "0010", "0001"];
+        assert_eq!(
+            transformed(Transform::Mirror, &rows),
+            chart(&["0001", "0010", "0100", "1000"])
+        );
+        assert_eq!(
+            transformed(Transform::Left, &rows),
+            chart(&["0100", "0001", "1000", "0010"])
+        );
+        assert_eq!(
+            transformed(Transform::Right, &rows),
+            chart(&["0010", "1000", "0001", "0100"])
+        );
+        assert_eq!(
+            transformed(Transform::Left, &["10000", "00001"]),
+            chart(&["00001", "00010"])
+        );
+        let shuffled = transformed(Transform::Shuffle(7), &rows);
+        assert_eq!(shuffled, transformed(Transform::Shuffle(7), &rows));
+        let mut columns: Vec<_> = shuffled[0].iter().map(|(row, _)| row[0].column).collect();
+        columns.sort();
+        assert_eq!(columns, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn super_shuffle_keeps_holds() {
+        let rows = ["2100", "0110", "0011", "3000", "1111"];
+        for seed in 0..50 {
+            let shuffled = transformed(Transform::SuperShuffle(seed), &rows);
+            let mut held = None;
+            for (row, _) in &shuffled[0] {
+                for note in row {
+                    match note.note_type {
+                        NoteType::Hold => held = Some(note.column),
+                        NoteType::HoldEnd => assert_eq!(held.take(), Some(note.column)),
+                        _ => assert_ne!(held, Some(note.column)),
+                    }
+                }
+            }
+            let notes: Vec<_> = shuffled[0].iter().map(|(row, _)| row.len()).collect();
+            assert_eq!(notes, vec![2, 2, 2, 1, 4]);
+        }
+    }
+
+    #[test]
+    fn removals() {
+        assert_eq!(
+            transformed(Transform::NoMines, &["1M00", "0M00", "0001"]),
+            chart(&["1000", "0000", "0001"])
+        );
+        assert_eq!(
+            transformed(Transform::NoHolds, &["2040", "0000", "3030"]),
+            chart(&["1010", "0000", "0000"])
+        );
+        assert_eq!(
+            transformed(Transform::NoJumps, &["0220", "M011", "0330", "1000"]),
+            chart(&["0200", "M010", "0300", "1000"])
+        );
+        assert_eq!(
+            transformed(
+                Transform::Little,
+                &["1000", "2000", "0100", "0010", "3001", "0000", "0000", "0000"]
+            ),
+            chart(&["1000", "0000", "0100", "0000", "0001", "0000", "0000", "0000"])
+        );
+    }
+
+    #[test]
+    fn parse_transforms() {
+        for transform in &[
+            Transform::Mirror,
+            Transform::Shuffle(12),
+            Transform::SuperShuffle(3),
+            Transform::Little,
+        ] {
+            assert_eq!(transform.to_string().parse(), Ok(*transform));
+        }
+        assert_eq!(" NoMines ".parse(), Ok(Transform::NoMines));
+        assert!(matches!("shuffle".parse(), Ok(Transform::Shuffle(_))));
+        assert!("shuffle=x".parse::<Transform>().is_err());
+        assert!("sideways".parse::<Transform>().is_err());
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{BeatPair, Chart, ChartInfo, Fraction, NoteData, NoteType, StructureData};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+//Positions are in measures, like BeatPair. Problems with timing name the chart whose own timing
+//they're in, or None for the song's.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Diagnostic {
+    //A hold or roll head with no HoldEnd after it in its column
+    UnendedHold {
+        chart: usize,
+        column: usize,
+        position: Fraction,
+    },
+    //A HoldEnd in a column with no hold or roll open
+    UnopenedHoldEnd {
+        chart: usize,
+        column: usize,
+        position: Fraction,
+    },
+    //Any other note in a column while a hold or roll is open there
+    NoteInsideHold {
+        chart: usize,
+        column: usize,
+        position: Fraction,
+    },
+    ColumnOutOfRange {
+        chart: usize,
+        column: usize,
+        columns: usize,
+        position: Fraction,
+    },
+    //Nothing in the chart is ever hit
+    EmptyChart {
+        chart: usize,
+    },
+    UnsortedBpm {
+        chart: Option<usize>,
+        position: Fraction,
+    },
+    DuplicateBpm {
+        chart: Option<usize>,
+        position: Fraction,
+    },
+    //Zero bpms are ignored by the timing
+    ZeroBpm {
+        chart: Option<usize>,
+        position: Fraction,
+    },
+    //A negative bpm warps until a positive one catches up, without one the rest of the chart is lost
+    UnendedNegativeBpm {
+        chart: Option<usize>,
+        position: Fraction,
+    },
+    //Without a positive bpm there's no timing at all
+    NoBpm {
+        chart: Option<usize>,
+    },
+    //Beat 0 lands further from the start of the music than the music is long, both in seconds
+    OffsetOutsideSong {
+        offset: f64,
+        song_length: f64,
+    },
+    NoMusic,
+    MissingMusic(PathBuf),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnendedHold {
+                chart,
+                column,
+                position,
+            } => write!(
+                f,
+                "hold in chart {}, column {} at measure {} never ends",
+                chart, column, position
+            ),
+            Self::UnopenedHoldEnd {
+                chart,
+                column,
+                position,
+            } => write!(
+                f,
+                "hold end in chart {}, column {} at measure {} has no hold to end",
+                chart, column, position
+            ),
+            Self::NoteInsideHold {
+                chart,
+                column,
+                position,
+            } => write!(
+                f,
+                "note in chart {}, column {} at measure {} is inside a hold",
+                chart, column, position
+            ),
+            Self::ColumnOutOfRange {
+                chart,
+                column,
+                columns,
+                position,
+            } => write!(
+                f,
+                "note in chart {} at measure {} is in column {} of {}",
+                chart, position, column, columns
+            ),
+            Self::EmptyChart { chart } => write!(f, "chart {} has no notes", chart),
+            Self::UnsortedBpm { chart, position } => {
+                write!(
+                    f,
+                    "bpm at measure {}{} is out of order",
+                    position,
+                    timing(*chart)
+                )
+            }
+            Self::DuplicateBpm { chart, position } => {
+                write!(
+                    f,
+                    "bpm at measure {}{} is repeated",
+                    position,
+                    timing(*chart)
+                )
+            }
+            Self::ZeroBpm { chart, position } => {
+                write!(f, "bpm at measure {}{} is zero", position, timing(*chart))
+            }
+            Self::UnendedNegativeBpm { chart, position } => write!(
+                f,
+                "negative bpm at measure {}{} is never followed by a positive one",
+                position,
+                timing(*chart)
+            ),
+            Self::NoBpm { chart } => write!(f, "no positive bpm{}", timing(*chart)),
+            Self::OffsetOutsideSong {
+                offset,
+                song_length,
+            } => write!(
+                f,
+                "offset of {}s is outside the {}s of music",
+                offset, song_length
+            ),
+            Self::NoMusic => write!(f, "no music file is given"),
+            Self::MissingMusic(path) => write!(f, "music file {} is missing", path.display()),
+        }
+    }
+}
+
+fn timing(chart: Option<usize>) -> String {
+    chart.map_or_else(String::new, |chart| format!(" in chart {}'s timing", chart))
+}
+
+fn position<T>(pair: &BeatPair<T>) -> Fraction {
+    Fraction::from(pair.beat) + pair.sub_beat
+}
+
+fn check_bpms(structure: &StructureData, chart: Option<usize>, diagnostics: &mut Vec<Diagnostic>) {
+    let mut seen: Vec<Fraction> = vec![];
+    let mut negative = None;
+    for bpm in &structure.bpms {
+        let position = position(bpm);
+        if seen.contains(&position) {
+            diagnostics.push(Diagnostic::DuplicateBpm { chart, position });
+        } else if seen.iter().any(|&previous| previous > position) {
+            diagnostics.push(Diagnostic::UnsortedBpm { chart, position });
+        }
+        seen.push(position);
+        if bpm.value == 0.0 {
+            diagnostics.push(Diagnostic::ZeroBpm { chart, position });
+        }
+    }
+    //The timing applies bpms in order of position, whatever order they're listed in
+    let mut bpms: Vec<_> = structure
+        .bpms
+        .iter()
+        .map(|bpm| (position(bpm), bpm.value))
+        .collect();
+    bpms.sort_by_key(|bpm| bpm.0);
+    for (position, bpm) in bpms {
+        if bpm < 0.0 {
+            negative = negative.or(Some(position));
+        } else if bpm > 0.0 {
+            negative = None;
+        }
+    }
+    if let Some(position) = negative {
+        diagnostics.push(Diagnostic::UnendedNegativeBpm { chart, position });
+    }
+    if !structure.bpms.iter().any(|bpm| bpm.value > 0.0) {
+        diagnostics.push(Diagnostic::NoBpm { chart });
+    }
+}
+
+fn check_notes(info: &ChartInfo, chart: &Chart, index: usize, diagnostics: &mut Vec<Diagnostic>) {
+    let columns = info.columns(chart);
+    let mut open_holds: Vec<Option<Fraction>> = vec![None; columns];
+    let mut hit = false;
+    for (measure_index, measure) in chart.iter().enumerate() {
+        for (row, row_position) in measure {
+            let position = Fraction::from(measure_index as i32) + row_position;
+            for note in row {
+                hit |= note.note_type.is_hit();
+                if note.column >= columns {
+                    diagnostics.push(Diagnostic::ColumnOutOfRange {
+                        chart: index,
+                        column: note.column,
+                        columns,
+                        position,
+                    });
+                    continue;
+                }
+                let open_hold = &mut open_holds[note.column];
+                match (note.note_type, open_hold.is_some()) {
+                    (NoteType::HoldEnd, true) => *open_hold = None,
+                    (NoteType::HoldEnd, false) => diagnostics.push(Diagnostic::UnopenedHoldEnd {
+                        chart: index,
+                        column: note.column,
+                        position,
+                    }),
+                    (_, true) => diagnostics.push(Diagnostic::NoteInsideHold {
+                        chart: index,
+                        column: note.column,
+                        position,
+                    }),
+                    (NoteType::Hold, false) | (NoteType::Roll, false) => {
+                        *open_hold = Some(position)
+                    }
+                    (_, false) => {}
+                }
+            }
+        }
+    }
+    for (column, open_hold) in open_holds.into_iter().enumerate() {
+        if let Some(position) = open_hold {
+            diagnostics.push(Diagnostic::UnendedHold {
+                chart: index,
+                column,
+                position,
+            });
+        }
+    }
+    if !hit {
+        diagnostics.push(Diagnostic::EmptyChart { chart: index });
+    }
+}
+
+#[must_use]
+pub(crate) fn validate(
+    data: &NoteData,
+    folder: &Path,
+    song_length: Option<f64>,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    check_bpms(&data.structure, None, &mut diagnostics);
+    for (index, (info, chart)) in data.charts.iter().enumerate() {
+        if let Some(structure) = &info.structure {
+            check_bpms(structure, Some(index), &mut diagnostics);
+        }
+        check_notes(info, chart, index, &mut diagnostics);
+    }
+    if let (Some(offset), Some(song_length)) = (data.structure.offset, song_length) {
+        if offset.abs() > song_length {
+            diagnostics.push(Diagnostic::OffsetOutsideSong {
+                offset,
+                song_length,
+            });
+        }
+    }
+    match &data.meta.music_path {
+        Some(music_path) if !folder.join(music_path).is_file() => {
+            diagnostics.push(Diagnostic::MissingMusic(folder.join(music_path)))
+        }
+        Some(_) => {}
+        None => diagnostics.push(Diagnostic::NoMusic),
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser_generic::row, Measure};
+
+    fn song(charts: Vec<Chart>, bpms: &[(f64, f64)]) -> NoteData {
+        let mut data = NoteData::new();
+        data.structure.offset = Some(0.0);
+        data.structure.bpms = bpms
+            .iter()
+            .map(|&(beat, bpm)| BeatPair::from_pair(beat, bpm).unwrap())
+            .collect();
+        data.meta.music_path = Some("song.ogg".to_owned());
+        data.charts = charts
+            .into_iter()
+            .map(|chart| (ChartInfo::new(), chart))
+            .collect();
+        data
+    }
+
+    #[test]
+    fn note_problems() {
+        let measure: Measure = vec![
+            row(
+                &[(NoteType::Hold, 0), (NoteType::Roll, 1)],
+                Fraction::new(0, 1),
+            ),
+            row(
+                &[(NoteType::Tap, 0), (NoteType::AutoKeysound, 1)],
+                Fraction::new(1, 4),
+            ),
+            row(
+                &[(NoteType::HoldEnd, 0), (NoteType::HoldEnd, 2)],
+                Fraction::new(1, 2),
+            ),
+            row(&[(NoteType::Tap, 5)], Fraction::new(3, 4)),
+        ];
+        let data = song(vec![vec![measure], vec![]], &[(0.0, 120.0)]);
+        let diagnostics = data.validate(Path::new("/nonexistent"), None);
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::NoteInsideHold {
+                    chart: 0,
+                    column: 0,
+                    position: Fraction::new(1, 4),
+                },
+                //Keysounds that play on their own count as well
+                Diagnostic::NoteInsideHold {
+                    chart: 0,
+                    column: 1,
+                    position: Fraction::new(1, 4),
+                },
+                Diagnostic::UnopenedHoldEnd {
+                    chart: 0,
+                    column: 2,
+                    position: Fraction::new(1, 2),
+                },
+                Diagnostic::ColumnOutOfRange {
+                    chart: 0,
+                    column: 5,
+                    columns: 4,
+                    position: Fraction::new(3, 4),
+                },
+                Diagnostic::UnendedHold {
+                    chart: 0,
+                    column: 1,
+                    position: Fraction::new(0, 1),
+                },
+                Diagnostic::EmptyChart { chart: 1 },
+                Diagnostic::MissingMusic(PathBuf::from("/nonexistent/song.ogg")),
+            ]
+        );
+    }
+
+    #[test]
+    fn timing_problems() {
+        let chart = vec![vec![row(&[(NoteType::Tap, 0)], Fraction::new(0, 1))]];
+        let mut data = song(
+            vec![chart],
+            &[
+                (0.0, 120.0),
+                (2.0, 0.0),
+                (1.0, 150.0),
+                (1.0, 160.0),
+                (3.0, -60.0),
+            ],
+        );
+        data.structure.offset = Some(-200.0);
+        data.meta.music_path = None;
+        data.charts[0].0.structure = Some(StructureData {
+            bpms: vec![BeatPair::from_pair(0.0, -120.0).unwrap()],
+            ..StructureData::default()
+        });
+        let diagnostics = data.validate(Path::new(""), Some(90.0));
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::ZeroBpm {
+                    chart: None,
+                    position: Fraction::new(2, 1),
+                },
+                Diagnostic::UnsortedBpm {
+                    chart: None,
+                    position: Fraction::new(1, 1),
+                },
+                Diagnostic::DuplicateBpm {
+                    chart: None,
+                    position: Fraction::new(1, 1),
+                },
+                Diagnostic::UnendedNegativeBpm {
+                    chart: None,
+                    position: Fraction::new(3, 1),
+                },
+                Diagnostic::UnendedNegativeBpm {
+                    chart: Some(0),
+                    position: Fraction::new(0, 1),
+                },
+                Diagnostic::NoBpm { chart: Some(0) },
+                Diagnostic::OffsetOutsideSong {
+                    offset: -200.0,
+                    song_length: 90.0,
+                },
+                Diagnostic::NoMusic,
+            ]
+        );
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "bpm at measure 1 is out of order"
+        );
+        assert_eq!(
+            diagnostics[4].to_string(),
+            "negative bpm at measure 0 in chart 0's timing is never followed by a positive one"
+        );
+    }
+}
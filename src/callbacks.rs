@@ -76,15 +76,23 @@ pub fn song_path(resource: Option<Resource>, globals: &Globals) -> Option<Resour
 
 pub fn song_from_path(resource: Option<Resource>, globals: &Globals) -> Option<Resource> {
     if let Some(Resource::_Path(path)) = resource {
-        Some(Resource::_Notes(
-            notedata::timingdata::TimingData::from_notedata(
-                &load_song(&path).ok().map(|(_, data)| data)?,
-                super::sprite_finder,
-                globals.song_options.rate,
-            )
-            .get(0)?
-            .clone(),
-        ))
+        let mut notes = notedata::timingdata::TimingData::from_notedata(
+            load_song(&path)
+                .ok()
+                .map(|(_, data)| data)?
+                .transform(&globals.song_options.mods),
+            super::sprite_finder,
+            globals.song_options.rate,
+        )
+        .get(0)?
+        .clone();
+        //Keysounds are named relative to the simfile's folder
+        notes.keysounds = notes
+            .keysounds
+            .iter()
+            .map(|keysound| path.with_file_name(keysound))
+            .collect();
+        Some(Resource::_Notes(notes))
     } else {
         None
     }
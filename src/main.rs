@@ -50,20 +50,19 @@ use crate::{
     gamestate::GameState,
     screen::{CacheEntry, Globals, Resources},
 };
-use bincode::deserialize;
 use ggez::{filesystem::mount, ContextBuilder};
-use log::{debug, info};
+use log::{debug, info, warn};
 use notedata::{
     timingdata::{CalcInfo, Rectangle, TimingData},
-    Fraction, NoteData, NoteType,
+    Fraction, NoteData, NoteType, ParseError, ParseOptions, SongHeader, Transform,
 };
 use std::{
     cmp::Ordering,
+    collections::HashMap,
     ffi::OsStr,
     fs::{File, OpenOptions},
-    io::Read,
-    path::PathBuf,
-    time::Instant,
+    path::{Path, PathBuf},
+    time::{Instant, UNIX_EPOCH},
 };
 use structopt::StructOpt;
 use utils::notefield::player_config::{NoteLayout, NoteSkin, PlayerOptions};
@@ -119,31 +118,42 @@ fn sprite_finder(
     }
 }
 
-pub fn load_song(sim: &PathBuf) -> Result<(f64, NoteData), LoadError> {
-    if let Some(extension) = sim.extension() {
-        let mut sim = match File::open(sim.clone()) {
-            Ok(file) => file,
-            Err(_) => return Err(LoadError::FailedParse),
-        };
-        match extension.to_str() {
-            Some("sm") => {
-                notedata::NoteData::from_sm_reader(sim).map_err(|_| LoadError::FailedParse)
-            }
-            Some("dwi") => {
-                notedata::NoteData::from_dwi_reader(sim).map_err(|_| LoadError::FailedParse)
-            }
-            Some("rm") => {
-                let mut n = vec![];
-                sim.read_to_end(&mut n)
-                    .expect("Failed to read to end of .rm file");
-                deserialize(&n).map_err(|_| LoadError::FailedParse)
-            }
-            _ => Err(LoadError::WrongExtension),
-        }
-    } else {
-        Err(LoadError::WrongExtension)
+//Slightly broken simfiles still load, with whatever was skipped reported in the log
+fn report_warnings<T>(path: &Path, (data, warnings): (T, Vec<ParseError>)) -> T {
+    for warning in warnings {
+        warn!("{}: {}", path.display(), warning);
+    }
+    data
+}
+
+fn load_notedata(path: &PathBuf) -> Result<NoteData, LoadError> {
+    let extension = path.extension().ok_or(LoadError::WrongExtension)?;
+    let sim = File::open(path).map_err(|error| LoadError::FailedParse(error.into()))?;
+    let lenient = ParseOptions {
+        lenient: true,
+        ..ParseOptions::default()
+    };
+    let report = |loaded| report_warnings(path, loaded);
+    match extension.to_str() {
+        Some("sm") => notedata::NoteData::from_sm_reader_with_options(sim, lenient).map(report),
+        Some("ssc") => notedata::NoteData::from_ssc_reader_with_options(sim, lenient).map(report),
+        Some("dwi") => notedata::NoteData::from_dwi_reader_with_options(sim, lenient).map(report),
+        Some("osu") => notedata::NoteData::from_osu_reader(sim),
+        Some("bms") | Some("bme") | Some("bml") => notedata::NoteData::from_bms_reader(sim),
+        Some("pms") => notedata::NoteData::from_pms_reader(sim),
+        Some("qua") => notedata::NoteData::from_qua_reader(sim),
+        Some("mc") => notedata::NoteData::from_mc_reader(sim),
+        Some("rm") => notedata::NoteData::from_rm_reader(sim),
+        _ => return Err(LoadError::WrongExtension),
     }
-    .map(|x| {
+    .map_err(|error| {
+        warn!("Failed to load {}: {}", path.display(), error);
+        LoadError::FailedParse(error)
+    })
+}
+
+pub fn load_song(path: &PathBuf) -> Result<(f64, NoteData), LoadError> {
+    load_notedata(path).map(|x| {
         if let Some(timing) = TimingData::<CalcInfo>::from_notedata(&x, sprite_finder, 1.0).get(0) {
             (difficulty_calc::rate_chart(&timing, 1.86), x)
         } else {
@@ -152,6 +162,60 @@ pub fn load_song(sim: &PathBuf) -> Result<(f64, NoteData), LoadError> {
     })
 }
 
+//The song list only needs headers, so the formats that can skip their notes do
+fn index_song(path: &PathBuf) -> Result<SongHeader, LoadError> {
+    let extension = path.extension().ok_or(LoadError::WrongExtension)?;
+    let lenient = ParseOptions {
+        lenient: true,
+        ..ParseOptions::default()
+    };
+    let header = match extension.to_str() {
+        Some("sm") => SongHeader::from_sm_reader,
+        Some("ssc") => SongHeader::from_ssc_reader,
+        _ => return load_notedata(path).map(|data| SongHeader::from(&data)),
+    };
+    let sim = File::open(path).map_err(|error| LoadError::FailedParse(error.into()))?;
+    header(sim, lenient)
+        .map(|loaded| report_warnings(path, loaded))
+        .map_err(|error| {
+            warn!("Failed to load {}: {}", path.display(), error);
+            LoadError::FailedParse(error)
+        })
+}
+
+//Rating a song needs all of its notes, so ratings are kept between runs by file and modified time
+const RATINGS_PATH: &str = "ratings.yml";
+
+type Ratings = HashMap<PathBuf, (u64, f64)>;
+
+fn modified(path: &Path) -> Option<u64> {
+    let time = path.metadata().ok()?.modified().ok()?;
+    Some(time.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+fn rate_song(path: &PathBuf, ratings: &Ratings) -> Result<(f64, SongHeader), LoadError> {
+    match ratings.get(path) {
+        Some(&(time, rating)) if Some(time) == modified(path) => Ok((rating, index_song(path)?)),
+        _ => load_song(path).map(|(rating, data)| (rating, SongHeader::from(&data))),
+    }
+}
+
+fn load_ratings() -> Ratings {
+    File::open(RATINGS_PATH)
+        .ok()
+        .and_then(|file| serde_yaml::from_reader(file).ok())
+        .unwrap_or_default()
+}
+
+fn save_ratings(ratings: &Ratings) {
+    let saved = File::create(RATINGS_PATH)
+        .map_err(|error| error.to_string())
+        .and_then(|file| serde_yaml::to_writer(file, ratings).map_err(|error| error.to_string()));
+    if let Err(error) = saved {
+        warn!("Failed to save song ratings: {}", error);
+    }
+}
+
 #[derive(Clone, Debug, Default, StructOpt)]
 #[structopt(name = "RustMania", author, about)]
 pub struct SongOptions {
@@ -170,6 +234,10 @@ pub struct SongOptions {
     /// The path to your lua theme file
     #[structopt(parse(from_os_str = parse_theme_path), short, long)]
     theme: Option<PathBuf>,
+
+    /// Modifiers for the chart, separated by commas, like mirror,noholds or shuffle=12
+    #[structopt(short, long, use_delimiter = true)]
+    mods: Vec<Transform>,
 }
 
 #[allow(clippy::too_many_lines)]
@@ -190,7 +258,10 @@ fn main() {
 
     let notedata_list = {
         let start_time = Instant::now();
-        let notedata_list = load_songs_folder(song_options.simfile.clone(), load_song);
+        let ratings = load_ratings();
+        let notedata_list = load_songs_folder(song_options.simfile.clone(), |path: &PathBuf| {
+            rate_song(path, &ratings)
+        });
         let duration = Instant::now() - start_time;
         info!("Found {} total songs", notedata_list.len());
         let mut notedata_list = notedata_list
@@ -203,16 +274,22 @@ fn main() {
             duration.as_secs(),
             duration.subsec_millis()
         );
+        save_ratings(
+            &notedata_list
+                .iter()
+                .filter_map(|(path, (rating, _))| Some((path.clone(), (modified(path)?, *rating))))
+                .collect(),
+        );
         notedata_list.sort_by(|a, b| (a.1).0.partial_cmp(&(b.1).0).unwrap_or(Ordering::Less));
         notedata_list
             .iter()
             .for_each(|x| info!("{:?}, {}", (x.1).1.meta.title, (x.1).0));
         notedata_list
             .into_iter()
-            .map(|(path, (difficulty, data))| CacheEntry {
+            .map(|(path, (difficulty, header))| CacheEntry {
                 path,
                 difficulty,
-                data: data.meta,
+                data: header.meta,
             })
             .collect::<Vec<_>>()
     };
@@ -236,7 +313,7 @@ fn main() {
     let p2_layout = NoteLayout::new(&default_note_skin, 600, p2_options);
 
     let resources = Resources::new(
-        vec![TimingData::new()],
+        vec![TimingData::new(4)],
         vec![PathBuf::new(); 2],
         vec![p1_layout, p2_layout],
         vec![song_options.rate, 0.0, 12.0, 36.0, 0.0],
@@ -6,7 +6,7 @@ use ggez::{
 };
 use notedata::{
     timingdata::{GameplayInfo, Judgement, TimingColumn, TimingData},
-    ChartMetadata, NOTEFIELD_SIZE,
+    ChartMetadata,
 };
 use serde_derive::{Deserialize, Serialize};
 use std::sync::mpsc::channel;
@@ -16,7 +16,7 @@ use std::{
     thread,
     time::{Duration, Instant},
 };
-use utils::music::{play_file, Music};
+use utils::music::{play_file, play_keysounds, Music};
 use utils::notefield::{player_config::NoteLayout, Notefield};
 
 pub trait Element: Send {
@@ -476,7 +476,7 @@ impl Element for Notefield {
             None => return Ok(Message::None),
         };
         let mut completed = true;
-        for column_index in 0..NOTEFIELD_SIZE {
+        for column_index in 0..self.column_info.len() {
             if let Some(value) = self.column_info[column_index].active_hold {
                 let delta = value - time;
                 if delta > 0 {
@@ -490,7 +490,9 @@ impl Element for Notefield {
             completed &= self.column_info[column_index].next_to_hit
                 == self.column_info[column_index].notes.notes.len();
             completed &= self.column_info[column_index].active_hold.is_none();
+            self.column_info[column_index].update_autoplay(time);
         }
+        self.play_keysounds();
         self.redraw_batch();
         let target_parameter =
             graphics::DrawParam::new().dest([0.0, -1.0 * (self.layout.delta_to_offset(time))]);
@@ -508,9 +510,16 @@ impl Element for Notefield {
         })
     }
     fn start(&mut self, _time: Option<Instant>) -> Result<Message, ggez::GameError> {
+        if !self.keysounds.is_empty() {
+            let paths = self.keysounds.clone();
+            let (send, recv) = channel();
+            self.keysound_player = Some(send);
+            thread::spawn(move || play_keysounds(paths, recv));
+        }
         Ok(Message::None)
     }
     fn finish(&mut self) -> Option<Resource> {
+        self.keysound_player = None;
         Some(Resource::Replay(
             self.column_info
                 .iter()
@@ -519,12 +528,9 @@ impl Element for Notefield {
         ))
     }
     fn handle_event(&mut self, keycode: ggez::event::KeyCode, time: Option<i64>, key_down: bool) {
-        let index = match keycode {
-            ggez::event::KeyCode::Z => 0,
-            ggez::event::KeyCode::X => 1,
-            ggez::event::KeyCode::Comma => 2,
-            ggez::event::KeyCode::Period => 3,
-            _ => return,
+        let index = match self.column_for_key(keycode) {
+            Some(index) => index,
+            None => return,
         };
         let time = match time {
             Some(time) => time,
@@ -542,6 +548,7 @@ impl Element for Notefield {
             if let Some(value) = self.column_info[index].handle_hit(time) {
                 self.handle_judgement(value)
             };
+            self.play_keysounds();
         } else if self.column_info[index].active_hold.is_some() {
             self.column_info[index]
                 .judgement_list
@@ -1,12 +1,15 @@
 //use crate::screen::{Element, Message, Resource};
-use cpal::traits::{DeviceTrait, EventLoopTrait, HostTrait};
+use cpal::{
+    traits::{DeviceTrait, EventLoopTrait, HostTrait},
+    EventLoop, Format, StreamId,
+};
 use lewton::inside_ogg::OggStreamReader;
 use minimp3::Decoder;
 use std::{
     self,
     fs::File,
     path::{Path, PathBuf},
-    sync::mpsc::{Receiver, Sender},
+    sync::mpsc::{Receiver, Sender, TryRecvError},
     time::{Duration, Instant},
 };
 
@@ -28,12 +31,7 @@ impl Music {
     }
 }
 
-//Known issue: playback only operates correctly on two channel audio
-//single channel audio needs to be accounted for
-pub fn play_file<T>(start_time: Instant, rate: f64, path: T, recv: Receiver<bool>)
-where
-    T: AsRef<Path>,
-{
+fn output_stream() -> (EventLoop, StreamId, Format) {
     let host = cpal::default_host();
     let event_loop = host.event_loop();
     let device = host
@@ -47,20 +45,19 @@ where
         .expect("no supported format?!")
         .with_max_sample_rate();
     let stream_id = event_loop.build_output_stream(&device, &format).unwrap();
+    (event_loop, stream_id, format)
+}
+
+//Known issue: playback only operates correctly on two channel output
+pub fn play_file<T>(start_time: Instant, rate: f64, path: T, recv: Receiver<bool>)
+where
+    T: AsRef<Path>,
+{
+    let (event_loop, stream_id, format) = output_stream();
 
     let sample_rate = f64::from(format.sample_rate.0);
 
-    let mut ext = PathBuf::new();
-    ext.push(&path);
-    let (stream_sample_rate, samples) = match ext.extension() {
-        Some(ext) => match ext.to_str() {
-            Some("ogg") => decode_ogg(path),
-            Some("mp3") => decode_mp3(path),
-            Some("wav") => decode_wav(path),
-            _ => panic!("unrecognized file type"),
-        },
-        _ => panic!("no file type found"),
-    };
+    let (stream_sample_rate, samples) = decode_file(path).expect("unrecognized file type");
 
     let mut sample_index = 0.0;
 
@@ -110,6 +107,94 @@ where
     });
 }
 
+//Keysounds are decoded up front so they start the moment they're sent. Each index received plays
+//that sample over whatever is already playing, until the sender is dropped.
+pub fn play_keysounds(paths: Vec<PathBuf>, recv: Receiver<usize>) {
+    let (event_loop, stream_id, format) = output_stream();
+
+    let sample_rate = f64::from(format.sample_rate.0);
+
+    //Samples that are missing or in an unknown format stay silent
+    let sounds: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            Some(path)
+                .filter(|path| path.exists())
+                .and_then(decode_file)
+                .map_or((0.0, vec![]), |(stream_sample_rate, samples)| {
+                    (f64::from(stream_sample_rate) / sample_rate, samples)
+                })
+        })
+        .collect();
+
+    //Each playing sample as its index and how many frames of it have played
+    let mut playing: Vec<(usize, f64)> = vec![];
+
+    event_loop
+        .play_stream(stream_id.clone())
+        .expect("failed to play_stream");
+
+    event_loop.run(move |_, stream_result| {
+        let data = match stream_result {
+            Ok(data) => data,
+            Err(err) => panic!("an error occurred on stream {:?}: {}", stream_id, err),
+        };
+
+        loop {
+            match recv.try_recv() {
+                Ok(index) if index < sounds.len() => playing.push((index, 0.0)),
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => panic!("Intentional panic"),
+            }
+        }
+
+        if let cpal::StreamData::Output {
+            buffer: cpal::UnknownTypeOutputBuffer::F32(mut buffer),
+        } = data
+        {
+            for sample in buffer.chunks_mut(format.channels as usize) {
+                for (i, out) in sample.iter_mut().enumerate() {
+                    let mixed: f32 = playing
+                        .iter()
+                        .map(|&(index, frame)| {
+                            f32::from(*sounds[index].1.get(frame as usize * 2 + i).unwrap_or(&0))
+                        })
+                        .sum();
+                    *out = (mixed / f32::from(i16::max_value())).max(-1.0).min(1.0);
+                }
+                for (index, frame) in &mut playing {
+                    *frame += sounds[*index].0;
+                }
+            }
+            playing.retain(|&(index, frame)| (frame as usize) * 2 < sounds[index].1.len());
+        }
+    });
+}
+
+fn decode_file<T>(path: T) -> Option<(i32, Vec<i16>)>
+where
+    T: AsRef<Path>,
+{
+    match path.as_ref().extension()?.to_str()? {
+        "ogg" => Some(decode_ogg(path)),
+        "mp3" => Some(decode_mp3(path)),
+        "wav" => Some(decode_wav(path)),
+        _ => None,
+    }
+}
+
+//Playback expects interleaved stereo, so mono samples are copied to both channels
+fn to_stereo(channels: usize, samples: Vec<i16>) -> Vec<i16> {
+    match channels {
+        1 => samples
+            .into_iter()
+            .flat_map(|sample| std::iter::repeat(sample).take(2))
+            .collect(),
+        _ => samples,
+    }
+}
+
 fn decode_ogg<T>(path: T) -> (i32, Vec<i16>)
 where
     T: AsRef<Path>,
@@ -120,7 +205,7 @@ where
     let mut coolvec = Vec::<i16>::new();
     while let Some(pck_samples) = srr.read_dec_packet_itl().unwrap() {
         match srr.ident_hdr.audio_channels {
-            2 => coolvec.append(&mut pck_samples.clone()),
+            n @ 1..=2 => coolvec.append(&mut to_stereo(n.into(), pck_samples)),
             n => panic!("unsupported number of channels: {}", n),
         };
     }
@@ -137,14 +222,14 @@ where
     let mut frames = Vec::new();
 
     let stream_sample_rate = if let Ok(frame) = decoder.next_frame() {
-        frames.append(&mut frame.data.clone());
+        frames.append(&mut to_stereo(frame.channels, frame.data));
         frame.sample_rate
     } else {
         0
     };
 
     while let Ok(frame) = decoder.next_frame() {
-        frames.append(&mut frame.data.clone());
+        frames.append(&mut to_stereo(frame.channels, frame.data));
     }
     (stream_sample_rate, frames)
 }
@@ -154,11 +239,15 @@ where
     T: AsRef<Path>,
 {
     let mut reader = hound::WavReader::open(path).unwrap();
+    let spec = reader.spec();
     (
-        reader.spec().sample_rate as i32,
-        reader
-            .samples::<i16>()
-            .filter_map(Result::ok)
-            .collect::<Vec<_>>(),
+        spec.sample_rate as i32,
+        to_stereo(
+            spec.channels.into(),
+            reader
+                .samples::<i16>()
+                .filter_map(Result::ok)
+                .collect::<Vec<_>>(),
+        ),
     )
 }
@@ -1,19 +1,22 @@
-use ggez::graphics::spritebatch::SpriteBatch;
+use ggez::{event::KeyCode, graphics::spritebatch::SpriteBatch};
 use notedata::{
     timingdata::{GameplayInfo, Judgement, TimingColumn, TimingData},
-    NoteType, NOTEFIELD_SIZE,
+    NoteType,
 };
 use player_config::NoteLayout;
+use std::{path::PathBuf, sync::mpsc::Sender};
 
 pub mod player_config;
 
-#[derive(PartialEq, Debug)]
+#[derive(Debug)]
 pub struct Notefield {
     pub layout: NoteLayout,
-    pub column_info: [ColumnInfo; NOTEFIELD_SIZE],
+    pub column_info: Vec<ColumnInfo>,
     pub batches: Vec<SpriteBatch>,
     pub draw_distance: i64,
     pub last_judgement: Option<Judgement>,
+    pub keysounds: Vec<PathBuf>,
+    pub keysound_player: Option<Sender<usize>>,
 }
 
 #[derive(PartialEq, Debug, Default)]
@@ -21,8 +24,11 @@ pub struct ColumnInfo {
     pub on_screen: (usize, usize),
     pub next_to_hit: usize,
     pub active_hold: Option<i64>,
+    pub next_autoplay: usize,
     pub notes: TimingColumn<GameplayInfo>,
     pub judgement_list: TimingColumn<Judgement>,
+    //Keysounds waiting to be sent to the player, as indices into the chart's keysound list
+    pub keysounds: Vec<usize>,
 }
 
 impl ColumnInfo {
@@ -74,7 +80,10 @@ impl ColumnInfo {
                 None => break,
             };
         }
-        while self.notes.notes.get(self.next_to_hit).map(|x| x.2) == Some(NoteType::HoldEnd) {
+        while matches!(
+            self.notes.notes.get(self.next_to_hit).map(|x| x.2),
+            Some(NoteType::HoldEnd) | Some(NoteType::Fake) | Some(NoteType::AutoKeysound)
+        ) {
             self.next_to_hit += 1;
         }
         missed_judge
@@ -83,22 +92,45 @@ impl ColumnInfo {
         self.update_misses(time);
         let offset = self.notes.notes.get(self.next_to_hit).map(|x| x.0 - time)?;
         if offset < 180 {
+            if let Some(keysound) = self.notes.notes[self.next_to_hit].3 {
+                self.keysounds.push(keysound);
+            }
             match self.notes.notes[self.next_to_hit].2 {
                 NoteType::Tap => self.judgement_list.add(Judgement::Hit(offset)),
                 NoteType::Hold => {
                     self.judgement_list.add(Judgement::Hit(offset));
-                    self.active_hold = self.notes.notes.get(self.next_to_hit + 1).map(|x| x.0);
+                    //Background keysounds can sit between a hold and its end
+                    self.active_hold = self.notes.notes[self.next_to_hit + 1..]
+                        .iter()
+                        .find(|x| x.2 == NoteType::HoldEnd)
+                        .map(|x| x.0);
                 }
                 NoteType::Mine => self.judgement_list.add(Judgement::Mine(true)),
                 _ => {}
             }
             self.next_to_hit += 1;
-            while self.notes.notes.get(self.next_to_hit).map(|x| x.2) == Some(NoteType::HoldEnd) {
+            while matches!(
+                self.notes.notes.get(self.next_to_hit).map(|x| x.2),
+                Some(NoteType::HoldEnd) | Some(NoteType::Fake) | Some(NoteType::AutoKeysound)
+            ) {
                 self.next_to_hit += 1;
             }
         };
         self.judgement_list.notes.last().copied()
     }
+    pub fn update_autoplay(&mut self, time: i64) {
+        while let Some(&GameplayInfo(note_time, _, note_type, keysound)) =
+            self.notes.notes.get(self.next_autoplay)
+        {
+            if note_time > time {
+                break;
+            }
+            if let (NoteType::AutoKeysound, Some(keysound)) = (note_type, keysound) {
+                self.keysounds.push(keysound);
+            }
+            self.next_autoplay += 1;
+        }
+    }
 }
 
 impl From<TimingColumn<GameplayInfo>> for ColumnInfo {
@@ -107,14 +139,21 @@ impl From<TimingColumn<GameplayInfo>> for ColumnInfo {
             on_screen: (0, 0),
             next_to_hit: 0,
             active_hold: None,
+            next_autoplay: 0,
             notes,
             judgement_list: TimingColumn::new(),
+            keysounds: vec![],
         }
     }
 }
 
 impl Notefield {
-    pub fn new(layout: NoteLayout, notes: &TimingData<GameplayInfo>, draw_distance: i64) -> Self {
+    pub fn new(
+        mut layout: NoteLayout,
+        notes: &TimingData<GameplayInfo>,
+        draw_distance: i64,
+    ) -> Self {
+        layout.set_columns(notes.notes.len());
         let batches = vec![
             SpriteBatch::new(layout.sprites.hold_end.clone()),
             SpriteBatch::new(layout.sprites.hold_body.clone()),
@@ -123,20 +162,22 @@ impl Notefield {
         ];
         Self {
             layout,
-            column_info: array_init::array_init(|i| ColumnInfo::from(notes.notes[i].clone())),
+            column_info: notes.notes.iter().cloned().map(ColumnInfo::from).collect(),
             //Using a Vec of SpriteBatch should be temporary, optimally we want to reference these
             // by a NoteType key, but this would require ggez refactoring.
             batches,
             draw_distance,
             last_judgement: None,
+            keysounds: notes.keysounds.clone(),
+            keysound_player: None,
         }
     }
     pub fn redraw_batch(&mut self) {
         self.batches.iter_mut().for_each(SpriteBatch::clear);
-        for column_index in 0..NOTEFIELD_SIZE {
-            let (draw_start, draw_end) = self.column_info[column_index].on_screen;
+        for (column_index, column) in self.column_info.iter().enumerate() {
+            let (draw_start, draw_end) = column.on_screen;
             self.layout.add_column_of_notes(
-                &self.column_info[column_index].notes.notes[draw_start..draw_end],
+                &column.notes.notes[draw_start..draw_end],
                 column_index,
                 &mut self.batches,
             );
@@ -147,6 +188,98 @@ impl Notefield {
             self.last_judgement = Some(judge);
         }
     }
+    //Queued keysounds are dropped when nothing is playing them
+    pub fn play_keysounds(&mut self) {
+        for column in &mut self.column_info {
+            for keysound in column.keysounds.drain(..) {
+                if let Some(player) = &self.keysound_player {
+                    let _ = player.send(keysound);
+                }
+            }
+        }
+    }
+    pub fn column_for_key(&self, keycode: KeyCode) -> Option<usize> {
+        column_keys(self.column_info.len())
+            .iter()
+            .position(|&key| key == keycode)
+    }
+}
+
+//These should eventually come from a keybind config, for now each column count gets a fixed set
+pub fn column_keys(columns: usize) -> &'static [KeyCode] {
+    const FALLBACK: [KeyCode; 20] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+        KeyCode::Key0,
+        KeyCode::Q,
+        KeyCode::W,
+        KeyCode::E,
+        KeyCode::R,
+        KeyCode::T,
+        KeyCode::Y,
+        KeyCode::U,
+        KeyCode::I,
+        KeyCode::O,
+        KeyCode::P,
+    ];
+    match columns {
+        3 => &[KeyCode::X, KeyCode::Space, KeyCode::Comma],
+        4 => &[KeyCode::Z, KeyCode::X, KeyCode::Comma, KeyCode::Period],
+        5 => &[
+            KeyCode::Z,
+            KeyCode::X,
+            KeyCode::Space,
+            KeyCode::Comma,
+            KeyCode::Period,
+        ],
+        6 => &[
+            KeyCode::Z,
+            KeyCode::X,
+            KeyCode::C,
+            KeyCode::Comma,
+            KeyCode::Period,
+            KeyCode::Slash,
+        ],
+        7 => &[
+            KeyCode::Z,
+            KeyCode::X,
+            KeyCode::C,
+            KeyCode::Space,
+            KeyCode::Comma,
+            KeyCode::Period,
+            KeyCode::Slash,
+        ],
+        8 => &[
+            KeyCode::A,
+            KeyCode::S,
+            KeyCode::D,
+            KeyCode::F,
+            KeyCode::J,
+            KeyCode::K,
+            KeyCode::L,
+            KeyCode::Semicolon,
+        ],
+        10 => &[
+            KeyCode::Z,
+            KeyCode::X,
+            KeyCode::C,
+            KeyCode::V,
+            KeyCode::B,
+            KeyCode::N,
+            KeyCode::M,
+            KeyCode::Comma,
+            KeyCode::Period,
+            KeyCode::Slash,
+        ],
+        _ => &FALLBACK[..columns.min(FALLBACK.len())],
+    }
 }
 
 /*
@@ -162,7 +295,7 @@ impl Element for Notefield {
             None => return Ok(Message::None),
         };
         let mut completed = true;
-        for column_index in 0..NOTEFIELD_SIZE {
+        for column_index in 0..self.column_info.len() {
             if let Some(value) = self.column_info[column_index].active_hold {
                 let delta = value - time;
                 if delta > 0 {
@@ -205,12 +338,9 @@ impl Element for Notefield {
         ))
     }
     fn handle_event(&mut self, keycode: ggez::event::KeyCode, time: Option<i64>, key_down: bool) {
-        let index = match keycode {
-            ggez::event::KeyCode::Z => 0,
-            ggez::event::KeyCode::X => 1,
-            ggez::event::KeyCode::Comma => 2,
-            ggez::event::KeyCode::Period => 3,
-            _ => return,
+        let index = match self.column_for_key(keycode) {
+            Some(index) => index,
+            None => return,
         };
         let time = match time {
             Some(time) => time,
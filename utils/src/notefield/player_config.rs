@@ -1,4 +1,3 @@
-use super::NOTEFIELD_SIZE;
 use ggez::{
     error::GameResult,
     graphics::{self, Rect, WrapMode},
@@ -9,23 +8,31 @@ use notedata::{
     NoteType,
 };
 use serde_derive::Deserialize;
-use std::{fs::File, io::Read, path::Path};
+use std::{collections::HashMap, fs::File, io::Read, path::Path};
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct NoteLayout {
     pub sprites: NoteSprites,
-    pub column_positions: [i64; NOTEFIELD_SIZE],
-    pub column_rotations: [f32; NOTEFIELD_SIZE],
+    pub column_positions: Vec<i64>,
+    pub column_rotations: Vec<f32>,
     pub receptor_height: i64,
     pub judgment_position: [f32; 2],
     pub scroll_speed: f32,
+    notefield_position: i64,
+    layouts: HashMap<usize, ColumnLayout>,
 }
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct NoteSkin {
     pub sprites: NoteSprites,
-    pub column_positions: [i64; NOTEFIELD_SIZE],
-    pub column_rotations: [f32; NOTEFIELD_SIZE],
+    pub layouts: HashMap<usize, ColumnLayout>,
+}
+
+//Positions are in pixels from the center of the notefield, rotations are in degrees
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+pub struct ColumnLayout {
+    pub column_positions: Vec<i64>,
+    pub column_rotations: Vec<f32>,
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -54,11 +61,7 @@ fn to_ggez(rect: Rectangle) -> ggez::graphics::Rect {
 
 impl NoteLayout {
     pub fn new(skin: &NoteSkin, screen_height: i64, player_options: PlayerOptions) -> Self {
-        let NoteSkin {
-            sprites,
-            mut column_positions,
-            mut column_rotations,
-        } = skin.clone();
+        let NoteSkin { sprites, layouts } = skin.clone();
         let PlayerOptions {
             notefield_position,
             mut receptor_height,
@@ -66,10 +69,6 @@ impl NoteLayout {
             is_reverse,
             mut judgment_position,
         } = player_options;
-        column_positions
-            .iter_mut()
-            .for_each(|x| *x += notefield_position);
-        column_rotations.iter_mut().for_each(|x| *x *= 6.28 / 360.0);
         judgment_position.0 += notefield_position as f32;
         if is_reverse {
             receptor_height = screen_height - receptor_height;
@@ -77,14 +76,43 @@ impl NoteLayout {
             scroll_speed *= -1.0;
         }
         let judgment_position = [judgment_position.0, judgment_position.1];
-        Self {
+        let mut layout = Self {
             sprites,
-            column_positions,
-            column_rotations,
+            column_positions: vec![],
+            column_rotations: vec![],
             receptor_height,
             judgment_position,
             scroll_speed,
-        }
+            notefield_position,
+            layouts,
+        };
+        //Until a chart is loaded, lay the notefield out for dance-single
+        layout.set_columns(4);
+        layout
+    }
+    //Noteskins without a layout for this many columns get evenly spaced, unrotated columns
+    pub fn set_columns(&mut self, columns: usize) {
+        let ColumnLayout {
+            column_positions,
+            column_rotations,
+        } = self
+            .layouts
+            .get(&columns)
+            .cloned()
+            .unwrap_or_else(|| ColumnLayout {
+                column_positions: (0..columns)
+                    .map(|column| 64 * column as i64 - 32 * (columns as i64 - 1))
+                    .collect(),
+                column_rotations: vec![0.0; columns],
+            });
+        self.column_positions = column_positions
+            .into_iter()
+            .map(|x| x + self.notefield_position)
+            .collect();
+        self.column_rotations = column_rotations
+            .into_iter()
+            .map(|x| x * 6.28 / 360.0)
+            .collect();
     }
     pub fn delta_to_position(&self, delta: i64) -> i64 {
         (delta as f32 * self.scroll_speed) as i64 + self.receptor_height
@@ -98,7 +126,7 @@ impl NoteLayout {
         column_data: &[GameplayInfo],
         batches: &mut Vec<graphics::spritebatch::SpriteBatch>,
     ) {
-        let GameplayInfo(position, coords, note_type) = match column_data.get(0) {
+        let GameplayInfo(position, coords, note_type, _) = match column_data.get(0) {
             Some(val) => *val,
             None => return,
         };
@@ -106,7 +134,11 @@ impl NoteLayout {
         let batch_index = match note_type {
             NoteType::Tap | NoteType::Roll | NoteType::Lift | NoteType::Fake => 2,
             NoteType::Hold => {
-                if let Some(GameplayInfo(end, _, _)) = column_data.get(1) {
+                if let Some(GameplayInfo(end, _, _, _)) = column_data
+                    .iter()
+                    .skip(1)
+                    .find(|note| note.2 == NoteType::HoldEnd)
+                {
                     batches[1].add(
                         graphics::DrawParam::new()
                             .src(Rect::new(
@@ -129,6 +161,7 @@ impl NoteLayout {
             }
             NoteType::Mine => 3,
             NoteType::HoldEnd => 0,
+            NoteType::AutoKeysound => return,
         };
         batches[batch_index].add(
             graphics::DrawParam::new()
@@ -263,8 +296,10 @@ struct NoteSkinInfo {
     hold_body: String,
     hold_head: String,
     mine: String,
-    column_positions: [i64; NOTEFIELD_SIZE],
-    column_rotations: [f32; NOTEFIELD_SIZE],
+    column_positions: Vec<i64>,
+    column_rotations: Vec<f32>,
+    #[serde(default)]
+    layouts: Vec<ColumnLayout>,
 }
 
 impl NoteSkin {
@@ -287,10 +322,25 @@ impl NoteSkin {
             mine,
             column_positions,
             column_rotations,
+            mut layouts,
         } = match toml::from_str(&config_string) {
             Ok(skin) => skin,
             Err(_) => return None,
         };
+        layouts.push(ColumnLayout {
+            column_positions,
+            column_rotations,
+        });
+        if layouts
+            .iter()
+            .any(|layout| layout.column_positions.len() != layout.column_rotations.len())
+        {
+            return None;
+        }
+        let layouts = layouts
+            .into_iter()
+            .map(|layout| (layout.column_positions.len(), layout))
+            .collect();
         if let (
             Ok(arrows),
             Ok(receptor),
@@ -315,11 +365,7 @@ impl NoteSkin {
                 hold_end: hold_head,
                 mine,
             };
-            Some(Self {
-                sprites,
-                column_positions,
-                column_rotations,
-            })
+            Some(Self { sprites, layouts })
         } else {
             None
         }
@@ -49,7 +49,7 @@ use std::{
 
 pub enum LoadError {
     WrongExtension,
-    FailedParse,
+    FailedParse(notedata::ParseError),
 }
 
 pub fn load_songs_folder<T, U: 'static>(
@@ -88,7 +88,7 @@ pub fn send_songs<U>(
                         }
                         Err(err) => match err {
                             LoadError::WrongExtension => {}
-                            LoadError::FailedParse => {
+                            LoadError::FailedParse(_) => {
                                 s.send(None).expect("Failed to send song along channel")
                             }
                         },